@@ -0,0 +1,24 @@
+//! Checks that the core of the crate builds without the standard library:
+//!
+//! `cargo build --no-default-features --example no_std`
+#![no_std]
+
+extern crate alloc;
+
+use alloc::{boxed::Box, vec, vec::Vec};
+use constraint::RawProblem;
+
+pub fn solve_small_problem() -> Option<Vec<i32>> {
+    let mut problem = RawProblem::new();
+    let x = problem.add_var(vec![1, 2, 3]);
+    let y = problem.add_var(vec![1, 2, 3]);
+    problem.add_constraint(
+        vec![x, y],
+        Box::new(|vals| vals.next().unwrap() + vals.next().unwrap() == 5),
+    );
+
+    problem
+        .normalize_problem()
+        .constraint_propagation()?
+        .solve_backtracking()
+}
@@ -0,0 +1,203 @@
+//! Standard problems with known outcomes, for comparing solvers, heuristics
+//! and propagators on the same ground
+
+use crate::{
+    random::{self, Rng},
+    sudoku::Sudoku,
+    RawProblem, Universe, Variable,
+};
+
+/// What solving a [`Benchmark`] should end in
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expected {
+    Satisfiable,
+    Unsatisfiable,
+    /// Has exactly this many solutions
+    Solutions(usize),
+    /// Has this solution and no other
+    UniqueSolution(Vec<Universe>),
+}
+
+#[derive(Debug)]
+pub struct Benchmark {
+    pub name: String,
+    pub problem: RawProblem,
+    pub expected: Expected,
+}
+
+/// Every benchmark, in sizes that solve in well under a second with MRV
+pub fn all() -> Vec<Benchmark> {
+    vec![
+        sudoku(),
+        n_queens(8),
+        graph_coloring(3),
+        graph_coloring(4),
+        random_model_b(0),
+    ]
+}
+
+/// A Sudoku with a single solution, which plain backtracking finds slowly
+pub fn sudoku() -> Benchmark {
+    let sudoku = Sudoku::from_slice(&[
+        3, 0, 6, 5, 0, 8, 4, 0, 0, 5, 2, 0, 0, 0, 0, 0, 0, 0, 0, 8, 7, 0, 0, 0, 0, 3, 1, 0, 0, 3,
+        0, 1, 0, 0, 8, 0, 9, 0, 0, 8, 6, 3, 0, 0, 5, 0, 5, 0, 0, 9, 0, 6, 0, 0, 1, 3, 0, 0, 0, 0,
+        2, 5, 0, 0, 0, 0, 0, 0, 0, 0, 7, 4, 0, 0, 5, 2, 0, 6, 3, 0, 0,
+    ]);
+    let solution = [
+        3, 1, 6, 5, 7, 8, 4, 9, 2, 5, 2, 9, 1, 3, 4, 7, 6, 8, 4, 8, 7, 6, 2, 9, 5, 3, 1, 2, 6, 3,
+        4, 1, 5, 9, 8, 7, 9, 7, 4, 8, 6, 3, 1, 2, 5, 8, 5, 1, 7, 9, 2, 6, 4, 3, 1, 3, 8, 9, 4, 7,
+        2, 5, 6, 6, 9, 2, 3, 5, 1, 8, 7, 4, 7, 4, 5, 2, 8, 6, 3, 1, 9,
+    ];
+    Benchmark {
+        name: "sudoku".to_owned(),
+        problem: sudoku.to_constraint_problem(),
+        expected: Expected::UniqueSolution(solution.to_vec()),
+    }
+}
+
+/// `n` queens on an `n` by `n` board, none attacking another. The variable
+/// of each row is the column of its queen
+pub fn n_queens(n: usize) -> Benchmark {
+    // Known counts from https://oeis.org/A000170
+    const SOLUTIONS: [usize; 11] = [1, 1, 0, 0, 2, 10, 4, 40, 92, 352, 724];
+    assert!(n < SOLUTIONS.len());
+
+    let mut problem = RawProblem::new();
+    let rows: Vec<Variable> = (0..n)
+        .map(|_| problem.add_var_range(0, n as Universe - 1))
+        .collect();
+    for (i, &a) in rows.iter().enumerate() {
+        for (j, &b) in rows.iter().enumerate().skip(i + 1) {
+            let distance = (j - i) as Universe;
+            problem.add_constraint(
+                vec![a, b],
+                Box::new(move |vals| {
+                    let (x, y) = (vals.next().unwrap(), vals.next().unwrap());
+                    x != y && (x - y).abs() != distance
+                }),
+            );
+        }
+    }
+
+    Benchmark {
+        name: format!("{n}-queens"),
+        problem,
+        expected: Expected::Solutions(SOLUTIONS[n]),
+    }
+}
+
+/// Coloring the Grötzsch graph, which has no triangles but needs 4 colors,
+/// so it sits right at the threshold: unsatisfiable with 3 colors and
+/// satisfiable with 4
+pub fn graph_coloring(colors: Universe) -> Benchmark {
+    // Mycielski construction over a 5-cycle: outer cycle 0..5, each inner
+    // vertex 5 + i copies the neighbors of i, and vertex 10 joins the inner ones
+    let mut edges: Vec<(usize, usize)> = Vec::new();
+    for i in 0..5 {
+        let next = (i + 1) % 5;
+        edges.push((i, next));
+        edges.push((next, 5 + i));
+        edges.push((i, 5 + next));
+        edges.push((5 + i, 10));
+    }
+
+    let mut problem = RawProblem::new();
+    let vertices: Vec<Variable> = (0..11).map(|_| problem.add_var_range(1, colors)).collect();
+    for (a, b) in edges {
+        problem.add_constraint(
+            vec![vertices[a.min(b)], vertices[a.max(b)]],
+            Box::new(|vals| vals.next() != vals.next()),
+        );
+    }
+
+    Benchmark {
+        name: format!("groetzsch-{colors}-coloring"),
+        problem,
+        expected: if colors >= 4 {
+            Expected::Satisfiable
+        } else {
+            Expected::Unsatisfiable
+        },
+    }
+}
+
+/// Random binary problem (model B) with a planted solution, so it is always
+/// satisfiable
+pub fn random_model_b(seed: u64) -> Benchmark {
+    let mut rng = Rng::new(seed);
+    let (problem, _) = random::planted_binary_problem(&mut rng, 20, 6, 0.3, 0.35);
+    Benchmark {
+        name: format!("model-b-{seed}"),
+        problem,
+        expected: Expected::Satisfiable,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{PropagatedProblem, SolverOptions, VariableOrder};
+
+    fn check(benchmark: Benchmark) {
+        let name = benchmark.name;
+        let problem = benchmark
+            .problem
+            .normalize_problem()
+            .constraint_propagation();
+        // Plain backtracking takes too long on the Sudoku
+        let solve = |problem: &PropagatedProblem| {
+            problem.solve_with_options(&SolverOptions {
+                variable_order: VariableOrder::Mrv,
+                ..Default::default()
+            })
+        };
+        match benchmark.expected {
+            Expected::Unsatisfiable => {
+                assert!(problem.and_then(|p| solve(&p)).is_none(), "{name}");
+            }
+            Expected::Satisfiable => {
+                let problem = problem.unwrap();
+                let solution = solve(&problem).unwrap();
+                assert!(problem.verify(&solution), "{name}");
+            }
+            Expected::Solutions(count) => {
+                let found = problem.map_or(0, |p| p.solutions().count());
+                assert_eq!(found, count, "{name}");
+            }
+            Expected::UniqueSolution(solution) => {
+                let problem = problem.unwrap();
+                assert_eq!(solve(&problem), Some(solution), "{name}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_benchmarks() {
+        for benchmark in all() {
+            check(benchmark);
+        }
+        for n in 1..=6 {
+            check(n_queens(n));
+        }
+    }
+
+    #[test]
+    fn test_queens_up_to_reflection() {
+        let problem = n_queens(6)
+            .problem
+            .normalize_problem()
+            .constraint_propagation()
+            .unwrap();
+        // Mirrored left to right, top to bottom and both
+        let reflections = |rows: &[Universe]| {
+            let mirrored: Vec<Universe> = rows.iter().map(|&col| 5 - col).collect();
+            let flipped: Vec<Universe> = rows.iter().rev().copied().collect();
+            let both = mirrored.iter().rev().copied().collect();
+            vec![mirrored, flipped, both]
+        };
+
+        let canonical: Vec<Vec<Universe>> = problem.solutions_canonical(reflections).collect();
+        assert_eq!(canonical, [[1, 3, 5, 0, 2, 4], [2, 5, 1, 4, 0, 3]]);
+        assert!(canonical.len() < problem.solutions().count());
+    }
+}
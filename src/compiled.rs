@@ -0,0 +1,184 @@
+//! A propagated problem frozen into a layout for solving it many times, see
+//! [`PropagatedProblem::compile`]
+
+use alloc::{vec, vec::Vec};
+
+use crate::{Constraint, PropagatedProblem, Universe, Variable};
+
+/// Values of a variable, as a bitset when they fit in 64 consecutive numbers
+#[derive(Debug, Clone)]
+enum CompactDomain {
+    /// Bit `i` set means `offset + i` is in the domain
+    Bits {
+        offset: Universe,
+        mask: u64,
+    },
+    Values(Vec<Universe>),
+}
+impl CompactDomain {
+    fn new(values: &[Universe]) -> Self {
+        match (values.first(), values.last()) {
+            (Some(&lo), Some(&hi)) if (hi as i64 - lo as i64) < 64 => CompactDomain::Bits {
+                offset: lo,
+                mask: values.iter().fold(0u64, |mask, &v| mask | 1 << (v - lo)),
+            },
+            _ => CompactDomain::Values(values.to_vec()),
+        }
+    }
+    fn contains(&self, value: Universe) -> bool {
+        match self {
+            CompactDomain::Bits { offset, mask } => {
+                let bit = value as i64 - *offset as i64;
+                (0..64).contains(&bit) && mask >> bit & 1 == 1
+            }
+            CompactDomain::Values(values) => values.binary_search(&value).is_ok(),
+        }
+    }
+    /// The values in increasing order
+    fn for_each(&self, mut f: impl FnMut(Universe) -> bool) -> bool {
+        match self {
+            CompactDomain::Bits { offset, mask } => {
+                let mut rest = *mask;
+                while rest != 0 {
+                    let bit = rest.trailing_zeros();
+                    rest &= rest - 1;
+                    if f(offset + bit as Universe) {
+                        return true;
+                    }
+                }
+                false
+            }
+            CompactDomain::Values(values) => values.iter().any(|&v| f(v)),
+        }
+    }
+}
+
+/// Constraints on a scope, with the scope as positions in the assignment
+struct Check {
+    scope: Vec<usize>,
+    constraints: Vec<Constraint>,
+}
+
+/// Immutable form of a [`PropagatedProblem`] for solving it over and over,
+/// like with different warm starts. The constraints to check after
+/// assigning each variable are found up front instead of on every step, and
+/// domains are bitsets where possible
+pub struct CompiledProblem {
+    domains: Vec<CompactDomain>,
+    checks: Vec<Check>,
+    /// For each variable, the checks whose last variable it is
+    complete: Vec<Vec<usize>>,
+    /// For each variable, the checks with a partial evaluator watching it
+    /// whose scope goes on past it
+    partial: Vec<Vec<usize>>,
+}
+
+impl PropagatedProblem {
+    /// Freezes the problem into a [`CompiledProblem`]
+    pub fn compile(mut self) -> CompiledProblem {
+        self.realize_all();
+        let n = self.variables.len();
+        let mut complete = vec![Vec::new(); n];
+        let mut partial = vec![Vec::new(); n];
+        let mut checks = Vec::with_capacity(self.constraints.len());
+        for (i, (scope, constraints)) in self.constraints.into_iter().enumerate() {
+            let last = scope.last().unwrap().id;
+            complete[last].push(i);
+            for var in &scope[..scope.len() - 1] {
+                if constraints.iter().any(|c| c.watches(*var)) {
+                    partial[var.id].push(i);
+                }
+            }
+            checks.push(Check {
+                scope: scope.iter().map(|var| var.id).collect(),
+                constraints,
+            });
+        }
+
+        CompiledProblem {
+            domains: self
+                .domains
+                .iter()
+                .map(|domain| CompactDomain::new(&domain.values))
+                .collect(),
+            checks,
+            complete,
+            partial,
+        }
+    }
+}
+
+impl CompiledProblem {
+    /// Backtracking search assigning variables by id and trying values in
+    /// increasing order, like [`PropagatedProblem::solve_backtracking`]
+    pub fn solve(&self) -> Option<Vec<Universe>> {
+        self.solve_from(&vec![None; self.domains.len()])
+    }
+    /// Same as [`Self::solve`] but tries the value in `warm_start` first for
+    /// the variables that have one
+    pub fn solve_from(&self, warm_start: &[Option<Universe>]) -> Option<Vec<Universe>> {
+        assert_eq!(warm_start.len(), self.domains.len());
+        let mut candidate = vec![0; self.domains.len()];
+        let mut scratch = Vec::new();
+        self.search(0, &mut candidate, warm_start, &mut scratch)
+            .then_some(candidate)
+    }
+
+    fn search(
+        &self,
+        k: usize,
+        candidate: &mut [Universe],
+        warm_start: &[Option<Universe>],
+        scratch: &mut Vec<Option<Universe>>,
+    ) -> bool {
+        if k == self.domains.len() {
+            return true;
+        }
+        let mut try_value = |value: Universe| {
+            candidate[k] = value;
+            self.consistent(k, candidate, scratch)
+                && self.search(k + 1, candidate, warm_start, scratch)
+        };
+
+        let hint = warm_start[k].filter(|&value| self.domains[k].contains(value));
+        if let Some(hint) = hint {
+            if try_value(hint) {
+                return true;
+            }
+        }
+        self.domains[k].for_each(|value| Some(value) != hint && try_value(value))
+    }
+
+    /// Whether the constraints allow the values of the variables up to `k`.
+    /// `scratch` holds the partial values, reused so checks don't allocate
+    fn consistent(
+        &self,
+        k: usize,
+        candidate: &[Universe],
+        scratch: &mut Vec<Option<Universe>>,
+    ) -> bool {
+        let complete = self.complete[k].iter().all(|&i| {
+            let check = &self.checks[i];
+            check
+                .constraints
+                .iter()
+                .all(|c| (c.evaluate)(&mut check.scope.iter().map(|&var| candidate[var])))
+        });
+        complete
+            && self.partial[k].iter().all(|&i| {
+                let check = &self.checks[i];
+                scratch.clear();
+                scratch.extend(
+                    check
+                        .scope
+                        .iter()
+                        .map(|&var| (var <= k).then_some(candidate[var])),
+                );
+                check
+                    .constraints
+                    .iter()
+                    .filter(|c| c.watches(Variable { id: k }))
+                    .all(|c| c.partially_satisfied(scratch))
+            })
+    }
+}
@@ -0,0 +1,559 @@
+//! Constraints and the constructors for the common kinds of them
+
+use alloc::{boxed::Box, vec, vec::Vec};
+use core::fmt::Debug;
+use hashbrown::HashSet;
+
+use crate::{Domain, Evaluation, Function, PartialEvaluation, Universe, Variable};
+
+/// Value of an optional variable that is absent, see
+/// [`crate::RawProblem::add_optional_var`]
+pub const ABSENT: Universe = Universe::MIN;
+
+/// What a constraint from one of the built-in constructors checks, so that
+/// duplicates can be recognized. Constraints over the same scope with equal
+/// kinds are equivalent
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConstraintKind {
+    NotEqual,
+    AllDifferent,
+    AllDifferentIgnoring(Universe),
+    PresentSum(Universe),
+    ExactlyOne,
+    AtMostOne,
+    CountEq {
+        value: Universe,
+        count: usize,
+    },
+    NValueLe(usize),
+    NValueEq(usize),
+    /// Sorted values without repeats
+    Member(Vec<Universe>),
+    /// Sorted values without repeats
+    NotMember(Vec<Universe>),
+    MinEq(Variable),
+    MaxEq(Variable),
+    /// The given variable is smaller than the other one of the scope
+    LessThan(Variable),
+    /// The given variable is at most the other one of the scope
+    LessEq(Variable),
+    /// Duration of the task of each variable of the scope
+    NoOverlap {
+        durations: Vec<u32>,
+    },
+    /// Successor of each node, in the order of the nodes
+    Circuit(Vec<Variable>),
+}
+
+pub struct Constraint {
+    pub scope: Vec<Variable>,
+    pub evaluate: Evaluation,
+    /// Used during search to reject partial assignments early
+    pub partial: Option<PartialEvaluation>,
+    /// Estimated cost of evaluating it. Constraints over the same scope are
+    /// checked from cheapest to most expensive, so the search can stop at a
+    /// cheap failing one before running the expensive ones
+    pub cost: u32,
+    /// Set if the constraint fixes the last variable of the scope as a
+    /// function of the others, so the search computes it instead of
+    /// branching on it
+    pub function: Option<Function>,
+    /// If set, backtracking only runs the partial evaluation when one of
+    /// these variables is assigned, saving work on constraints with big
+    /// scopes. The full evaluation still runs once the scope is assigned
+    pub watched: Option<Vec<Variable>>,
+    /// Set by the built-in constructors, `None` for arbitrary closures
+    pub kind: Option<ConstraintKind>,
+    /// Set if the binary constraint gives the same answer with its two
+    /// values swapped, so arc consistency can pass them in either order
+    pub symmetric: bool,
+    /// Same closure as `evaluate` if the constraint was built with
+    /// [`Constraint::new_sync`] or a built-in binary constructor, so it can
+    /// be checked on other threads
+    #[cfg(feature = "parallel")]
+    pub(crate) sync: Option<crate::SyncEvaluation>,
+}
+impl Constraint {
+    pub fn new(scope: Vec<Variable>, evaluate: Evaluation) -> Self {
+        Constraint {
+            scope,
+            evaluate,
+            partial: None,
+            cost: 1,
+            function: None,
+            watched: None,
+            kind: None,
+            symmetric: false,
+            #[cfg(feature = "parallel")]
+            sync: None,
+        }
+    }
+    /// Like [`Self::new`] for a closure that can be shared between threads,
+    /// which [`crate::NormalizedProblem::constraint_propagation_parallel`]
+    /// needs to revise the arcs of the constraint on another thread
+    #[cfg(feature = "parallel")]
+    pub fn new_sync(
+        scope: Vec<Variable>,
+        evaluate: impl Fn(&mut dyn Iterator<Item = Universe>) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        let sync: crate::SyncEvaluation = alloc::sync::Arc::new(evaluate);
+        let shared = sync.clone();
+        let mut constraint = Constraint::new(scope, Box::new(move |vals| shared(vals)));
+        constraint.sync = Some(sync);
+        constraint
+    }
+    /// Binary constraint whose `evaluate` doesn't care about the order of its
+    /// two values, like `x + y == 5`, so `a` and `b` can be given in any order
+    pub fn symmetric(a: Variable, b: Variable, evaluate: Evaluation) -> Self {
+        Self::symmetric_with(a, b, |scope| Constraint::new(scope, evaluate))
+    }
+    /// Like [`Self::symmetric`] for a closure that can be shared between
+    /// threads, see [`Self::new_sync`]
+    #[cfg(feature = "parallel")]
+    pub fn symmetric_sync(
+        a: Variable,
+        b: Variable,
+        evaluate: impl Fn(&mut dyn Iterator<Item = Universe>) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        Self::symmetric_with(a, b, |scope| Constraint::new_sync(scope, evaluate))
+    }
+    /// The constraint `build` makes over the sorted scope of `a` and `b`,
+    /// marked symmetric
+    fn symmetric_with(a: Variable, b: Variable, build: impl FnOnce(Vec<Variable>) -> Self) -> Self {
+        assert_ne!(a, b);
+        let scope = if a < b { vec![a, b] } else { vec![b, a] };
+        let mut constraint = build(scope);
+        constraint.symmetric = true;
+        constraint
+    }
+    /// For the built-in constructors: [`Self::new_sync`] when the crate can
+    /// propagate in parallel, so they go to other threads, else [`Self::new`]
+    fn built_in(
+        scope: Vec<Variable>,
+        evaluate: impl Fn(&mut dyn Iterator<Item = Universe>) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        #[cfg(feature = "parallel")]
+        return Constraint::new_sync(scope, evaluate);
+        #[cfg(not(feature = "parallel"))]
+        Constraint::new(scope, Box::new(evaluate))
+    }
+    /// Like [`Self::new`] but `evaluate` gets the values as a slice ordered
+    /// like the scope, so it can check how many there are instead of
+    /// unwrapping an iterator, like `let &[x, y] = vals else { return false }`
+    pub fn new_slice(
+        scope: Vec<Variable>,
+        evaluate: impl Fn(&[Universe]) -> bool + 'static,
+    ) -> Self {
+        Constraint::new(
+            scope,
+            Box::new(move |vals| {
+                let vals: Vec<Universe> = vals.collect();
+                evaluate(&vals)
+            }),
+        )
+    }
+    /// The last variable of the scope equals `function` of the others, like
+    /// `y == x + 3`
+    pub fn functional(scope: Vec<Variable>, function: Function) -> Self {
+        let function = alloc::rc::Rc::new(function);
+        let evaluate = function.clone();
+        let last = scope.len() - 1;
+        let mut constraint = Constraint::new(
+            scope,
+            Box::new(move |vals| {
+                let vals: Vec<Universe> = vals.collect();
+                evaluate(&mut vals[..last].iter().copied()) == vals[last]
+            }),
+        );
+        constraint.function = Some(Box::new(move |vals| function(vals)));
+        constraint
+    }
+    pub fn with_partial(mut self, partial: PartialEvaluation) -> Self {
+        self.partial = Some(partial);
+        self
+    }
+    pub fn with_cost(mut self, cost: u32) -> Self {
+        self.cost = cost;
+        self
+    }
+    pub fn with_watched(mut self, watched: Vec<Variable>) -> Self {
+        self.watched = Some(watched);
+        self
+    }
+    fn with_kind(mut self, kind: ConstraintKind) -> Self {
+        self.kind = Some(kind);
+        self
+    }
+    /// Whether assigning `var` should run the partial evaluation
+    pub(crate) fn watches(&self, var: Variable) -> bool {
+        self.partial.is_some() && self.watched.as_ref().is_none_or(|w| w.contains(&var))
+    }
+    /// Returns false if the partial evaluator rejects the partially assigned
+    /// values, which must be ordered like the scope
+    pub(crate) fn partially_satisfied(&self, vals: &[Option<Universe>]) -> bool {
+        self.partial
+            .as_ref()
+            .is_none_or(|partial| partial(&mut vals.iter().copied()))
+    }
+
+    /// Returns true if every constraint accepts the given values, which must
+    /// be ordered like their shared scope
+    pub fn all_satisfied(constraints: &[Constraint], vals: &[Universe]) -> bool {
+        constraints
+            .iter()
+            .all(|constraint| (constraint.evaluate)(&mut vals.iter().copied()))
+    }
+    /// Returns false if some constraint's partial evaluator rejects the
+    /// partially assigned values, which must be ordered like their shared scope
+    pub fn all_partially_satisfied(constraints: &[Constraint], vals: &[Option<Universe>]) -> bool {
+        constraints
+            .iter()
+            .all(|constraint| constraint.partially_satisfied(vals))
+    }
+
+    /// Evaluates a binary constraint on every pair of values, where
+    /// `matrix[i][j]` is whether `a.values[i]` and `b.values[j]` are allowed
+    /// together. `a` and `b` are the domains of the scope's two variables
+    pub fn to_support_matrix(&self, a: &Domain, b: &Domain) -> Vec<Vec<bool>> {
+        assert_eq!(self.scope, [a.of, b.of]);
+
+        a.values
+            .iter()
+            .map(|&va| {
+                b.values
+                    .iter()
+                    .map(|&vb| (self.evaluate)(&mut [va, vb].into_iter()))
+                    .collect()
+            })
+            .collect()
+    }
+    /// Fraction of the pairs of values from `a` and `b` that the binary
+    /// constraint disallows, 0 if either domain is empty. Problems whose
+    /// constraints are tight on average tend to be the hard ones
+    pub fn tightness(&self, a: &Domain, b: &Domain) -> f64 {
+        let pairs = a.values.len() * b.values.len();
+        if pairs == 0 {
+            return 0.0;
+        }
+        let allowed = self
+            .to_support_matrix(a, b)
+            .iter()
+            .flatten()
+            .filter(|&&allowed| allowed)
+            .count();
+        (pairs - allowed) as f64 / pairs as f64
+    }
+    /// Binary constraint allowing the pairs of values marked in `matrix`, laid
+    /// out like in [`Self::to_support_matrix`]. Values outside the domains
+    /// are never allowed
+    pub fn from_support_matrix(a: &Domain, b: &Domain, matrix: Vec<Vec<bool>>) -> Constraint {
+        assert!(a.of.id < b.of.id);
+        assert_eq!(matrix.len(), a.values.len());
+        assert!(matrix.iter().all(|row| row.len() == b.values.len()));
+
+        let a_values = a.values.clone();
+        let b_values = b.values.clone();
+        Constraint::new(
+            vec![a.of, b.of],
+            Box::new(move |vals| {
+                let va = vals.next().unwrap();
+                let vb = vals.next().unwrap();
+                let i = a_values.iter().position(|&v| v == va);
+                let j = b_values.iter().position(|&v| v == vb);
+                i.zip(j).is_some_and(|(i, j)| matrix[i][j])
+            }),
+        )
+    }
+    /// `var` takes one of the values of `set`. Node consistency intersects the
+    /// domain with it
+    pub fn member(var: Variable, set: Vec<Universe>) -> Constraint {
+        let kind = ConstraintKind::Member(sorted_set(&set));
+        let set: HashSet<Universe> = set.into_iter().collect();
+        Constraint::new(
+            vec![var],
+            Box::new(move |vals| vals.next().is_some_and(|val| set.contains(&val))),
+        )
+        .with_kind(kind)
+    }
+    /// `var` takes none of the values of `set`
+    pub fn not_member(var: Variable, set: Vec<Universe>) -> Constraint {
+        let kind = ConstraintKind::NotMember(sorted_set(&set));
+        let set: HashSet<Universe> = set.into_iter().collect();
+        Constraint::new(
+            vec![var],
+            Box::new(move |vals| vals.next().is_some_and(|val| !set.contains(&val))),
+        )
+        .with_kind(kind)
+    }
+    /// `a` and `b` have different values. Normalization replaces groups of
+    /// three or more of these over every pair of the group by a
+    /// [`crate::AllDifferent`] propagator, which propagates much better
+    pub fn not_equal(a: Variable, b: Variable) -> Constraint {
+        Constraint::symmetric_with(a, b, |scope| {
+            Constraint::built_in(scope, |vals| vals.next() != vals.next())
+        })
+        .with_kind(ConstraintKind::NotEqual)
+    }
+    /// `a < b`
+    pub fn less_than(a: Variable, b: Variable) -> Constraint {
+        Self::ordered(a, b, |va, vb| va < vb).with_kind(ConstraintKind::LessThan(a))
+    }
+    /// `a <= b`
+    pub fn less_equal(a: Variable, b: Variable) -> Constraint {
+        Self::ordered(a, b, |va, vb| va <= vb).with_kind(ConstraintKind::LessEq(a))
+    }
+    /// `holds(a, b)` over the sorted scope of `a` and `b`
+    fn ordered(a: Variable, b: Variable, holds: fn(Universe, Universe) -> bool) -> Constraint {
+        assert_ne!(a, b);
+        let flipped = a > b;
+        Constraint::built_in(vec![a.min(b), a.max(b)], move |vals| {
+            let (x, y) = (vals.next().unwrap(), vals.next().unwrap());
+            if flipped {
+                holds(y, x)
+            } else {
+                holds(x, y)
+            }
+        })
+    }
+    /// The variables that aren't [`ABSENT`] add up to `total`
+    pub fn present_sum(scope: Vec<Variable>, total: Universe) -> Constraint {
+        Constraint::new(
+            scope,
+            Box::new(move |vals| vals.filter(|&v| v != ABSENT).sum::<Universe>() == total),
+        )
+        .with_kind(ConstraintKind::PresentSum(total))
+    }
+    /// The variables that aren't [`ABSENT`] all have different values
+    pub fn present_all_different(scope: Vec<Variable>) -> Constraint {
+        Self::all_different_ignoring(scope, ABSENT)
+    }
+    /// The variables that aren't set to `ignored` all have different values,
+    /// so any number of them can be `ignored`
+    pub fn all_different_ignoring(scope: Vec<Variable>, ignored: Universe) -> Constraint {
+        let distinct = move |vals: &mut dyn Iterator<Item = Option<Universe>>| {
+            let mut seen = HashSet::new();
+            vals.flatten()
+                .filter(|&v| v != ignored)
+                .all(|v| seen.insert(v))
+        };
+        Constraint::new(scope, Box::new(move |vals| distinct(&mut vals.map(Some))))
+            .with_partial(Box::new(distinct))
+            .with_kind(ConstraintKind::AllDifferentIgnoring(ignored))
+    }
+    /// The variables all have different values
+    pub fn all_different(scope: Vec<Variable>) -> Constraint {
+        let distinct = |vals: &mut dyn Iterator<Item = Option<Universe>>| {
+            let mut seen = HashSet::new();
+            vals.flatten().all(|v| seen.insert(v))
+        };
+        Constraint::new(scope, Box::new(move |vals| distinct(&mut vals.map(Some))))
+            .with_partial(Box::new(distinct))
+            .with_kind(ConstraintKind::AllDifferent)
+    }
+
+    /// `result` is the smallest value of `over`, like `m == min(x1..xn)`
+    pub fn min_eq(result: Variable, over: Vec<Variable>) -> Constraint {
+        Self::extremum_eq(result, over, false).with_kind(ConstraintKind::MinEq(result))
+    }
+    /// `result` is the largest value of `over`, like a makespan
+    /// `m == max(end1..endn)`
+    pub fn max_eq(result: Variable, over: Vec<Variable>) -> Constraint {
+        Self::extremum_eq(result, over, true).with_kind(ConstraintKind::MaxEq(result))
+    }
+    fn extremum_eq(result: Variable, mut over: Vec<Variable>, max: bool) -> Constraint {
+        assert!(!over.is_empty());
+        assert!(!over.contains(&result));
+
+        // Repeats don't change the min or max
+        over.push(result);
+        over.sort_unstable();
+        over.dedup();
+        let scope = over;
+        let at = scope.binary_search(&result).unwrap();
+
+        Constraint::new(
+            scope,
+            Box::new(move |vals| {
+                let vals: Vec<Universe> = vals.collect();
+                let others = vals
+                    .iter()
+                    .enumerate()
+                    .filter(|&(i, _)| i != at)
+                    .map(|(_, &v)| v);
+                let extremum = if max { others.max() } else { others.min() };
+                extremum == Some(vals[at])
+            }),
+        )
+    }
+
+    /// `next[i]` is the node visited after node `i`, and following them
+    /// visits all the nodes in a single cycle, like a route for a traveling
+    /// salesman. Nodes are numbered from 0. Partial assignments are rejected
+    /// as soon as they close a cycle that leaves some node out.
+    /// Normalization adds a [`crate::Circuit`] propagator for it
+    pub fn circuit(next: Vec<Variable>) -> Constraint {
+        let n = next.len();
+        let mut nodes: Vec<usize> = (0..n).collect();
+        nodes.sort_unstable_by_key(|&i| next[i]);
+        let scope: Vec<Variable> = nodes.iter().map(|&i| next[i]).collect();
+        assert!(scope.windows(2).all(|pair| pair[0] != pair[1]));
+        let kind = ConstraintKind::Circuit(next);
+
+        let no_subtour = move |vals: &mut dyn Iterator<Item = Option<Universe>>| {
+            // Successor of each node, by node instead of by scope position
+            let mut successor = vec![None; n];
+            let mut taken = vec![false; n];
+            for (&node, val) in nodes.iter().zip(vals) {
+                let Some(val) = val else { continue };
+                let Some(succ) = usize::try_from(val).ok().filter(|&s| s < n && s != node) else {
+                    return false;
+                };
+                if core::mem::replace(&mut taken[succ], true) {
+                    return false;
+                }
+                successor[node] = Some(succ);
+            }
+
+            (0..n).all(|start| {
+                let mut node = start;
+                for length in 1.. {
+                    match successor[node] {
+                        None => return true,
+                        Some(succ) if succ == start => return length == n,
+                        Some(succ) => node = succ,
+                    }
+                }
+                unreachable!()
+            })
+        };
+        let complete = no_subtour.clone();
+        Constraint::new(scope, Box::new(move |vals| complete(&mut vals.map(Some))))
+            .with_partial(Box::new(no_subtour))
+            .with_kind(kind)
+    }
+
+    /// Exactly `count` of the variables are `value`. Pair it with
+    /// [`crate::Count`] to also propagate it
+    pub fn count_eq(scope: Vec<Variable>, value: Universe, count: usize) -> Constraint {
+        Constraint::new(
+            scope,
+            Box::new(move |vals| vals.filter(|&v| v == value).count() == count),
+        )
+        .with_partial(Box::new(move |vals| {
+            let (mut matching, mut unassigned) = (0, 0);
+            for val in vals {
+                match val {
+                    Some(v) if v == value => matching += 1,
+                    None => unassigned += 1,
+                    Some(_) => {}
+                }
+            }
+            matching <= count && count <= matching + unassigned
+        }))
+        .with_kind(ConstraintKind::CountEq { value, count })
+    }
+    /// Tasks starting at `starts` and lasting `durations` don't overlap, like
+    /// lessons sharing a room. A task covers its start and the times after it
+    /// up to the end, so one can start right when another ends. Partial
+    /// assignments are rejected as soon as two assigned tasks overlap. Ends
+    /// are computed in `i64`, so tasks running past [`Universe::MAX`] still
+    /// overlap the ones after them. Normalization adds a [`crate::NoOverlap`]
+    /// propagator for it
+    pub fn no_overlap(starts: Vec<Variable>, durations: Vec<u32>) -> Constraint {
+        assert_eq!(starts.len(), durations.len());
+        let mut tasks: Vec<(Variable, u32)> = starts.into_iter().zip(durations).collect();
+        tasks.sort_unstable_by_key(|&(var, _)| var);
+        assert!(tasks.windows(2).all(|pair| pair[0].0 != pair[1].0));
+        let (scope, durations): (Vec<Variable>, Vec<u32>) = tasks.into_iter().unzip();
+        let kind = durations.clone();
+
+        let disjoint = move |vals: &mut dyn Iterator<Item = Option<Universe>>| {
+            let mut intervals: Vec<(i64, i64)> = vals
+                .zip(&durations)
+                .filter_map(|(start, &duration)| {
+                    let start = i64::from(start?);
+                    Some((start, start + i64::from(duration)))
+                })
+                .collect();
+            intervals.sort_unstable();
+            intervals.windows(2).all(|pair| pair[0].1 <= pair[1].0)
+        };
+        let evaluate = disjoint.clone();
+        Constraint::new(scope, Box::new(move |vals| evaluate(&mut vals.map(Some))))
+            .with_partial(Box::new(disjoint))
+            .with_kind(ConstraintKind::NoOverlap { durations: kind })
+    }
+    /// The variables take at most `count` different values, like using at
+    /// most that many colors. Partial assignments are rejected as soon as
+    /// they use too many
+    pub fn nvalue_le(scope: Vec<Variable>, count: usize) -> Constraint {
+        Self::nvalue(scope, count, false).with_kind(ConstraintKind::NValueLe(count))
+    }
+    /// The variables take exactly `count` different values. Partial
+    /// assignments are rejected as soon as they use too many, or too few
+    /// variables are left to reach `count`
+    pub fn nvalue_eq(scope: Vec<Variable>, count: usize) -> Constraint {
+        Self::nvalue(scope, count, true).with_kind(ConstraintKind::NValueEq(count))
+    }
+    fn nvalue(scope: Vec<Variable>, count: usize, exact: bool) -> Constraint {
+        let fits = move |vals: &mut dyn Iterator<Item = Option<Universe>>| {
+            let mut distinct = HashSet::new();
+            let mut unassigned = 0;
+            for val in vals {
+                match val {
+                    Some(v) => {
+                        distinct.insert(v);
+                    }
+                    None => unassigned += 1,
+                }
+            }
+            distinct.len() <= count && (!exact || count <= distinct.len() + unassigned)
+        };
+        Constraint::new(scope, Box::new(move |vals| fits(&mut vals.map(Some))))
+            .with_partial(Box::new(fits))
+    }
+
+    /// Exactly one of the variables is 1, for variables with {0, 1} domains.
+    /// Pair it with [`crate::OneHot::exactly_one`] to also propagate it
+    pub fn exactly_one(scope: Vec<Variable>) -> Constraint {
+        Constraint::new(
+            scope,
+            Box::new(|vals| vals.filter(|&v| v == 1).count() == 1),
+        )
+        .with_partial(Box::new(|vals| {
+            let (mut ones, mut unassigned) = (0, 0);
+            for val in vals {
+                match val {
+                    Some(1) => ones += 1,
+                    None => unassigned += 1,
+                    Some(_) => {}
+                }
+            }
+            ones == 1 || ones == 0 && unassigned > 0
+        }))
+        .with_kind(ConstraintKind::ExactlyOne)
+    }
+    /// At most one of the variables is 1, for variables with {0, 1} domains.
+    /// Pair it with [`crate::OneHot::at_most_one`] to also propagate it
+    pub fn at_most_one(scope: Vec<Variable>) -> Constraint {
+        Constraint::new(
+            scope,
+            Box::new(|vals| vals.filter(|&v| v == 1).count() <= 1),
+        )
+        .with_partial(Box::new(|vals| vals.filter(|&v| v == Some(1)).count() <= 1))
+        .with_kind(ConstraintKind::AtMostOne)
+    }
+}
+fn sorted_set(values: &[Universe]) -> Vec<Universe> {
+    let mut set = values.to_vec();
+    set.sort_unstable();
+    set.dedup();
+    set
+}
+impl Debug for Constraint {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("Constraint")
+            .field("scope", &self.scope)
+            .finish()
+    }
+}
@@ -0,0 +1,302 @@
+//! A tiny text format for writing problems without Rust, one statement per
+//! line:
+//!
+//! ```text
+//! # comments start with a hash
+//! var x 1..9        # values from 1 to 9 inclusive
+//! var y 2 4 8       # or listed one by one
+//! alldiff x y
+//! x != y            # also ==, <, <=, > and >=
+//! x < 5             # either side can be a number
+//! ```
+
+use alloc::{
+    boxed::Box,
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
+use core::fmt::Display;
+use hashbrown::HashMap;
+
+use crate::{Constraint, RawProblem, Universe, Variable};
+
+/// Why a line couldn't be parsed, with lines counted from 1
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    pub line: usize,
+    pub message: String,
+}
+impl Display for ParseError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+/// Parses the whole text into a problem, along with the variables by name
+pub fn parse_dsl(text: &str) -> Result<(RawProblem, HashMap<String, Variable>), ParseError> {
+    parse_statements(text.lines().map(|line| {
+        let line = line.split('#').next().unwrap();
+        line.split_whitespace().collect()
+    }))
+}
+
+/// Like [`parse_dsl`] for statements already split into words, with the
+/// `line` of an error being the position of its statement, from 1
+pub fn parse_statements<'a>(
+    statements: impl IntoIterator<Item = Vec<&'a str>>,
+) -> Result<(RawProblem, HashMap<String, Variable>), ParseError> {
+    let mut parser = Parser {
+        problem: RawProblem::new(),
+        names: HashMap::new(),
+    };
+    for (i, words) in statements.into_iter().enumerate() {
+        parser.statement(&words).map_err(|message| ParseError {
+            line: i + 1,
+            message,
+        })?;
+    }
+    Ok((parser.problem, parser.names))
+}
+
+/// Either side of a comparison
+#[derive(Clone, Copy)]
+enum Term {
+    Var(Variable),
+    Value(Universe),
+}
+
+#[derive(Clone, Copy)]
+enum Comparison {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+impl Comparison {
+    fn parse(op: &str) -> Option<Self> {
+        Some(match op {
+            "==" => Self::Eq,
+            "!=" => Self::Ne,
+            "<" => Self::Lt,
+            "<=" => Self::Le,
+            ">" => Self::Gt,
+            ">=" => Self::Ge,
+            _ => return None,
+        })
+    }
+    /// The same comparison with its sides swapped
+    fn flip(self) -> Self {
+        match self {
+            Self::Lt => Self::Gt,
+            Self::Le => Self::Ge,
+            Self::Gt => Self::Lt,
+            Self::Ge => Self::Le,
+            other => other,
+        }
+    }
+    fn holds(self, a: Universe, b: Universe) -> bool {
+        match self {
+            Self::Eq => a == b,
+            Self::Ne => a != b,
+            Self::Lt => a < b,
+            Self::Le => a <= b,
+            Self::Gt => a > b,
+            Self::Ge => a >= b,
+        }
+    }
+}
+
+struct Parser {
+    problem: RawProblem,
+    names: HashMap<String, Variable>,
+}
+impl Parser {
+    fn statement(&mut self, words: &[&str]) -> Result<(), String> {
+        match words {
+            [] => Ok(()),
+            ["var", name, values @ ..] => self.var(name, values),
+            ["alldiff", names @ ..] => self.alldiff(names),
+            [a, op, b] => match Comparison::parse(op) {
+                Some(op) => self.comparison(a, op, b),
+                None => Err(["unknown comparison ", op].concat()),
+            },
+            [word, ..] => Err(["unknown statement ", word].concat()),
+        }
+    }
+
+    fn var(&mut self, name: &str, values: &[&str]) -> Result<(), String> {
+        if self.names.contains_key(name) {
+            return Err(["variable ", name, " is already declared"].concat());
+        }
+        if name.parse::<Universe>().is_ok() {
+            return Err(["variable name ", name, " is a number"].concat());
+        }
+
+        let domain = match values {
+            [] => return Err(["variable ", name, " has no values"].concat()),
+            [range] if range.contains("..") => {
+                let (lo, hi) = range.split_once("..").unwrap();
+                (parse_value(lo)?..=parse_value(hi)?).collect()
+            }
+            values => values
+                .iter()
+                .map(|value| parse_value(value))
+                .collect::<Result<Vec<_>, _>>()?,
+        };
+        let var = self.problem.add_var(domain);
+        self.names.insert(name.to_string(), var);
+        Ok(())
+    }
+
+    fn alldiff(&mut self, names: &[&str]) -> Result<(), String> {
+        let mut scope = names
+            .iter()
+            .map(|name| self.variable(name))
+            .collect::<Result<Vec<_>, _>>()?;
+        scope.sort_unstable();
+        if scope.windows(2).any(|pair| pair[0] == pair[1]) {
+            return Err("alldiff repeats a variable".to_string());
+        }
+        self.problem
+            .push_constraint(Constraint::all_different(scope));
+        Ok(())
+    }
+
+    fn comparison(&mut self, a: &str, op: Comparison, b: &str) -> Result<(), String> {
+        match (self.term(a)?, self.term(b)?) {
+            (Term::Var(a), Term::Var(b)) if a == b => {
+                Err("comparing a variable with itself".to_string())
+            }
+            (Term::Var(a), Term::Var(b)) if matches!(op, Comparison::Ne) => {
+                self.problem.push_constraint(Constraint::not_equal(a, b));
+                Ok(())
+            }
+            (Term::Var(a), Term::Var(b)) => {
+                // Orderings get built in so their bounds are tightened early
+                let constraint = match op {
+                    Comparison::Lt => Constraint::less_than(a, b),
+                    Comparison::Le => Constraint::less_equal(a, b),
+                    Comparison::Gt => Constraint::less_than(b, a),
+                    Comparison::Ge => Constraint::less_equal(b, a),
+                    Comparison::Eq | Comparison::Ne => Constraint::symmetric(
+                        a,
+                        b,
+                        Box::new(move |vals| {
+                            let (va, vb) = (vals.next().unwrap(), vals.next().unwrap());
+                            op.holds(va, vb)
+                        }),
+                    ),
+                };
+                self.problem.push_constraint(constraint);
+                Ok(())
+            }
+            (Term::Var(var), Term::Value(value)) => {
+                self.unary(var, op, value);
+                Ok(())
+            }
+            (Term::Value(value), Term::Var(var)) => {
+                self.unary(var, op.flip(), value);
+                Ok(())
+            }
+            (Term::Value(_), Term::Value(_)) => Err("comparing two numbers".to_string()),
+        }
+    }
+
+    /// `var op value`
+    fn unary(&mut self, var: Variable, op: Comparison, value: Universe) {
+        self.problem.add_constraint(
+            vec![var],
+            Box::new(move |vals| op.holds(vals.next().unwrap(), value)),
+        );
+    }
+
+    fn term(&self, word: &str) -> Result<Term, String> {
+        match word.parse() {
+            Ok(value) => Ok(Term::Value(value)),
+            Err(_) => self.variable(word).map(Term::Var),
+        }
+    }
+    fn variable(&self, name: &str) -> Result<Variable, String> {
+        self.names
+            .get(name)
+            .copied()
+            .ok_or_else(|| ["unknown variable ", name].concat())
+    }
+}
+
+fn parse_value(word: &str) -> Result<Universe, String> {
+    word.parse()
+        .map_err(|_| ["expected a number, found ", word].concat())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_graph_coloring() {
+        let (problem, vars) = parse_dsl(
+            "
+            # A triangle with a tail, in three colors
+            var a 1..3
+            var b 1..3
+            var c 1..3
+            var d 1 2 3
+
+            a != b
+            b != c
+            c != a   # scopes get sorted
+            c != d
+            1 < a    # the first color is taken
+            ",
+        )
+        .unwrap();
+
+        let problem = problem
+            .normalize_problem()
+            .constraint_propagation()
+            .unwrap();
+        let solution = problem.solve_backtracking().unwrap();
+        assert!(problem.verify(&solution));
+
+        let color = |name: &str| solution[vars[name].id];
+        assert_eq!(color("a"), 2);
+        assert_eq!(color("b"), 1);
+        assert_eq!(color("c"), 3);
+        assert_eq!(color("d"), 1);
+    }
+
+    #[test]
+    fn test_alldiff() {
+        let (problem, _) = parse_dsl("var x 1..3\nvar y 1..3\nvar z 1..3\nalldiff z x y").unwrap();
+        let problem = problem
+            .normalize_problem()
+            .constraint_propagation()
+            .unwrap();
+        assert_eq!(problem.solutions().count(), 6);
+    }
+
+    #[test]
+    fn test_errors() {
+        let error = |text| parse_dsl(text).unwrap_err();
+
+        assert_eq!(
+            error("var x 1..3\n\nconstrain x"),
+            ParseError {
+                line: 3,
+                message: "unknown statement constrain".to_string(),
+            }
+        );
+        assert_eq!(error("var x 1..3\nx != y").line, 2);
+        assert_eq!(error("var x 1..3\nvar x 1..3").line, 2);
+        assert_eq!(error("var x 1..a").line, 1);
+        assert_eq!(error("var x 1..3\nx ~ 2").line, 2);
+        assert_eq!(
+            error("var x 1\nalldiff x x").to_string(),
+            "line 2: alldiff repeats a variable"
+        );
+    }
+}
@@ -1,39 +1,161 @@
-use hashbrown::{HashMap, HashSet};
-use std::{cmp::Ordering, fmt::Debug};
+#![cfg_attr(not(feature = "std"), no_std)]
 
+extern crate alloc;
+
+use alloc::{borrow::ToOwned, boxed::Box, collections::BTreeMap, string::String, vec, vec::Vec};
+use core::cell::OnceCell;
+use hashbrown::HashMap;
+
+#[cfg(feature = "std")]
+pub mod benchmarks;
+pub mod compiled;
+pub mod constraints;
+pub mod dsl;
+pub mod local_search;
+#[cfg(feature = "parallel")]
+pub mod parallel;
+pub mod propagate;
+pub mod random;
+pub mod solve;
+#[cfg(feature = "std")]
 pub mod sudoku;
+pub mod var_array;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+pub mod weighted;
+
+#[cfg(test)]
+mod property_tests;
+
+pub use compiled::CompiledProblem;
+pub use constraints::{Constraint, ConstraintKind, ABSENT};
+pub use dsl::{parse_dsl, ParseError};
+pub use local_search::AnnealingSchedule;
+pub use propagate::{
+    AllDifferent, ArcAlgorithm, ArcOrder, Circuit, ConsistencyLevel, Count, DomainSnapshot,
+    NoOverlap, OneHot, ProblemTemplate, PropagationResult, Propagator,
+};
+#[cfg(feature = "std")]
+pub use solve::PhaseTimings;
+pub use solve::{
+    diff_solutions, Activity, BoundedProblem, ConstraintWeights, OptimumTieBreak, SearchEvent,
+    SearchSpaceTooLarge, SearchStats, Sense, Solution, Solutions, SolveIntent, SolverOptions,
+    TieBreak, ValueOrder, VariableOrder, BRUTE_FORCE_LIMIT, DOT_NODE_LIMIT, RESTART_VALUES,
+};
+pub use var_array::{VarArray, VarGrid};
+pub use weighted::CostConstraint;
 
 type Universe = i32;
 type Evaluation = Box<dyn Fn(&mut dyn Iterator<Item = Universe>) -> bool>;
+/// Like [`Evaluation`] but some of the values may not be assigned yet (`None`).
+/// Returns false only if no way of assigning the rest can satisfy the constraint
+type PartialEvaluation = Box<dyn Fn(&mut dyn Iterator<Item = Option<Universe>>) -> bool>;
+/// Computes the value of the last variable of a scope from the others
+type Function = Box<dyn Fn(&mut dyn Iterator<Item = Universe>) -> Universe>;
+/// [`Evaluation`] that can be shared between threads, see
+/// [`Constraint::new_sync`]
+#[cfg(feature = "parallel")]
+type SyncEvaluation =
+    alloc::sync::Arc<dyn Fn(&mut dyn Iterator<Item = Universe>) -> bool + Send + Sync>;
 type Candidate = Vec<Option<Universe>>;
 
-pub struct Constraint {
-    pub scope: Vec<Variable>,
-    pub evaluate: Evaluation,
-}
-impl Debug for Constraint {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.debug_struct("Constraint")
-            .field("scope", &self.scope)
-            .finish()
-    }
-}
-
 #[derive(PartialEq, Eq, Debug, Clone)]
 pub struct Domain {
     pub of: Variable,
     pub values: Vec<Universe>,
 }
+impl Domain {
+    /// Domain of `of` with the values from `lo` to `hi` inclusive, empty if
+    /// `lo > hi`
+    pub fn range(of: Variable, lo: Universe, hi: Universe) -> Domain {
+        Domain {
+            of,
+            values: (lo..=hi).collect(),
+        }
+    }
+}
+
+/// Domain of a variable added with [`RawProblem::add_var_lazy`], only
+/// generated once propagation or search first needs its values
+pub struct LazyDomain {
+    generate: Box<dyn Fn() -> Vec<Universe>>,
+    values: OnceCell<Vec<Universe>>,
+}
+impl LazyDomain {
+    fn values(&self) -> &[Universe] {
+        self.values.get_or_init(|| {
+            let mut values = (self.generate)();
+            // Sorted like every domain after propagation
+            values.sort_unstable();
+            values
+        })
+    }
+    fn into_values(self) -> Vec<Universe> {
+        self.values();
+        self.values.into_inner().unwrap()
+    }
+}
+impl core::fmt::Debug for LazyDomain {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("LazyDomain")
+            .field("values", &self.values.get())
+            .finish()
+    }
+}
+/// Values of the `i`th domain, generating them if the domain is lazy
+fn domain_values<'a>(
+    domains: &'a [Domain],
+    lazy_domains: &'a BTreeMap<Variable, LazyDomain>,
+    i: usize,
+) -> &'a [Universe] {
+    match lazy_domains.get(&domains[i].of) {
+        Some(lazy) => lazy.values(),
+        None => &domains[i].values,
+    }
+}
+/// Moves the values of the lazy domains of `vars` into `domains`
+fn realize_domains(
+    domains: &mut [Domain],
+    lazy_domains: &mut BTreeMap<Variable, LazyDomain>,
+    vars: impl IntoIterator<Item = Variable>,
+) {
+    for var in vars {
+        if let Some(lazy) = lazy_domains.remove(&var) {
+            domains[var.id].values = lazy.into_values();
+        }
+    }
+}
 
-#[derive(PartialEq, Eq, Hash, Debug, Clone, Copy)]
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Clone, Copy)]
 pub struct Variable {
     pub id: usize,
 }
 
+/// Why [`RawProblem::try_push_constraint`] rejected a constraint
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InvalidScope {
+    /// The variables of the scope aren't sorted by id
+    Unsorted,
+    /// A variable that wasn't added to the problem, which has `num_vars`
+    UnknownVariable { var: Variable, num_vars: usize },
+}
+impl core::fmt::Display for InvalidScope {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            InvalidScope::Unsorted => write!(f, "constraint scope isn't sorted by id"),
+            InvalidScope::UnknownVariable { var, num_vars } => write!(
+                f,
+                "constraint on {var:?}, but the problem only has {num_vars} variables"
+            ),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct RawProblem {
     variables: Vec<Variable>,
     domains: Vec<Domain>,
+    lazy_domains: BTreeMap<Variable, LazyDomain>,
     constraints: Vec<Constraint>,
 }
 
@@ -42,6 +164,7 @@ impl RawProblem {
         RawProblem {
             variables: Vec::new(),
             domains: Vec::new(),
+            lazy_domains: BTreeMap::new(),
             constraints: Vec::new(),
         }
     }
@@ -60,32 +183,288 @@ impl RawProblem {
 
         new_var
     }
+    /// Adds a variable whose domain is only generated by `generate` once
+    /// propagation or search needs it, for huge domains that other variables'
+    /// constraints may never get to. Propagation generates it if the
+    /// variable is in a unary or binary constraint or there are custom
+    /// propagators, otherwise search does when it reaches the variable
+    pub fn add_var_lazy(&mut self, generate: impl Fn() -> Vec<Universe> + 'static) -> Variable {
+        let var = self.add_var(Vec::new());
+        let lazy = LazyDomain {
+            generate: Box::new(generate),
+            values: OnceCell::new(),
+        };
+        self.lazy_domains.insert(var, lazy);
+        var
+    }
+    /// Fixes `var` to `value` by cutting its domain down to it, instead of
+    /// going through a unary constraint. The domain is left empty if it
+    /// doesn't have `value`, making the problem infeasible
+    pub fn fix_var(&mut self, var: Variable, value: Universe) {
+        realize_domains(&mut self.domains, &mut self.lazy_domains, [var]);
+        self.domains[var.id].values.retain(|&v| v == value);
+    }
+    /// Adds a variable equal to `x + y`, with every sum they can reach as
+    /// its domain. Pairs of values whose sum overflows are ruled out by an
+    /// extra constraint on `x` and `y`
+    pub fn add_sum_var(&mut self, x: Variable, y: Variable) -> Variable {
+        realize_domains(&mut self.domains, &mut self.lazy_domains, [x, y]);
+        let xs = &self.domains[x.id].values;
+        let ys = &self.domains[y.id].values;
+        let sums: Vec<Option<Universe>> = if x == y {
+            xs.iter().map(|&v| v.checked_add(v)).collect()
+        } else {
+            xs.iter()
+                .flat_map(|&vx| ys.iter().map(move |&vy| vx.checked_add(vy)))
+                .collect()
+        };
+        let overflows = sums.contains(&None);
+        let mut sums: Vec<Universe> = sums.into_iter().flatten().collect();
+        sums.sort_unstable();
+        sums.dedup();
+
+        // The same variable twice only shows up once in the scope
+        let mut operands = vec![x.min(y), x.max(y)];
+        operands.dedup();
+        if overflows {
+            self.add_constraint(
+                operands.clone(),
+                Box::new(|vals| {
+                    let first = vals.next().unwrap();
+                    first.checked_add(vals.next().unwrap_or(first)).is_some()
+                }),
+            );
+        }
+        let sum = self.add_var(sums);
+        let mut scope = operands;
+        scope.push(sum);
+        self.push_constraint(Constraint::functional(
+            scope,
+            // Wrapping, since solvers may still evaluate it on the pairs
+            // the other constraint rejects
+            Box::new(|vals| {
+                let first = vals.next().unwrap();
+                first.wrapping_add(vals.next().unwrap_or(first))
+            }),
+        ));
+        sum
+    }
+    /// Adds `values` to the domain of `var`, for relaxing a problem. The
+    /// domain ends up sorted and without repeats
+    pub fn extend_domain(&mut self, var: Variable, values: &[Universe]) {
+        realize_domains(&mut self.domains, &mut self.lazy_domains, [var]);
+        let domain = &mut self.domains[var.id].values;
+        domain.extend_from_slice(values);
+        domain.sort_unstable();
+        domain.dedup();
+    }
+    /// Adds a variable that may also be [`ABSENT`], like an optional task in a
+    /// schedule. Constraints like [`Constraint::present_sum`] ignore it when
+    /// it is absent
+    pub fn add_optional_var(&mut self, mut domain: Vec<Universe>) -> Variable {
+        domain.push(ABSENT);
+        self.add_var(domain)
+    }
+    /// Adds a variable with the values from `lo` to `hi` inclusive
+    pub fn add_var_range(&mut self, lo: Universe, hi: Universe) -> Variable {
+        self.add_var((lo..=hi).collect())
+    }
 
     pub fn add_constraint(&mut self, scope: Vec<Variable>, evaluation: Evaluation) {
-        assert!(scope.is_sorted_by_key(|v| v.id));
+        self.push_constraint(Constraint::new(scope, evaluation));
+    }
+
+    /// Like [`Self::add_constraint`] for a closure that can be shared between
+    /// threads, see [`Constraint::new_sync`]
+    #[cfg(feature = "parallel")]
+    pub fn add_constraint_sync(
+        &mut self,
+        scope: Vec<Variable>,
+        evaluation: impl Fn(&mut dyn Iterator<Item = Universe>) -> bool + Send + Sync + 'static,
+    ) {
+        self.push_constraint(Constraint::new_sync(scope, evaluation));
+    }
+
+    /// Adds a constraint over `vars` that gets their values by name, so it
+    /// doesn't depend on the order of the scope like [`Self::add_constraint`]
+    pub fn add_constraint_named(
+        &mut self,
+        vars: &[(&str, Variable)],
+        f: impl Fn(&HashMap<&str, Universe>) -> bool + 'static,
+    ) {
+        let mut names: Vec<(String, Variable)> = vars
+            .iter()
+            .map(|&(name, var)| (name.to_owned(), var))
+            .collect();
+        names.sort_unstable_by_key(|&(_, var)| var);
+        assert!(names.windows(2).all(|pair| pair[0].1 != pair[1].1));
+        assert!(names
+            .iter()
+            .enumerate()
+            .all(|(i, (name, _))| names[..i].iter().all(|(other, _)| other != name)));
+        let scope = names.iter().map(|&(_, var)| var).collect();
 
-        self.constraints.push(Constraint {
+        self.add_constraint(
             scope,
-            evaluate: evaluation,
-        });
+            Box::new(move |vals| {
+                let values: HashMap<&str, Universe> = names
+                    .iter()
+                    .map(|(name, _)| name.as_str())
+                    .zip(vals)
+                    .collect();
+                f(&values)
+            }),
+        );
+    }
+
+    /// Adds a constraint that can also be checked while only some of its
+    /// variables are assigned, letting the search prune earlier
+    pub fn add_constraint_with_partial(
+        &mut self,
+        scope: Vec<Variable>,
+        evaluation: Evaluation,
+        partial: PartialEvaluation,
+    ) {
+        self.push_constraint(Constraint::new(scope, evaluation).with_partial(partial));
+    }
+
+    /// Adds an already built constraint, like [`Constraint::exactly_one`] or
+    /// one with a [`Constraint::with_cost`]
+    ///
+    /// # Panics
+    ///
+    /// If the scope isn't sorted or has a variable that wasn't added to this
+    /// problem, see [`Self::try_push_constraint`] to get an error instead
+    pub fn push_constraint(&mut self, constraint: Constraint) {
+        if let Err(err) = self.try_push_constraint(constraint) {
+            panic!("{err}");
+        }
+    }
+    /// Like [`Self::push_constraint`], but returns an error instead of
+    /// panicking if the scope is invalid, leaving the problem unchanged
+    pub fn try_push_constraint(&mut self, constraint: Constraint) -> Result<(), InvalidScope> {
+        if !constraint.scope.is_sorted_by_key(|v| v.id) {
+            return Err(InvalidScope::Unsorted);
+        }
+        if let Some(&var) = constraint
+            .scope
+            .iter()
+            .find(|var| var.id >= self.variables.len())
+        {
+            return Err(InvalidScope::UnknownVariable {
+                var,
+                num_vars: self.variables.len(),
+            });
+        }
+
+        self.constraints.push(constraint);
+        Ok(())
+    }
+
+    /// Checks the unary constraints against the domains without modifying
+    /// anything. Returns the variables left with no allowed value
+    pub fn validate(&self) -> Result<(), Vec<Variable>> {
+        let emptied: Vec<Variable> = self
+            .domains
+            .iter()
+            .filter(|domain| {
+                let unary: Vec<&Constraint> = self
+                    .constraints
+                    .iter()
+                    .filter(|c| c.scope == [domain.of])
+                    .collect();
+                let values = domain_values(&self.domains, &self.lazy_domains, domain.of.id);
+                !values.iter().any(|&val| {
+                    unary
+                        .iter()
+                        .all(|c| (c.evaluate)(&mut core::iter::once(val)))
+                })
+            })
+            .map(|domain| domain.of)
+            .collect();
+
+        if emptied.is_empty() {
+            Ok(())
+        } else {
+            Err(emptied)
+        }
+    }
+
+    /// Variables that are in no constraint's scope, so any value of their
+    /// domain works. Not an error, but often a forgotten constraint
+    pub fn unconstrained_variables(&self) -> Vec<Variable> {
+        let mut constrained = vec![false; self.variables.len()];
+        for var in self.constraints.iter().flat_map(|c| &c.scope) {
+            constrained[var.id] = true;
+        }
+        self.variables
+            .iter()
+            .filter(|var| !constrained[var.id])
+            .copied()
+            .collect()
     }
 
     pub fn normalize_problem(self) -> NormalizedProblem {
-        let mut normalized_cons: HashMap<Vec<Variable>, Evaluation> = HashMap::new();
+        let mut normalized_cons: BTreeMap<Vec<Variable>, Vec<Constraint>> = BTreeMap::new();
 
-        // Combine constraints with same scope
-        for Constraint { scope, evaluate } in self.constraints {
-            if let Some(curr_eval) = normalized_cons.remove(&scope) {
-                normalized_cons.insert(scope, Box::new(move |u| curr_eval(u) && evaluate(u)));
-            } else {
-                normalized_cons.insert(scope, evaluate);
+        // Group constraints with same scope, keeping each one separate
+        for constraint in self.constraints {
+            normalized_cons
+                .entry(constraint.scope.clone())
+                .or_default()
+                .push(constraint);
+        }
+        for constraints in normalized_cons.values_mut() {
+            constraints.sort_by_key(|constraint| constraint.cost);
+            // Built-in constraints of the same kind are duplicates, keep the
+            // cheapest one. Closures can't be compared so they all stay
+            let mut kinds = Vec::new();
+            constraints.retain(|constraint| match &constraint.kind {
+                Some(kind) if kinds.contains(kind) => false,
+                Some(kind) => {
+                    kinds.push(kind.clone());
+                    true
+                }
+                None => true,
+            });
+        }
+
+        // Cliques of not-equal constraints propagate better as a whole. So do
+        // all-different constraints, which arc consistency skips for not
+        // being binary, without having to spell out every pair of them
+        let mut all_different = propagate::not_equal_cliques(&normalized_cons);
+        for constraint in normalized_cons.values().flatten() {
+            if constraint.kind == Some(ConstraintKind::AllDifferent)
+                && !all_different.contains(&constraint.scope)
+            {
+                all_different.push(constraint.scope.clone());
+            }
+        }
+        let mut propagators: Vec<Box<dyn Propagator>> = all_different
+            .into_iter()
+            .map(|scope| Box::new(AllDifferent::new(scope)) as Box<dyn Propagator>)
+            .collect();
+        for constraint in normalized_cons.values().flatten() {
+            match &constraint.kind {
+                Some(ConstraintKind::NoOverlap { durations }) => {
+                    let starts = constraint.scope.clone();
+                    propagators.push(Box::new(NoOverlap::new(starts, durations.clone())));
+                }
+                Some(ConstraintKind::Circuit(next)) => {
+                    propagators.push(Box::new(Circuit { next: next.clone() }));
+                }
+                _ => {}
             }
         }
 
         NormalizedProblem {
             variables: self.variables,
             domains: self.domains,
+            lazy_domains: self.lazy_domains,
             constraints: normalized_cons,
+            propagators,
+            arc_order: ArcOrder::default(),
+            arc_algorithm: ArcAlgorithm::default(),
         }
     }
 }
@@ -97,386 +476,2894 @@ impl Default for RawProblem {
 
 pub struct NormalizedProblem {
     pub variables: Vec<Variable>,
+    /// Lazy domains are left empty here until they are generated
     pub domains: Vec<Domain>,
-    pub constraints: HashMap<Vec<Variable>, Evaluation>,
+    pub lazy_domains: BTreeMap<Variable, LazyDomain>,
+    /// Ordered so that going through them is the same on every run
+    pub constraints: BTreeMap<Vec<Variable>, Vec<Constraint>>,
+    pub propagators: Vec<Box<dyn Propagator>>,
+    /// How arc consistency picks the next arc to revise
+    pub arc_order: ArcOrder,
+    /// How arc consistency revises each arc
+    pub arc_algorithm: ArcAlgorithm,
 }
-
 impl NormalizedProblem {
-    pub fn constraint_propagation(self) -> Option<PropagatedProblem> {
-        self.make_node_consistency()
-            .make_arc_consistency()
-            .map(Self::sort_domains)
-            .map(
-                |NormalizedProblem {
-                     variables,
-                     domains,
-                     constraints,
-                 }| PropagatedProblem {
-                    variables,
-                    domains,
-                    constraints: Self::sort_constraints(constraints.into_iter().collect()),
-                },
-            )
+    /// Values of the `i`th domain, generating them if it is lazy
+    pub(crate) fn values(&self, i: usize) -> &[Universe] {
+        domain_values(&self.domains, &self.lazy_domains, i)
+    }
+    /// Generates the lazy domains of `vars`
+    pub(crate) fn realize(&mut self, vars: impl IntoIterator<Item = Variable>) {
+        realize_domains(&mut self.domains, &mut self.lazy_domains, vars);
+    }
+    /// Scope of every constraint, ordered by scope
+    pub fn constraints(&self) -> impl Iterator<Item = &[Variable]> {
+        self.constraints
+            .values()
+            .flatten()
+            .map(|constraint| constraint.scope.as_slice())
+    }
+    /// Fraction of the constraints with every variable of their scope
+    /// assigned that `assignment`, indexed by variable, satisfies. The others
+    /// aren't counted, so it is 1 if there are none. Custom propagators are
+    /// ignored
+    pub fn satisfied_fraction(&self, assignment: &[Option<Universe>]) -> f64 {
+        let mut assigned = 0;
+        let mut satisfied = 0;
+        for constraint in self.constraints.values().flatten() {
+            let vals: Option<Vec<Universe>> = constraint
+                .scope
+                .iter()
+                .map(|var| assignment[var.id])
+                .collect();
+            if let Some(vals) = vals {
+                assigned += 1;
+                satisfied += (constraint.evaluate)(&mut vals.into_iter()) as usize;
+            }
+        }
+        if assigned == 0 {
+            1.0
+        } else {
+            satisfied as f64 / assigned as f64
+        }
+    }
+}
+
+pub struct PropagatedProblem {
+    pub variables: Vec<Variable>,
+    /// Lazy domains are left empty here until they are generated
+    pub domains: Vec<Domain>,
+    pub lazy_domains: BTreeMap<Variable, LazyDomain>,
+    pub constraints: Vec<(Vec<Variable>, Vec<Constraint>)>,
+    pub consistency_level: ConsistencyLevel,
+    /// Kept from the [`NormalizedProblem`] so [`Self::assign`] can run them
+    /// again
+    pub propagators: Vec<Box<dyn Propagator>>,
+}
+impl PropagatedProblem {
+    /// Values of the `i`th domain, generating them if it is lazy
+    pub(crate) fn values(&self, i: usize) -> &[Universe] {
+        domain_values(&self.domains, &self.lazy_domains, i)
     }
+    /// Generates every lazy domain left
+    pub(crate) fn realize_all(&mut self) {
+        let vars: Vec<Variable> = self.lazy_domains.keys().copied().collect();
+        realize_domains(&mut self.domains, &mut self.lazy_domains, vars);
+    }
+    /// Copy of the domains, with the lazy ones generated
+    pub(crate) fn realized_domains(&self) -> Vec<Domain> {
+        (0..self.domains.len())
+            .map(|i| Domain {
+                of: self.domains[i].of,
+                values: self.values(i).to_vec(),
+            })
+            .collect()
+    }
+    /// Scope of every constraint, ordered by their last variable. Unary
+    /// constraints are already folded into the domains
+    pub fn constraints(&self) -> impl Iterator<Item = &[Variable]> {
+        self.constraints
+            .iter()
+            .flat_map(|(_, constraints)| constraints)
+            .map(|constraint| constraint.scope.as_slice())
+    }
+    /// Returns true if the assignment gives every variable a value from its
+    /// domain and satisfies every constraint
+    pub fn verify(&self, solution: &[Universe]) -> bool {
+        solution.len() == self.variables.len()
+            && (0..self.domains.len()).all(|i| self.values(i).contains(&solution[i]))
+            && self.constraints.iter().all(|(scope, constraints)| {
+                let vals: Vec<Universe> = scope.iter().map(|var| solution[var.id]).collect();
+                Constraint::all_satisfied(constraints, &vals)
+            })
+    }
+    /// Narrows every domain to its value in `solution`, to keep working with
+    /// the problem after solving it. Panics if some value isn't in its domain
+    pub fn with_solution(mut self, solution: &[Universe]) -> Self {
+        assert_eq!(solution.len(), self.variables.len());
+        self.realize_all();
+        for (domain, &value) in self.domains.iter_mut().zip(solution) {
+            assert!(
+                domain.values.binary_search(&value).is_ok(),
+                "{value} is not in the domain of {:?}",
+                domain.of
+            );
+            domain.values = vec![value];
+        }
+        self
+    }
+    /// Re-checks a certificate from [`Self::solve_with_certificate`]: every
+    /// scope in it must have its constraints satisfied by `solution`, and
+    /// every constraint of the problem must be covered by it
+    pub fn verify_certificate(&self, solution: &[Universe], certificate: &[Vec<Variable>]) -> bool {
+        let covered = self.constraints().all(|scope| {
+            certificate
+                .iter()
+                .any(|checked| checked.as_slice() == scope)
+        });
+        covered
+            && certificate.iter().all(|scope| {
+                let Ok(i) = self
+                    .constraints
+                    .binary_search_by(|(other, _)| propagate::cmp_scopes(other, scope))
+                else {
+                    return false;
+                };
+                let vals: Vec<Universe> = scope.iter().map(|var| solution[var.id]).collect();
+                Constraint::all_satisfied(&self.constraints[i].1, &vals)
+            })
+    }
+}
+
+// https://cs.uwaterloo.ca/~vanbeek/Publications/jair01.pdf
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+    use hashbrown::HashSet;
+
+    use super::*;
+
+    #[test]
+    fn test_same_scope_constraints_kept_separate() {
+        let mut problem = RawProblem::new();
+        let x = problem.add_var(vec![1, 2, 3, 4, 5, 6]);
+        let y = problem.add_var(vec![1, 2, 3, 4, 5, 6]);
 
-    fn make_node_consistency(mut self) -> Self {
-        for i in 0..self.variables.len() {
-            let var = self.variables[i];
-            let domain = &mut self.domains[i].values;
+        // x < y and x + y == 7
+        problem.add_constraint(
+            vec![x, y],
+            Box::new(|vals| vals.next().unwrap() < vals.next().unwrap()),
+        );
+        problem.add_constraint(
+            vec![x, y],
+            Box::new(|vals| vals.next().unwrap() + vals.next().unwrap() == 7),
+        );
 
-            if let Some(eval) = self.constraints.remove(&vec![var]) {
-                domain.retain(|&vx| eval(&mut [vx].into_iter()));
+        let problem = problem.normalize_problem();
+        assert_eq!(problem.constraints.len(), 1);
+        assert_eq!(problem.constraints[&vec![x, y]].len(), 2);
+
+        let problem = problem.constraint_propagation().unwrap();
+        assert_eq!(problem.constraints[0].1.len(), 2);
+
+        let solution = problem.solve_backtracking().unwrap();
+        assert_eq!(solution, vec![1, 6]);
+        let solution = problem.solve_cbj().unwrap();
+        assert!(solution[0] < solution[1] && solution[0] + solution[1] == 7);
+    }
+
+    /// 4x4 sudoku made of binary not-equal constraints
+    fn mini_sudoku() -> RawProblem {
+        let mut problem = RawProblem::new();
+        let cells: Vec<Variable> = (0..16).map(|_| problem.add_var(vec![1, 2, 3, 4])).collect();
+
+        let same_group = |a: usize, b: usize| {
+            let (ax, ay, bx, by) = (a % 4, a / 4, b % 4, b / 4);
+            ax == bx || ay == by || (ax / 2 == bx / 2 && ay / 2 == by / 2)
+        };
+        for a in 0..16 {
+            for b in a + 1..16 {
+                if same_group(a, b) {
+                    problem.add_constraint(
+                        vec![cells[a], cells[b]],
+                        Box::new(|vals| vals.next() != vals.next()),
+                    );
+                }
             }
         }
 
-        self
+        problem
+    }
+
+    #[test]
+    fn test_template_instantiate() {
+        let mut template = ProblemTemplate::new(mini_sudoku().normalize_problem()).unwrap();
+        let var = |id| Variable { id };
+
+        let clues_a = [
+            (var(0), 1),
+            (var(1), 2),
+            (var(4), 3),
+            (var(8), 2),
+            (var(13), 1),
+        ];
+        let problem = template.instantiate(&clues_a).unwrap();
+        let solution_a = problem.solve_backtracking().unwrap();
+        assert!(problem.verify(&solution_a));
+        assert!(clues_a.iter().all(|&(v, val)| solution_a[v.id] == val));
+
+        let clues_b = [
+            (var(0), 4),
+            (var(1), 3),
+            (var(4), 2),
+            (var(8), 3),
+            (var(13), 4),
+        ];
+        let problem = template.instantiate(&clues_b).unwrap();
+        let solution_b = problem.solve_cbj().unwrap();
+        assert!(problem.verify(&solution_b));
+        assert!(clues_b.iter().all(|&(v, val)| solution_b[v.id] == val));
+
+        assert_ne!(solution_a, solution_b);
+
+        // Conflicting clues in the same row
+        assert!(template.instantiate(&[(var(0), 1), (var(1), 1)]).is_none());
+    }
+
+    #[test]
+    fn test_brute_force_matches_cbj() {
+        let mut problem = RawProblem::new();
+        let x = problem.add_var(vec![1, 2, 3, 4]);
+        let y = problem.add_var(vec![1, 2, 3, 4]);
+        let z = problem.add_var(vec![1, 2, 3, 4]);
+
+        // x < y < z and x + y + z == 8
+        problem.add_constraint(
+            vec![x, y],
+            Box::new(|vals| vals.next().unwrap() < vals.next().unwrap()),
+        );
+        problem.add_constraint(
+            vec![y, z],
+            Box::new(|vals| vals.next().unwrap() < vals.next().unwrap()),
+        );
+        problem.add_constraint(vec![x, y, z], Box::new(|vals| vals.sum::<Universe>() == 8));
+
+        let problem = problem
+            .normalize_problem()
+            .constraint_propagation()
+            .unwrap();
+        let brute_force = problem.solve_brute_force().unwrap();
+        assert_eq!(brute_force, problem.solve_cbj());
+        assert_eq!(brute_force, Some(vec![1, 3, 4]));
+    }
+
+    #[test]
+    fn test_brute_force_refuses_large_problems() {
+        let mut problem = RawProblem::new();
+        for _ in 0..10 {
+            problem.add_var((0..10).collect());
+        }
+        let problem = problem
+            .normalize_problem()
+            .constraint_propagation()
+            .unwrap();
+
+        assert_eq!(
+            problem.solve_brute_force(),
+            Err(SearchSpaceTooLarge {
+                size: 10_000_000_000
+            })
+        );
+    }
+
+    #[test]
+    fn test_constraint_over_all_variables() {
+        let mut problem = RawProblem::new();
+        let vars: Vec<Variable> = (0..4)
+            .map(|_| problem.add_var(vec![1, 2, 3, 4, 5]))
+            .collect();
+
+        problem.add_constraint(
+            vec![vars[0], vars[1]],
+            Box::new(|vals| vals.next().unwrap() > vals.next().unwrap()),
+        );
+        // Strictly decreasing with a prime sum, only [5, 3, 2, 1] and [5, 4, 3, 1]
+        problem.add_constraint(
+            vars.clone(),
+            Box::new(|vals| {
+                let vals: Vec<Universe> = vals.collect();
+                let sum: Universe = vals.iter().sum();
+                vals.windows(2).all(|w| w[0] > w[1]) && (2..sum).all(|d| sum % d != 0)
+            }),
+        );
+
+        let problem = problem
+            .normalize_problem()
+            .constraint_propagation()
+            .unwrap();
+        let solutions = [vec![5, 3, 2, 1], vec![5, 4, 3, 1]];
+
+        assert_eq!(problem.solve_backtracking(), Some(solutions[0].clone()));
+        assert_eq!(
+            problem.solve_brute_force().unwrap(),
+            Some(solutions[0].clone())
+        );
+        assert!(solutions.contains(&problem.solve_cbj().unwrap()));
+    }
+
+    #[test]
+    fn test_cbj_jumps_to_latest_culprit() {
+        let mut problem = RawProblem::new();
+        let a = problem.add_var(vec![1, 2]);
+        let b = problem.add_var(vec![1, 2]);
+        let c = problem.add_var(vec![1, 2]);
+
+        // `a` takes part in more conflicts, but only changing `b` leads to the
+        // solution [2, 1, 1], so CBJ must not jump over it back to `a`
+        problem.add_constraint(vec![a, c], Box::new(|vals| vals.next() != vals.next()));
+        problem.add_constraint(vec![a, b, c], Box::new(|vals| vals.eq([2, 1, 1])));
+
+        let problem = problem
+            .normalize_problem()
+            .constraint_propagation()
+            .unwrap();
+        assert_eq!(problem.solve_cbj(), Some(vec![2, 1, 1]));
+        assert_eq!(problem.solve_cbj(), problem.solve_brute_force().unwrap());
     }
-    fn make_arc_consistency(mut self) -> Option<Self> {
-        // Using AC-3 from https://en.wikipedia.org/wiki/AC-3_algorithm
-        let mut vars_cartesian_product =
-            Vec::with_capacity(self.variables.len() * self.variables.len());
-        for &var1 in &self.variables {
-            for &var2 in &self.variables {
-                vars_cartesian_product.push((var1, var2));
+
+    #[test]
+    fn test_cbj_weighted() {
+        let mut problem = RawProblem::new();
+        // Four variables that are easy to satisfy, coming first
+        let easy: Vec<Variable> = (0..4).map(|_| problem.add_var_range(1, 4)).collect();
+        for pair in easy.windows(2) {
+            problem.add_constraint(pair.to_vec(), Box::new(|vals| vals.next() != vals.next()));
+        }
+        // Three variables that can't all differ with two values, which arc
+        // consistency doesn't notice
+        let core: Vec<Variable> = (0..3).map(|_| problem.add_var_range(1, 2)).collect();
+        let mut core_scopes = Vec::new();
+        for (i, &a) in core.iter().enumerate() {
+            for &b in &core[i + 1..] {
+                problem.add_constraint(vec![a, b], Box::new(|vals| vals.next() != vals.next()));
+                core_scopes.push(vec![a, b]);
             }
         }
+        let problem = problem
+            .normalize_problem()
+            .constraint_propagation()
+            .unwrap();
 
-        let mut worklist: Vec<(Variable, Variable)> =
-            Vec::from_iter(vars_cartesian_product.iter().cloned().filter(|&(x, y)| {
-                self.constraints.get(&vec![x, y]).is_some()
-                    || self.constraints.get(&vec![y, x]).is_some()
-            }));
+        let (solution, unweighted) = problem.solve_cbj_stats();
+        assert_eq!(solution, None);
+        // No weights is the same search as plain CBJ
+        let mut weights = ConstraintWeights::new();
+        let (_, first_run) = problem.solve_cbj_weighted(&mut weights);
+        assert_eq!(first_run, unweighted);
+
+        // The core's constraints failed, so the next run starts with it and
+        // never assigns the easy variables
+        let easy_weight = weights.weight(&easy[..2]);
+        assert!(core_scopes
+            .iter()
+            .all(|scope| weights.weight(scope) > easy_weight));
+        let (solution, learned) = problem.solve_cbj_weighted(&mut weights);
+        assert_eq!(solution, None);
+        assert!(
+            learned.nodes < unweighted.nodes,
+            "{learned:?} {unweighted:?}"
+        );
 
-        while let Some(arc) = worklist.pop() {
-            let (x, y) = arc;
+        // Weights given up front do the same
+        let mut weights = ConstraintWeights::new();
+        for scope in core_scopes {
+            weights.set(scope, 1);
+        }
+        let (_, given) = problem.solve_cbj_weighted(&mut weights);
+        assert!(given.nodes < unweighted.nodes, "{given:?} {unweighted:?}");
+    }
 
-            if self.arc_reduce(x, y) {
-                if self.domains[x.id].values.is_empty() {
-                    return None;
+    #[test]
+    fn test_custom_propagator() {
+        struct RemoveValue(Variable, Universe);
+        impl Propagator for RemoveValue {
+            fn propagate(&mut self, domains: &mut [Domain]) -> PropagationResult {
+                let values = &mut domains[self.0.id].values;
+                if values.contains(&self.1) {
+                    values.retain(|&v| v != self.1);
+                    PropagationResult::Changed
                 } else {
-                    worklist.extend(vars_cartesian_product.iter().cloned().filter(|&(z, xx)| {
-                        z != y && xx == x && self.constraints.get(&vec![z, x]).is_some()
-                            || self.constraints.get(&vec![x, z]).is_some()
-                    }))
+                    PropagationResult::Unchanged
                 }
             }
         }
 
-        Some(self)
+        let mut problem = RawProblem::new();
+        let x = problem.add_var(vec![1, 2, 3]);
+        let y = problem.add_var(vec![1, 2, 3]);
+        problem.add_constraint(vec![x, y], Box::new(|vals| vals.next() == vals.next()));
+
+        let mut problem = problem.normalize_problem();
+        problem.add_propagator(Box::new(RemoveValue(x, 2)));
+        let problem = problem.constraint_propagation().unwrap();
+
+        assert_eq!(problem.domains[x.id].values, vec![1, 3]);
+        // Arc consistency carries the removal over to `y`
+        assert_eq!(problem.domains[y.id].values, vec![1, 3]);
     }
-    fn arc_reduce(&mut self, x: Variable, y: Variable) -> bool {
-        let mut change = false;
 
-        for vx in self.domains[x.id].values.clone() {
-            if !self.domains[y.id].values.iter().any(|&vy| {
-                self.constraints
-                    .get(&vec![x, y])
-                    .is_some_and(|eval| eval(&mut [vx, vy].into_iter()))
-            }) {
-                self.domains[x.id].values.retain(|&vxx| vxx != vx);
-                change = true;
+    #[test]
+    fn test_partial_evaluation_prunes_early() {
+        use alloc::rc::Rc;
+        use core::cell::Cell;
+
+        // x + y + z == 3 over 1..=5 has the single solution [1, 1, 1]
+        let solve = |with_partial: bool| {
+            let full_checks = Rc::new(Cell::new(0));
+            let counter = full_checks.clone();
+
+            let mut problem = RawProblem::new();
+            let vars: Vec<Variable> = (0..3)
+                .map(|_| problem.add_var(vec![1, 2, 3, 4, 5]))
+                .collect();
+            let sum_is_three: Evaluation = Box::new(move |vals| {
+                counter.set(counter.get() + 1);
+                vals.sum::<Universe>() == 3
+            });
+            if with_partial {
+                problem.add_constraint_with_partial(
+                    vars,
+                    sum_is_three,
+                    // The sum so far can't go over 3
+                    Box::new(|vals| vals.flatten().sum::<Universe>() <= 3),
+                );
+            } else {
+                problem.add_constraint(vars, sum_is_three);
             }
-        }
-        change
+
+            let problem = problem
+                .normalize_problem()
+                .constraint_propagation()
+                .unwrap();
+            let backtracking = problem.solve_backtracking();
+            assert_eq!(backtracking, problem.solve_cbj());
+            (backtracking, full_checks.get())
+        };
+
+        let (solution, checks_without) = solve(false);
+        assert_eq!(solution, Some(vec![1, 1, 1]));
+        let (solution, checks_with) = solve(true);
+        assert_eq!(solution, Some(vec![1, 1, 1]));
+        assert!(checks_with < checks_without);
+    }
+
+    #[test]
+    fn test_validate_unary_constraints() {
+        let mut problem = RawProblem::new();
+        let x = problem.add_var(vec![1, 2, 3]);
+        let y = problem.add_var(vec![1, 2, 3]);
+        problem.add_constraint(vec![x], Box::new(|vals| vals.next().unwrap() > 1));
+        assert_eq!(problem.validate(), Ok(()));
+
+        problem.add_constraint(vec![y], Box::new(|vals| vals.next().unwrap() > 3));
+        assert_eq!(problem.validate(), Err(vec![y]));
+
+        // Each constraint leaves values, but not together
+        problem.add_constraint(vec![x], Box::new(|vals| vals.next().unwrap() < 2));
+        assert_eq!(problem.validate(), Err(vec![x, y]));
     }
-    fn sort_domains(mut self) -> Self {
-        for domain in self.domains.iter_mut() {
-            domain.values.sort_unstable()
+
+    #[test]
+    fn test_solutions_shuffled() {
+        let mut problem = RawProblem::new();
+        let vars: Vec<Variable> = (0..3).map(|_| problem.add_var(vec![1, 2, 3, 4])).collect();
+        for (i, &a) in vars.iter().enumerate() {
+            for &b in &vars[i + 1..] {
+                problem.add_constraint(vec![a, b], Box::new(|vals| vals.next() != vals.next()));
+            }
         }
-        self
+        let problem = problem
+            .normalize_problem()
+            .constraint_propagation()
+            .unwrap();
+
+        let ordered: Vec<Vec<Universe>> = problem.solutions().collect();
+        assert_eq!(ordered.len(), 24);
+        assert!(ordered.is_sorted());
+        assert_eq!(ordered.first(), problem.solve_backtracking().as_ref());
+
+        let shuffled: Vec<Vec<Universe>> = problem.solutions_shuffled(42).collect();
+        assert_ne!(shuffled, ordered);
+        let mut sorted = shuffled.clone();
+        sorted.sort();
+        assert_eq!(sorted, ordered);
+
+        // Same seed, same order
+        assert_eq!(problem.solutions_shuffled(42).collect::<Vec<_>>(), shuffled);
+    }
+
+    #[test]
+    fn test_add_var_range() {
+        let mut problem = RawProblem::new();
+        let x = problem.add_var_range(1, 9);
+        assert_eq!(
+            problem.domains[x.id].values,
+            vec![1, 2, 3, 4, 5, 6, 7, 8, 9]
+        );
+
+        let y = problem.add_var_range(5, 4);
+        assert!(problem.domains[y.id].values.is_empty());
+        assert!(problem
+            .normalize_problem()
+            .constraint_propagation()
+            .is_none());
     }
-    fn sort_constraints(
-        mut constraints: Vec<(Vec<Variable>, Evaluation)>,
-    ) -> Vec<(Vec<Variable>, Evaluation)> {
-        constraints.sort_unstable_by(|(scope_a, _), (scope_b, _)| {
-            let mut rev_a = scope_a.iter().rev();
-            let mut rev_b = scope_b.iter().rev();
-            loop {
-                let a = rev_a.next().map(|v| v.id);
-                let b = rev_b.next().map(|v| v.id);
-
-                match (a, b) {
-                    (None, None) => return Ordering::Equal,
-                    (None, Some(_)) => return Ordering::Less,
-                    (Some(_), None) => return Ordering::Greater,
-                    (Some(a), Some(b)) => {
-                        if a == b {
-                            continue;
-                        } else {
-                            return a.cmp(&b);
-                        }
-                    }
+
+    #[test]
+    fn test_consistency_level() {
+        let not_equal_clique = |values: Vec<Universe>| {
+            let mut problem = RawProblem::new();
+            let vars: Vec<Variable> = (0..3).map(|_| problem.add_var(values.clone())).collect();
+            for (i, &a) in vars.iter().enumerate() {
+                for &b in &vars[i + 1..] {
+                    problem.add_constraint(vec![a, b], Box::new(|vals| vals.next() != vals.next()));
                 }
             }
-        });
-        constraints
+            problem
+                .normalize_problem()
+                .constraint_propagation()
+                .unwrap()
+        };
+
+        let problem = not_equal_clique(vec![1, 2, 3]);
+        assert_eq!(problem.consistency_level, ConsistencyLevel::ArcConsistent);
+        let problem = problem.make_singleton_arc_consistency().unwrap();
+        assert_eq!(
+            problem.consistency_level,
+            ConsistencyLevel::SingletonArcConsistent
+        );
+
+        // Arc consistent but infeasible, which singleton arc consistency notices
+        let problem = not_equal_clique(vec![1, 2]);
+        assert!(problem.make_singleton_arc_consistency().is_none());
     }
-}
 
-pub struct PropagatedProblem {
-    pub variables: Vec<Variable>,
-    pub domains: Vec<Domain>,
-    pub constraints: Vec<(Vec<Variable>, Evaluation)>,
-}
+    #[test]
+    fn test_solution_upper_bound() {
+        let mut problem = RawProblem::new();
+        let x = problem.add_var_range(1, 3);
+        let y = problem.add_var_range(1, 3);
+        problem.add_constraint(vec![x], Box::new(|vals| vals.next() == Some(2)));
+        problem.add_constraint(vec![x, y], Box::new(|vals| vals.next() == vals.next()));
+        let problem = problem
+            .normalize_problem()
+            .constraint_propagation()
+            .unwrap();
+        assert_eq!(problem.solution_upper_bound(), 1);
+        assert_eq!(problem.solutions().count(), 1);
 
-// Based on https://en.wikipedia.org/wiki/Backtracking and https://www.geeksforgeeks.org/sudoku-backtracking-7/
-impl PropagatedProblem {
-    pub fn solve_backtracking(&self) -> Option<Vec<Universe>> {
-        let mut candidate: Candidate = vec![None; self.variables.len()];
-        if self.backtrack(&mut candidate, 0) {
-            candidate.into_iter().collect()
-        } else {
-            None
+        let mut problem = RawProblem::new();
+        problem.add_var_range(1, 3);
+        problem.add_var_range(1, 4);
+        problem.add_var_range(1, 5);
+        let problem = problem
+            .normalize_problem()
+            .constraint_propagation()
+            .unwrap();
+        assert_eq!(problem.solution_upper_bound(), 60);
+
+        let mut problem = RawProblem::new();
+        for _ in 0..40 {
+            problem.add_var_range(0, 99);
         }
+        let problem = problem
+            .normalize_problem()
+            .constraint_propagation()
+            .unwrap();
+        assert_eq!(problem.solution_upper_bound(), u128::MAX);
     }
-    fn backtrack(&self, candidate: &mut Candidate, k: usize) -> bool {
-        // for _ in 0..k {
-        //     print!("-");
-        // }
-        // println!();
 
-        if self.reject(candidate, k) {
-            return false;
+    #[test]
+    fn test_exactly_one_and_at_most_one() {
+        let count_accepted = |constraint: &Constraint| {
+            (0..8)
+                .filter(|bits| {
+                    let mut vals = (0..3).map(|i| (bits >> i) & 1);
+                    (constraint.evaluate)(&mut vals)
+                })
+                .count()
+        };
+        let scope = vec![Variable { id: 0 }, Variable { id: 1 }, Variable { id: 2 }];
+        assert_eq!(count_accepted(&Constraint::exactly_one(scope.clone())), 3);
+        assert_eq!(count_accepted(&Constraint::at_most_one(scope.clone())), 4);
+
+        // Setting one variable forces the rest to 0
+        let mut problem = RawProblem::new();
+        let vars: Vec<Variable> = (0..4).map(|_| problem.add_var(vec![0, 1])).collect();
+        problem.push_constraint(Constraint::exactly_one(vars.clone()));
+        problem.add_constraint(vec![vars[2]], Box::new(|vals| vals.next() == Some(1)));
+        let mut problem = problem.normalize_problem();
+        problem.add_propagator(Box::new(OneHot::exactly_one(vars.clone())));
+        let problem = problem.constraint_propagation().unwrap();
+        let domains: Vec<&[Universe]> = problem
+            .domains
+            .iter()
+            .map(|d| d.values.as_slice())
+            .collect();
+        assert_eq!(domains, vec![&[0][..], &[0], &[1], &[0]]);
+        assert_eq!(problem.solve_backtracking(), Some(vec![0, 0, 1, 0]));
+
+        // With all but one set to 0 the last one is forced to 1
+        let mut problem = RawProblem::new();
+        let vars: Vec<Variable> = (0..3).map(|_| problem.add_var(vec![0, 1])).collect();
+        for &var in &vars[..2] {
+            problem.add_constraint(vec![var], Box::new(|vals| vals.next() == Some(0)));
         }
-        if self.accept(candidate) {
-            return true;
+        let mut problem = problem.normalize_problem();
+        problem.add_propagator(Box::new(OneHot::exactly_one(vars.clone())));
+        let problem = problem.constraint_propagation().unwrap();
+        assert_eq!(problem.domains[2].values, vec![1]);
+
+        // At most one allows all of them to be 0, but not two ones
+        let mut problem = RawProblem::new();
+        let vars: Vec<Variable> = (0..3).map(|_| problem.add_var(vec![0, 1])).collect();
+        for &var in &vars[..2] {
+            problem.add_constraint(vec![var], Box::new(|vals| vals.next() == Some(1)));
         }
+        let mut problem = problem.normalize_problem();
+        problem.add_propagator(Box::new(OneHot::at_most_one(vars.clone())));
+        assert!(problem.constraint_propagation().is_none());
+    }
 
-        let mut s = self.first(candidate, k);
-        while s {
-            let res = self.backtrack(candidate, k + 1);
-            if res {
-                return true;
-            }
+    #[test]
+    fn test_assign() {
+        let mut problem = mini_sudoku()
+            .normalize_problem()
+            .constraint_propagation()
+            .unwrap();
+        assert!(problem.domains.iter().all(|dom| dom.values.len() == 4));
 
-            s = self.next(candidate, k + 1);
+        problem.assign(Variable { id: 0 }, 1).unwrap();
+        assert_eq!(problem.domains[0].values, vec![1]);
+        // Same row, column and square lose the value
+        for id in [1, 2, 3, 4, 8, 12, 5] {
+            assert_eq!(problem.domains[id].values, vec![2, 3, 4]);
         }
+        assert_eq!(problem.domains[10].values, vec![1, 2, 3, 4]);
+
+        problem.assign(Variable { id: 1 }, 2).unwrap();
+        assert_eq!(problem.domains[4].values, vec![3, 4]);
+        let solution = problem.solve_backtracking().unwrap();
+        assert_eq!(solution[..2], [1, 2]);
 
-        candidate[k] = None;
-        false
+        // Same value in the same row
+        let before = problem.snapshot();
+        assert!(problem.assign(Variable { id: 2 }, 1).is_none());
+        assert_eq!(problem.snapshot(), before);
     }
-    /// Returns true if candidate values are inconsistent with constraints
-    fn reject(&self, candidate: &Candidate, k: usize) -> bool {
-        // let k = candidate.len();
-        if k == 0 {
-            return false;
+
+    #[test]
+    fn test_assign_runs_propagators() {
+        let mut problem = RawProblem::new();
+        let vars: Vec<Variable> = (0..4).map(|_| problem.add_var_range(1, 3)).collect();
+        let mut problem = problem.normalize_problem();
+        let scopes = [
+            vec![vars[0], vars[1], vars[2]],
+            vec![vars[1], vars[3]],
+            vec![vars[2], vars[3]],
+        ];
+        for scope in scopes {
+            problem.add_propagator(Box::new(AllDifferent::new(scope)));
         }
+        let mut problem = problem.constraint_propagation().unwrap();
 
-        let curr_var = self.variables[k - 1];
+        // There are no binary constraints, only the propagators remove values
+        problem.assign(vars[0], 1).unwrap();
+        assert_eq!(problem.domains[vars[1].id].values, vec![2, 3]);
+        assert_eq!(problem.domains[vars[2].id].values, vec![2, 3]);
+        assert_eq!(problem.domains[vars[3].id].values, vec![1, 2, 3]);
 
-        let to_check = self
-            .constraints
-            .iter()
-            .filter(|constraint| constraint.0.last() == Some(&curr_var));
+        // The other two both become 3, which the first propagator rejects
+        let before = problem.snapshot();
+        assert!(problem.assign(vars[3], 2).is_none());
+        assert_eq!(problem.snapshot(), before);
+    }
 
-        for constraint in to_check {
-            let mut vals_needed = constraint.0.iter().map(|var| candidate[var.id].unwrap());
-            if !constraint.1(&mut vals_needed) {
-                return true;
-            }
-        }
+    #[test]
+    fn test_cheap_constraints_checked_first() {
+        use alloc::rc::Rc;
+        use core::cell::Cell;
+
+        let expensive_calls = Rc::new(Cell::new(0));
+        let counter = expensive_calls.clone();
 
-        false
+        let mut problem = RawProblem::new();
+        let x = problem.add_var(vec![1, 2, 3]);
+        let y = problem.add_var(vec![1, 2, 3]);
+        let z = problem.add_var(vec![1, 2, 3]);
+        problem.push_constraint(
+            Constraint::new(
+                vec![x, y, z],
+                Box::new(move |vals| {
+                    counter.set(counter.get() + 1);
+                    vals.sum::<Universe>() > 0
+                }),
+            )
+            .with_cost(10),
+        );
+        problem.add_constraint(vec![x, y, z], Box::new(|_| false));
+
+        let problem = problem.normalize_problem();
+        assert_eq!(problem.constraints[&vec![x, y, z]][0].cost, 1);
+        let problem = problem.constraint_propagation().unwrap();
+        assert_eq!(problem.solve_backtracking(), None);
+        assert_eq!(problem.solve_cbj(), None);
+        assert_eq!(expensive_calls.get(), 0);
     }
-    /// Returns true if candidate values are consistent and complete with constraints
-    fn accept(&self, candidate: &Candidate) -> bool {
-        candidate[candidate.len() - 1].is_some()
+
+    #[test]
+    fn test_optional_variables() {
+        let mut problem = RawProblem::new();
+        let x = problem.add_var(vec![1, 2, 3]);
+        let y = problem.add_optional_var(vec![4, 5]);
+        let z = problem.add_var(vec![1, 2, 3]);
+        problem.push_constraint(Constraint::present_sum(vec![x, y, z], 3));
+        problem.push_constraint(Constraint::present_all_different(vec![x, y, z]));
+
+        let problem = problem
+            .normalize_problem()
+            .constraint_propagation()
+            .unwrap();
+        let solutions: Vec<_> = problem.solutions().collect();
+        // `y` can't fit in a sum of 3, so only `x` and `z` are summed
+        assert_eq!(solutions, vec![vec![1, ABSENT, 2], vec![2, ABSENT, 1]]);
+
+        let mut problem = RawProblem::new();
+        let vars: Vec<Variable> = (0..3).map(|_| problem.add_optional_var(vec![1])).collect();
+        problem.push_constraint(Constraint::present_all_different(vars));
+        let problem = problem
+            .normalize_problem()
+            .constraint_propagation()
+            .unwrap();
+        // At most one of them can be present
+        assert_eq!(problem.solutions().count(), 4);
     }
-    fn first(&self, candidate: &mut Candidate, k: usize) -> bool {
-        // let k = candidate.len();
-        if candidate.last().is_some_and(|x| x.is_some()) {
-            false
-        } else {
-            let first_val_next_var = self.domains[k].values[0];
-            // let mut next_cand = candidate.clone();
-            // next_cand.push(first_val_next_var);
-            // Some(next_cand)
-            candidate[k] = Some(first_val_next_var);
 
-            true
-        }
+    #[test]
+    fn test_find_mus() {
+        let mut problem = RawProblem::new();
+        let x = problem.add_var(vec![1, 2, 3]);
+        let y = problem.add_var(vec![1, 2, 3]);
+        let z = problem.add_var(vec![1, 2, 3]);
+        problem.add_constraint(vec![y, z], Box::new(|vals| vals.next() != vals.next()));
+        problem.add_constraint(vec![x], Box::new(|vals| vals.next() == Some(1)));
+        problem.add_constraint(
+            vec![x, y],
+            Box::new(|vals| vals.next().unwrap() > vals.next().unwrap()),
+        );
+
+        assert_eq!(
+            problem.normalize_problem().find_mus(),
+            vec![vec![x], vec![x, y]]
+        );
+
+        let satisfiable = mini_sudoku().normalize_problem();
+        assert!(satisfiable.find_mus().is_empty());
     }
-    fn next(&self, candidate: &mut Candidate, k: usize) -> bool {
-        // let k = candidate.len();
-        if candidate[k - 1] == self.domains[k - 1].values.last().copied() {
-            return false;
-        }
 
-        let curr_val = candidate[k - 1].unwrap();
-        let i = self.domains[k - 1].values.binary_search(&curr_val).unwrap();
-        candidate[k - 1] = Some(self.domains[k - 1].values[i + 1]);
-        true
+    #[test]
+    fn test_solve_all_optimal() {
+        // cost == x + y with x != y, cheapest is 1 + 2 either way around
+        let mut problem = RawProblem::new();
+        let x = problem.add_var(vec![1, 2, 3]);
+        let y = problem.add_var(vec![1, 2, 3]);
+        let cost = problem.add_var_range(0, 10);
+        problem.add_constraint(vec![x, y], Box::new(|vals| vals.next() != vals.next()));
+        problem.add_constraint(
+            vec![x, y, cost],
+            Box::new(|vals| vals.next().unwrap() + vals.next().unwrap() == vals.next().unwrap()),
+        );
+        let problem = problem
+            .normalize_problem()
+            .constraint_propagation()
+            .unwrap();
+
+        let (best, solution) = problem.solve_optimize(cost, Sense::Minimize).unwrap();
+        assert_eq!(best, 3);
+        assert!(problem.verify(&solution) && solution[cost.id] == 3);
+
+        let (best, solutions) = problem.solve_all_optimal(cost, Sense::Minimize).unwrap();
+        assert_eq!(best, 3);
+        assert_eq!(solutions, vec![vec![1, 2, 3], vec![2, 1, 3]]);
+
+        let (best, solutions) = problem.solve_all_optimal(cost, Sense::Maximize).unwrap();
+        assert_eq!(best, 5);
+        assert_eq!(solutions, vec![vec![2, 3, 5], vec![3, 2, 5]]);
     }
-}
 
-// CBJ based on https://cse.unl.edu/~choueiry/Documents/Hybrid-Prosser.pdf
-// (HYBRID ALGORITHMS FOR THE CONSTRAINT SATISFACTION PROBLEM PATRICK PROSS)
-// impl PropagatedProblem {
-//     pub fn solve_cbj(&mut self) -> Option<Vec<Universe>> {
-//         let mut vals = vec![0; self.variables.len()];
-//         let mut current_domain = self.domains.iter().map(|dom| dom.values.clone()).collect();
-//         let mut conf_set: Vec<HashSet<usize>> = vec![HashSet::new(); self.variables.len()];
-//         let mut status = Status::Unknown;
-
-//         self.cbj_bcssp(&mut vals, &mut current_domain, &mut conf_set, &mut status);
-
-//         if status == Status::Solution {
-//             Some(vals)
-//         } else {
-//             None
-//         }
-//     }
-
-//     fn cbj_bcssp(
-//         &mut self,
-//         vals: &mut Vec<Universe>,
-//         current_domain: &mut Vec<Vec<Universe>>,
-//         conf_set: &mut Vec<HashSet<usize>>,
-//         status: &mut Status,
-//     ) {
-//         let mut consistent = true;
-//         *status = Status::Unknown;
-//         let mut i = 0;
-//         let n = self.variables.len();
-
-//         while *status == Status::Unknown {
-//             if consistent {
-//                 i = self.cbj_label(i, vals, current_domain, conf_set, &mut consistent);
-//             } else {
-//                 i = self.cbj_unlabel(i, &mut consistent);
-//             }
-//             if i >= n {
-//                 *status = Status::Solution;
-//             } else if i == 0 {
-//                 *status = Status::Impossible;
-//             }
-//         }
-//     }
-//     fn cbj_label(
-//         &self,
-//         i: usize,
-//         vals: &mut Vec<Universe>,
-//         current_domain: &mut Vec<Vec<Universe>>,
-//         conf_set: &mut Vec<HashSet<usize>>,
-//         consistent: &mut bool,
-//     ) -> usize {
-//         *consistent = false;
-//         for &val in &current_domain[i] {
-//             vals[i] = val;
-//         }
-
-//         todo!()
-//     }
-//     fn cbj_unlabel(&self, i: usize, consistent: &mut bool) -> usize {}
-// }
-
-// #[derive(Debug, Clone, Copy, PartialEq, Eq)]
-// enum Status {
-//     Unknown,
-//     Solution,
-//     Impossible,
-// }
-
-// Based on https://ics.uci.edu/~dechter/books/chapter06.pdf figure 6.7
-impl PropagatedProblem {
-    pub fn solve_cbj(&self) -> Option<Vec<Universe>> {
-        let mut i: usize = 0;
-        let n = self.variables.len();
-        let mut curr_domain: Vec<Vec<Universe>> =
-            self.domains.iter().map(|dom| dom.values.clone()).collect();
-        let mut conf_set: Vec<HashSet<usize>> = vec![HashSet::new(); n];
-        let mut vals: Candidate = vec![None; n];
-
-        while i < n {
-            vals[i] = self.select_val_cbj(i, &mut curr_domain, &mut conf_set, &mut vals);
-
-            if vals[i].is_none() {
-                let i_prev = i;
-                let max = conf_set[i].iter().max();
-                if let Some(&max) = max {
-                    i = max;
-                    let b = conf_set[i_prev].clone();
-                    conf_set[i].extend(&b);
-                    conf_set[i].remove(&i);
-                } else {
-                    return None;
-                }
+    #[test]
+    fn test_fix_var() {
+        let mut problem = RawProblem::new();
+        let x = problem.add_var_range(1, 5);
+        let y = problem.add_var_range(1, 5);
+        problem.add_constraint(vec![x, y], Box::new(|vals| vals.next() < vals.next()));
+        problem.fix_var(y, 2);
+        assert_eq!(problem.domains[y.id].values, vec![2]);
+        assert_eq!(problem.constraints.len(), 1);
+
+        let problem = problem
+            .normalize_problem()
+            .constraint_propagation()
+            .unwrap();
+        assert_eq!(problem.domains[x.id].values, vec![1]);
+
+        let mut problem = RawProblem::new();
+        let x = problem.add_var_range(1, 5);
+        problem.fix_var(x, 7);
+        assert!(problem.domains[x.id].values.is_empty());
+        assert!(problem
+            .normalize_problem()
+            .constraint_propagation()
+            .is_none());
+    }
+
+    #[test]
+    fn test_arc_order() {
+        use alloc::rc::Rc;
+        use core::cell::Cell;
+
+        // Chain of x0 < x1 < ... where only the last variable is narrow, so
+        // its arc is the one that prunes everything else
+        let propagate = |order: ArcOrder| {
+            let checks = Rc::new(Cell::new(0));
+            let mut problem = RawProblem::new();
+            let vars: Vec<Variable> = (0..8).map(|_| problem.add_var_range(0, 20)).collect();
+            problem.fix_var(vars[7], 7);
+            for pair in vars.windows(2) {
+                let counter = checks.clone();
+                problem.add_constraint(
+                    pair.to_vec(),
+                    Box::new(move |vals| {
+                        counter.set(counter.get() + 1);
+                        vals.next() < vals.next()
+                    }),
+                );
+            }
+
+            let mut problem = problem.normalize_problem();
+            problem.arc_order = order;
+            let problem = problem.constraint_propagation().unwrap();
+            (problem.domains, checks.get())
+        };
+
+        let (lifo_domains, lifo_checks) = propagate(ArcOrder::Lifo);
+        let (domains, checks) = propagate(ArcOrder::SmallestDomainFirst);
+        assert_eq!(domains, lifo_domains);
+        assert!(domains
+            .iter()
+            .enumerate()
+            .all(|(i, dom)| dom.values == [i as Universe]));
+        assert!(checks < lifo_checks);
+    }
+
+    #[test]
+    fn test_functional_constraint() {
+        // y == x + 3 and x + y + z == 15
+        let solve = |functional: bool| {
+            let mut problem = RawProblem::new();
+            let x = problem.add_var_range(0, 9);
+            let y = problem.add_var_range(0, 9);
+            let z = problem.add_var_range(0, 9);
+            let plus_three: Function = Box::new(|vals| vals.next().unwrap() + 3);
+            if functional {
+                problem.push_constraint(Constraint::functional(vec![x, y], plus_three));
             } else {
-                i += 1;
-                if i == n {
-                    break;
-                }
-                self.domains[i].values.clone_into(&mut curr_domain[i]);
-                conf_set[i].clear();
+                problem.add_constraint(
+                    vec![x, y],
+                    Box::new(|vals| vals.next().unwrap() + 3 == vals.next().unwrap()),
+                );
             }
-        }
+            problem.add_constraint(vec![x, y, z], Box::new(|vals| vals.sum::<Universe>() == 15));
+            let problem = problem
+                .normalize_problem()
+                .constraint_propagation()
+                .unwrap();
+            assert_eq!(problem.domains[y.id], Domain::range(y, 3, 9));
+            problem.solve_backtracking_stats()
+        };
 
-        vals.into_iter().collect()
+        let (solution, stats) = solve(false);
+        let (functional_solution, functional_stats) = solve(true);
+        assert_eq!(solution, Some(vec![2, 5, 8]));
+        assert_eq!(functional_solution, solution);
+        assert!(functional_stats.nodes < stats.nodes);
     }
 
-    fn select_val_cbj(
-        &self,
-        i: usize,
-        curr_domain: &mut [Vec<Universe>],
-        conf_set: &mut [HashSet<usize>],
-        vals: &mut Candidate,
-    ) -> Option<Universe> {
-        while let Some(a) = curr_domain[i].pop() {
-            vals[i] = Some(a);
-            let mut consistent = true;
-            let mut k = 0;
-            while k < i && consistent {
-                let broken_constraint = self.search_broken_constraint(i, k, vals);
+    #[test]
+    fn test_snapshot_restore() {
+        let mut problem = mini_sudoku()
+            .normalize_problem()
+            .constraint_propagation()
+            .unwrap();
+        problem.assign(Variable { id: 0 }, 1).unwrap();
 
-                if broken_constraint.is_none() {
-                    // Passed all consistency checks
-                    k += 1;
-                } else {
-                    let scope = broken_constraint.unwrap();
-                    conf_set[i].extend(scope.iter().filter_map(|var| {
-                        if var.id != i {
-                            Some(var.id)
-                        } else {
-                            None
-                        }
-                    }));
-                    consistent = false;
-                }
-            }
-            if consistent {
-                return Some(a);
+        let snapshot = problem.snapshot();
+        let before = problem.domains.clone();
+        problem.assign(Variable { id: 5 }, 2).unwrap();
+        problem.assign(Variable { id: 10 }, 1).unwrap();
+        assert_ne!(problem.domains, before);
+
+        problem.restore(snapshot.clone());
+        assert_eq!(problem.domains, before);
+        assert_eq!(problem.snapshot(), snapshot);
+    }
+
+    #[test]
+    fn test_member_constraints() {
+        let mut problem = RawProblem::new();
+        let x = problem.add_var_range(1, 10);
+        let y = problem.add_var_range(1, 10);
+        problem.add_constraint(vec![x, y], Box::new(|vals| vals.next() == vals.next()));
+
+        let primes = vec![2, 3, 5, 7, 11];
+        problem.push_constraint(Constraint::member(x, primes));
+        problem.push_constraint(Constraint::not_member(y, vec![3, 4]));
+        let problem = problem
+            .normalize_problem()
+            .constraint_propagation()
+            .unwrap();
+
+        assert_eq!(problem.domains[x.id].values, vec![2, 5, 7]);
+        assert_eq!(problem.domains[y.id].values, vec![2, 5, 7]);
+    }
+
+    #[test]
+    fn test_deterministic_results() {
+        // Any two of the clues contradict each other, so which pair is found
+        // depends on the order the constraints are gone through
+        let conflicting_clues = || {
+            let mut problem = RawProblem::new();
+            let vars: Vec<Variable> = (0..6).map(|_| problem.add_var_range(1, 3)).collect();
+            for &var in &vars {
+                problem.add_constraint(vec![var], Box::new(|vals| vals.next() == Some(1)));
+                problem.add_constraint(vec![var], Box::new(|vals| vals.next() == Some(2)));
             }
+            problem.normalize_problem()
+        };
+
+        let first = conflicting_clues().find_mus();
+        for _ in 0..10 {
+            assert_eq!(conflicting_clues().find_mus(), first);
         }
 
-        None
+        let problem = mini_sudoku().normalize_problem();
+        let scopes: Vec<Vec<Variable>> = problem.constraints().map(<[Variable]>::to_vec).collect();
+        let solution = problem.constraint_propagation().unwrap().solve_cbj();
+        for _ in 0..10 {
+            let problem = mini_sudoku().normalize_problem();
+            assert!(problem.constraints().eq(scopes.iter().map(Vec::as_slice)));
+            let problem = problem.constraint_propagation().unwrap();
+            assert_eq!(problem.solve_cbj(), solution);
+        }
     }
 
-    fn search_broken_constraint(
-        &self,
-        i: usize,
-        k: usize,
-        vals: &Candidate,
-    ) -> Option<&Vec<Variable>> {
-        let mut broken_constraint = None;
-        for (scope, eval) in &self.constraints {
-            let len = scope.len();
-            if scope[len - 1].id > i {
-                break;
-            }
+    #[test]
+    fn test_iterative_backtracking_deep_chain() {
+        // x0 != x1 != ... over {0, 1}, built directly since arc consistency
+        // over every pair of 5000 variables would take far too long
+        let n = 5000;
+        let variables: Vec<Variable> = (0..n).map(|id| Variable { id }).collect();
+        let domains = variables
+            .iter()
+            .map(|&of| Domain {
+                of,
+                values: vec![0, 1],
+            })
+            .collect();
+        let constraints = variables
+            .windows(2)
+            .map(|pair| {
+                let constraint =
+                    Constraint::new(pair.to_vec(), Box::new(|vals| vals.next() != vals.next()));
+                (pair.to_vec(), vec![constraint])
+            })
+            .collect();
+        let problem = PropagatedProblem {
+            variables,
+            domains,
+            lazy_domains: BTreeMap::new(),
+            constraints,
+            consistency_level: ConsistencyLevel::NodeConsistent,
+            propagators: Vec::new(),
+        };
 
-            if !(len >= 2 && scope[len - 1].id == i && scope[len - 2].id == k) {
-                continue;
-            }
+        let solution = problem.solve_backtracking_iterative().unwrap();
+        assert!(problem.verify(&solution));
+        assert_eq!(solution[..4], [0, 1, 0, 1]);
+
+        let small = mini_sudoku()
+            .normalize_problem()
+            .constraint_propagation()
+            .unwrap();
+        assert_eq!(
+            small.solve_backtracking_iterative(),
+            small.solve_backtracking()
+        );
+    }
 
-            let mut vals_needed = scope.iter().map(|var| vals[var.id].unwrap());
-            if !eval(&mut vals_needed) {
-                broken_constraint = Some(scope);
-                break;
+    #[test]
+    fn test_tie_break() {
+        // Every variable is different from the rest, so every variable ties
+        // under MRV and gets the smallest value left when it's picked
+        let mut problem = RawProblem::new();
+        let vars: Vec<Variable> = (0..5).map(|_| problem.add_var_range(0, 4)).collect();
+        for (i, &a) in vars.iter().enumerate() {
+            for &b in &vars[i + 1..] {
+                problem.add_constraint(vec![a, b], Box::new(|vals| vals.next() != vals.next()));
             }
         }
+        let problem = problem
+            .normalize_problem()
+            .constraint_propagation()
+            .unwrap();
+
+        let solve = |tie_break| {
+            let options = SolverOptions {
+                variable_order: VariableOrder::Mrv,
+                tie_break,
+            };
+            let solution = problem.solve_with_options(&options).unwrap();
+            assert!(problem.verify(&solution));
+            solution
+        };
+        let first_picked = |solution: Vec<Universe>| solution.iter().position(|&v| v == 0);
+
+        assert_eq!(solve(TieBreak::LowestId), vec![0, 1, 2, 3, 4]);
+        assert_eq!(solve(TieBreak::HighestDegree), vec![0, 1, 2, 3, 4]);
+        assert_eq!(solve(TieBreak::Random(1)), solve(TieBreak::Random(1)));
 
-        broken_constraint
+        let firsts: HashSet<Option<usize>> = (0..10)
+            .map(|seed| first_picked(solve(TieBreak::Random(seed))))
+            .collect();
+        assert!(firsts.len() > 1);
     }
-}
 
-// https://cs.uwaterloo.ca/~vanbeek/Publications/jair01.pdf
+    #[test]
+    fn test_support_matrix() {
+        let a = Domain {
+            of: Variable { id: 0 },
+            values: vec![1, 2, 3],
+        };
+        let b = Domain {
+            of: Variable { id: 1 },
+            values: vec![2, 3],
+        };
+        let not_equal = Constraint::new(
+            vec![a.of, b.of],
+            Box::new(|vals| vals.next() != vals.next()),
+        );
+
+        let matrix = not_equal.to_support_matrix(&a, &b);
+        assert_eq!(
+            matrix,
+            vec![vec![true, true], vec![false, true], vec![true, false]]
+        );
+
+        let rebuilt = Constraint::from_support_matrix(&a, &b, matrix.clone());
+        assert_eq!(rebuilt.to_support_matrix(&a, &b), matrix);
+        assert!(!(rebuilt.evaluate)(&mut [4, 2].into_iter()));
+    }
+
+    #[test]
+    fn test_watched_partial_evaluation() {
+        use alloc::rc::Rc;
+        use core::cell::Cell;
+
+        // The 10 variables in 0..=2 add up to at least 12, and the first and
+        // last differ. Only that last part can be checked early, and only
+        // once the last variable is assigned
+        let solve = |watched: bool| {
+            let partial_checks = Rc::new(Cell::new(0));
+            let counter = partial_checks.clone();
+
+            let mut problem = RawProblem::new();
+            let vars: Vec<Variable> = (0..10).map(|_| problem.add_var_range(0, 2)).collect();
+            let mut constraint = Constraint::new(
+                vars.clone(),
+                Box::new(|vals| {
+                    let vals: Vec<Universe> = vals.collect();
+                    vals.iter().sum::<Universe>() >= 12 && vals[0] != vals[9]
+                }),
+            )
+            .with_partial(Box::new(move |vals| {
+                counter.set(counter.get() + 1);
+                let vals: Candidate = vals.collect();
+                vals[0].is_none() || vals[9].is_none() || vals[0] != vals[9]
+            }));
+            if watched {
+                constraint = constraint.with_watched(vec![vars[0], vars[9]]);
+            }
+            problem.push_constraint(constraint);
+
+            let problem = problem
+                .normalize_problem()
+                .constraint_propagation()
+                .unwrap();
+            let solutions: Vec<_> = problem.solutions().collect();
+            (solutions, partial_checks.get())
+        };
+
+        let (solutions, checks) = solve(false);
+        let (watched_solutions, watched_checks) = solve(true);
+        assert!(!solutions.is_empty());
+        assert_eq!(watched_solutions, solutions);
+        assert!(watched_checks < checks);
+    }
+
+    #[test]
+    fn test_every_unary_constraint_applied() {
+        let mut problem = RawProblem::new();
+        let x = problem.add_var_range(1, 10);
+        problem.add_constraint(vec![x], Box::new(|vals| vals.next().unwrap() % 2 == 0));
+        problem.add_constraint(vec![x], Box::new(|vals| vals.next().unwrap() > 5));
+        problem.push_constraint(Constraint::not_member(x, vec![8]));
+
+        let problem = problem.normalize_problem();
+        assert_eq!(problem.constraints[&vec![x]].len(), 3);
+        let problem = problem.constraint_propagation().unwrap();
+        assert_eq!(problem.domains[x.id].values, vec![6, 10]);
+    }
+
+    #[test]
+    fn test_reexported_paths() {
+        use core::any::TypeId;
+
+        // The old crate root paths keep working next to the module ones
+        assert_eq!(
+            TypeId::of::<crate::Constraint>(),
+            TypeId::of::<crate::constraints::Constraint>()
+        );
+        assert_eq!(
+            TypeId::of::<crate::ProblemTemplate>(),
+            TypeId::of::<crate::propagate::ProblemTemplate>()
+        );
+        assert_eq!(
+            TypeId::of::<crate::SolverOptions>(),
+            TypeId::of::<crate::solve::SolverOptions>()
+        );
+        assert_eq!(crate::ABSENT, crate::constraints::ABSENT);
+        assert_eq!(crate::BRUTE_FORCE_LIMIT, crate::solve::BRUTE_FORCE_LIMIT);
+    }
+
+    #[test]
+    fn test_backtracking_guard_prunes() {
+        let mut problem = RawProblem::new();
+        let x = problem.add_var_range(0, 5);
+        let y = problem.add_var_range(0, 5);
+        let z = problem.add_var_range(0, 5);
+        problem.add_constraint(vec![x, y], Box::new(|vals| vals.next() < vals.next()));
+        problem.add_constraint(vec![y, z], Box::new(|vals| vals.next() < vals.next()));
+        let problem = problem
+            .normalize_problem()
+            .constraint_propagation()
+            .unwrap();
+        assert_eq!(problem.solve_backtracking(), Some(vec![0, 1, 2]));
+
+        // Consecutive assigned values must be at least 2 apart, which no
+        // constraint says
+        let guard = |candidate: &[Option<Universe>]| {
+            candidate.windows(2).all(|pair| match pair {
+                [Some(a), Some(b)] => b - a >= 2,
+                _ => true,
+            })
+        };
+        let solution = problem.solve_backtracking_with_guard(guard).unwrap();
+        assert_eq!(solution, vec![0, 2, 4]);
+        assert!(problem.verify(&solution));
+
+        assert_eq!(problem.solve_backtracking_with_guard(|_| false), None);
+    }
+
+    #[test]
+    fn test_max_partial_assignment() {
+        // A cycle of `<` can't hold all at once, but any two of them can
+        let cycle = || {
+            let mut problem = RawProblem::new();
+            let x = problem.add_var_range(0, 2);
+            let y = problem.add_var_range(0, 2);
+            let z = problem.add_var_range(0, 2);
+            problem.add_constraint(vec![x, y], Box::new(|vals| vals.next() < vals.next()));
+            problem.add_constraint(vec![y, z], Box::new(|vals| vals.next() < vals.next()));
+            problem.add_constraint(vec![x, z], Box::new(|vals| vals.next() > vals.next()));
+            problem.normalize_problem()
+        };
+        assert!(cycle().constraint_propagation().is_none());
+
+        let partial = cycle().solve_max_partial();
+        assert_eq!(partial, vec![Some(0), Some(1), None]);
+
+        let mut problem = RawProblem::new();
+        let x = problem.add_var_range(0, 2);
+        let y = problem.add_var_range(0, 2);
+        problem.add_constraint(vec![x, y], Box::new(|vals| vals.next() < vals.next()));
+        assert_eq!(
+            problem.normalize_problem().solve_max_partial(),
+            vec![Some(0), Some(1)]
+        );
+    }
+
+    #[test]
+    fn test_min_eq_and_max_eq() {
+        let accepts = |constraint: &Constraint, vals: &[Universe]| {
+            (constraint.evaluate)(&mut vals.iter().copied())
+        };
+        let [a, b, c] = [0, 1, 2].map(|id| Variable { id });
+
+        // The result sorts first in the scope, so values are (a, b, c)
+        let min = Constraint::min_eq(a, vec![c, b]);
+        assert_eq!(min.scope, vec![a, b, c]);
+        assert!(accepts(&min, &[2, 2, 5]));
+        assert!(accepts(&min, &[3, 3, 3]));
+        assert!(!accepts(&min, &[1, 2, 5]));
+        assert!(!accepts(&min, &[5, 2, 5]));
+
+        // And in the middle here, so values are (a, m, c) with m == max(a, c)
+        let max = Constraint::max_eq(b, vec![a, c]);
+        assert_eq!(max.scope, vec![a, b, c]);
+        assert!(accepts(&max, &[1, 4, 4]));
+        assert!(accepts(&max, &[4, 4, 4]));
+        assert!(!accepts(&max, &[1, 3, 4]));
+        assert!(!accepts(&max, &[1, 5, 4]));
+
+        let single = Constraint::max_eq(c, vec![a]);
+        assert_eq!(single.scope, vec![a, c]);
+        assert!(accepts(&single, &[7, 7]));
+        assert!(!accepts(&single, &[7, 6]));
+
+        let mut problem = RawProblem::new();
+        let x = problem.add_var_range(1, 3);
+        let y = problem.add_var_range(2, 4);
+        let m = problem.add_var_range(0, 9);
+        problem.push_constraint(Constraint::max_eq(m, vec![x, y]));
+        let problem = problem
+            .normalize_problem()
+            .constraint_propagation()
+            .unwrap();
+        assert_eq!(problem.solve_backtracking(), Some(vec![1, 2, 2]));
+    }
+
+    #[test]
+    fn test_lazy_domain_generated_by_search() {
+        use alloc::rc::Rc;
+        use core::cell::Cell;
+
+        let generated = Rc::new(Cell::new(0));
+        let lazy_var = |problem: &mut RawProblem| {
+            let generated = generated.clone();
+            problem.add_var_lazy(move || {
+                generated.set(generated.get() + 1);
+                vec![3, 1, 2, 0]
+            })
+        };
+
+        // Arc consistency doesn't look at the ternary constraint, so only
+        // the search needs the lazy domain
+        let mut problem = RawProblem::new();
+        let x = problem.add_var_range(0, 3);
+        let y = lazy_var(&mut problem);
+        let z = problem.add_var_range(0, 3);
+        problem.add_constraint(
+            vec![x, y, z],
+            Box::new(|vals| {
+                let (x, y, z) = (vals.next(), vals.next(), vals.next());
+                x < y && y < z
+            }),
+        );
+        problem.add_constraint(vec![x, z], Box::new(|vals| vals.next() != vals.next()));
+        let problem = problem
+            .normalize_problem()
+            .constraint_propagation()
+            .unwrap();
+        assert_eq!(generated.get(), 0);
+
+        assert_eq!(problem.solve_backtracking(), Some(vec![0, 1, 2]));
+        assert_eq!(generated.get(), 1);
+        assert_eq!(problem.solve_cbj_all().len(), 4);
+        assert_eq!(generated.get(), 1);
+
+        // In a binary constraint propagation generates it right away
+        let mut problem = RawProblem::new();
+        let x = problem.add_var_range(2, 3);
+        let y = lazy_var(&mut problem);
+        problem.add_constraint(vec![x, y], Box::new(|vals| vals.next() < vals.next()));
+        let problem = problem
+            .normalize_problem()
+            .constraint_propagation()
+            .unwrap();
+        assert_eq!(generated.get(), 2);
+        assert_eq!(problem.domains[y.id].values, vec![3]);
+    }
+
+    #[test]
+    fn test_duplicate_constraints_dropped() {
+        let mut problem = RawProblem::new();
+        let vars: Vec<Variable> = (0..3).map(|_| problem.add_var_range(0, 2)).collect();
+        problem.push_constraint(Constraint::all_different(vars.clone()));
+        problem.push_constraint(Constraint::all_different(vars.clone()));
+        // Same kind over another scope, and same scope with another kind
+        problem.push_constraint(Constraint::all_different(vars[..2].to_vec()));
+        problem.push_constraint(Constraint::present_sum(vars.clone(), 3));
+        // Opaque closures can't be told apart
+        for _ in 0..2 {
+            problem.add_constraint(vars.clone(), Box::new(|vals| vals.sum::<Universe>() > 0));
+        }
+        problem.push_constraint(Constraint::member(vars[0], vec![2, 1, 1]));
+        problem.push_constraint(Constraint::member(vars[0], vec![1, 2]));
+
+        let problem = problem.normalize_problem();
+        assert_eq!(problem.constraints[&vars].len(), 4);
+        assert_eq!(problem.constraints[&vars[..2]].len(), 1);
+        assert_eq!(problem.constraints[&vars[..1]].len(), 1);
+
+        let problem = problem.constraint_propagation().unwrap();
+        assert_eq!(problem.solutions().count(), 4);
+    }
+
+    #[test]
+    fn test_unconstrained_variables() {
+        let mut problem = RawProblem::new();
+        let x = problem.add_var_range(0, 3);
+        let y = problem.add_var_range(0, 3);
+        let z = problem.add_var_range(0, 3);
+        problem.add_constraint(vec![x, z], Box::new(|vals| vals.next() < vals.next()));
+        assert_eq!(problem.unconstrained_variables(), vec![y]);
+
+        problem.push_constraint(Constraint::not_member(y, vec![0]));
+        assert!(problem.unconstrained_variables().is_empty());
+    }
+
+    #[test]
+    fn test_vsids_activity() {
+        // The last variable is in every constraint, so in every conflict
+        let problem = || {
+            let mut problem = RawProblem::new();
+            let vars: Vec<Variable> = (0..4).map(|_| problem.add_var_range(0, 2)).collect();
+            for pair in [[0, 1], [0, 2], [1, 2]] {
+                problem.add_constraint(
+                    vec![vars[pair[0]], vars[pair[1]], vars[3]],
+                    Box::new(|vals| vals.sum::<Universe>() == 3),
+                );
+            }
+            problem
+                .normalize_problem()
+                .constraint_propagation()
+                .unwrap()
+        };
+        let last = Variable { id: 3 };
+
+        let problem = problem();
+        let mut activity = Activity::new(4, 0.9);
+        assert_eq!(activity.order()[0], Variable { id: 0 });
+        assert_eq!(
+            problem.solve_cbj_with_activity(&mut activity),
+            Some(vec![1; 4])
+        );
+        assert!((0..3).all(|id| activity.score(Variable { id }) < activity.score(last)));
+        // So the next search branches on it first
+        assert_eq!(activity.order()[0], last);
+
+        let mut activity = Activity::new(4, 0.9);
+        assert_eq!(problem.solve_vsids(&mut activity), Some(vec![1; 4]));
+        assert!((0..3).all(|id| activity.score(Variable { id }) < activity.score(last)));
+        assert_eq!(activity.order()[0], last);
+    }
+
+    #[test]
+    fn test_degenerate_problems() {
+        let solve_all = |problem: RawProblem| {
+            let problem = problem
+                .normalize_problem()
+                .constraint_propagation()
+                .unwrap();
+            let solution = problem.solve_backtracking();
+            // CBJ tries the largest values first
+            let cbj = problem.solve_cbj().unwrap();
+            assert!(problem.verify(&cbj));
+            assert_eq!(problem.solve_backtracking_iterative(), solution);
+            assert_eq!(problem.solve_brute_force(), Ok(solution.clone()));
+            assert_eq!(
+                problem.solve_with_options(&SolverOptions::default()),
+                solution
+            );
+            let mut activity = Activity::new(problem.variables.len(), 0.95);
+            assert_eq!(problem.solve_vsids(&mut activity), solution);
+            solution
+        };
+
+        assert_eq!(solve_all(RawProblem::new()), Some(vec![]));
+
+        let mut problem = RawProblem::new();
+        problem.add_var(vec![3, 1, 2]);
+        problem.add_var_range(-2, 5);
+        assert_eq!(solve_all(problem), Some(vec![1, -2]));
+
+        let mut problem = RawProblem::new();
+        problem.add_var(vec![7]);
+        assert_eq!(solve_all(problem), Some(vec![7]));
+    }
+
+    #[test]
+    fn test_constraint_tightness() {
+        let [x, y] = [0, 1].map(|id| Variable { id });
+        let domain = |of| Domain {
+            of,
+            values: vec![1, 2, 3, 4],
+        };
+        let not_equal = Constraint::new(vec![x, y], Box::new(|vals| vals.next() != vals.next()));
+        assert_eq!(not_equal.tightness(&domain(x), &domain(y)), 1.0 / 4.0);
+
+        let less = Constraint::new(vec![x, y], Box::new(|vals| vals.next() < vals.next()));
+        assert_eq!(less.tightness(&domain(x), &domain(y)), 10.0 / 16.0);
+
+        let empty = Domain {
+            of: y,
+            values: vec![],
+        };
+        assert_eq!(less.tightness(&domain(x), &empty), 0.0);
+    }
+
+    #[test]
+    fn test_all_different_ignoring() {
+        let scope: Vec<Variable> = (0..4).map(|id| Variable { id }).collect();
+        let constraint = Constraint::all_different_ignoring(scope, 0);
+        let accepts = |vals: [Universe; 4]| (constraint.evaluate)(&mut vals.into_iter());
+
+        assert!(accepts([0, 0, 0, 0]));
+        assert!(accepts([1, 0, 2, 0]));
+        assert!(accepts([3, 1, 2, 0]));
+        assert!(!accepts([1, 0, 1, 0]));
+        assert!(!accepts([2, 2, 0, 3]));
+
+        let partial = constraint.partial.as_ref().unwrap();
+        assert!(partial(&mut [Some(0), None, Some(0), Some(1)].into_iter()));
+        assert!(!partial(&mut [Some(1), None, Some(1), None].into_iter()));
+    }
+
+    #[test]
+    fn test_custom_value_order() {
+        let mut problem = RawProblem::new();
+        let x = problem.add_var_range(0, 3);
+        let y = problem.add_var_range(0, 3);
+        problem.add_constraint(vec![x, y], Box::new(|vals| vals.next() < vals.next()));
+        let problem = problem
+            .normalize_problem()
+            .constraint_propagation()
+            .unwrap();
+        assert_eq!(problem.domains[x.id].values, vec![0, 1, 2]);
+
+        let ascending = problem.solve_backtracking_ordered(&ValueOrder::ascending());
+        assert_eq!(ascending, problem.solve_backtracking());
+        assert_eq!(ascending, Some(vec![0, 1]));
+
+        let descending = ValueOrder::by_key(&problem, |_, value| core::cmp::Reverse(value));
+        assert_eq!(
+            problem.solve_backtracking_ordered(&descending),
+            Some(vec![2, 3])
+        );
+        // Storage is untouched, so binary search still finds the values
+        assert_eq!(problem.domains[y.id].values, vec![1, 2, 3]);
+
+        // Orders can differ per variable and needn't be monotonic
+        let mut order = ValueOrder::ascending();
+        order.set(&problem, x, &[1, 2, 0]);
+        order.set(&problem, y, &[3, 1, 2]);
+        assert_eq!(problem.solve_backtracking_ordered(&order), Some(vec![1, 3]));
+
+        // x = 0 leaves y the most values
+        let lcv = ValueOrder::least_constraining(&problem);
+        assert_eq!(problem.solve_backtracking_ordered(&lcv), Some(vec![0, 3]));
+    }
+
+    #[test]
+    fn test_not_equal_clique_upgraded() {
+        let triangle = |not_equal: &dyn Fn(Variable, Variable) -> Constraint| {
+            let mut problem = RawProblem::new();
+            let vars: Vec<Variable> = (0..3).map(|_| problem.add_var(vec![1, 2])).collect();
+            for (a, b) in [(0, 1), (1, 2), (0, 2)] {
+                problem.push_constraint(not_equal(vars[a], vars[b]));
+            }
+            problem.normalize_problem()
+        };
+
+        // Every value has a support in every pair, so arc consistency alone
+        // keeps all of them
+        let opaque = triangle(&|a, b| {
+            Constraint::new(vec![a, b], Box::new(|vals| vals.next() != vals.next()))
+        });
+        assert!(opaque.propagators.is_empty());
+        assert!(opaque.constraint_propagation().is_some());
+
+        let recognized = triangle(&Constraint::not_equal);
+        assert_eq!(recognized.propagators.len(), 1);
+        assert!(recognized.constraint_propagation().is_none());
+    }
+
+    #[test]
+    fn test_all_different_propagator() {
+        // Two variables share {1, 2}, so the third can't use them
+        let mut problem = RawProblem::new();
+        let x = problem.add_var(vec![1, 2]);
+        let y = problem.add_var(vec![1, 2]);
+        let z = problem.add_var(vec![1, 2, 3]);
+        let w = problem.add_var_range(1, 4);
+        for (a, b) in [(x, y), (y, z), (x, z)] {
+            problem.push_constraint(Constraint::not_equal(a, b));
+        }
+        // Only a path, not a clique
+        problem.push_constraint(Constraint::not_equal(z, w));
+        let problem = problem.normalize_problem();
+        assert_eq!(problem.propagators.len(), 1);
+
+        let problem = problem.constraint_propagation().unwrap();
+        assert_eq!(problem.domains[z.id].values, vec![3]);
+        assert_eq!(problem.domains[w.id].values, vec![1, 2, 4]);
+    }
+
+    #[test]
+    fn test_solve_map() {
+        let mut problem = RawProblem::new();
+        let x = problem.add_var_range(1, 3);
+        let y = problem.add_var_range(1, 3);
+        let z = problem.add_var(vec![2, 5]);
+        problem.add_constraint(
+            vec![x, y],
+            Box::new(|vals| vals.next().unwrap() > vals.next().unwrap()),
+        );
+        let problem = problem
+            .normalize_problem()
+            .constraint_propagation()
+            .unwrap();
+
+        let solution = problem.solve_map().unwrap();
+        assert_eq!(solution.len(), 3);
+        assert_eq!(solution[&x], 2);
+        assert_eq!(solution[&y], 1);
+        assert_eq!(solution[&z], 2);
+    }
+
+    #[test]
+    fn test_arc_consistency_stops_at_empty_domain() {
+        use alloc::rc::Rc;
+        use core::cell::Cell;
+
+        let checks = Rc::new(Cell::new(0));
+        let counter = checks.clone();
+
+        let mut problem = RawProblem::new();
+        let x = problem.add_var_range(1, 3);
+        let y = problem.add_var_range(1, 3);
+        let z = problem.add_var_range(1, 3);
+        problem.add_constraint(
+            vec![x, y],
+            Box::new(move |vals| {
+                counter.set(counter.get() + 1);
+                vals.next().unwrap() > vals.next().unwrap() + 5
+            }),
+        );
+        problem.add_constraint(vec![y, z], Box::new(|vals| vals.next() != vals.next()));
+
+        assert!(problem
+            .normalize_problem()
+            .constraint_propagation()
+            .is_none());
+        // Revising (x, y) empties x after checking its 3 values against the 3
+        // of y, then nothing else gets revised
+        assert_eq!(checks.get(), 9);
+    }
+
+    #[test]
+    fn test_named_constraint_ignores_ids() {
+        let solve = |reversed: bool| {
+            let mut problem = RawProblem::new();
+            let first = problem.add_var_range(1, 3);
+            let second = problem.add_var_range(1, 3);
+            let (small, big) = if reversed {
+                (second, first)
+            } else {
+                (first, second)
+            };
+            problem.add_constraint_named(&[("small", small), ("big", big)], |v| {
+                v["small"] + 1 < v["big"]
+            });
+            let solution = problem
+                .normalize_problem()
+                .constraint_propagation()
+                .unwrap()
+                .solve_backtracking()
+                .unwrap();
+            (solution[small.id], solution[big.id])
+        };
+
+        assert_eq!(solve(false), (1, 3));
+        assert_eq!(solve(true), (1, 3));
+    }
+
+    #[test]
+    fn test_neighborhood_inverse_consistency() {
+        // y and z take 1 and 2 between them, so x can only be 3, which arc
+        // consistency alone can't see
+        let mut problem = RawProblem::new();
+        let x = problem.add_var_range(1, 3);
+        let y = problem.add_var_range(1, 2);
+        let z = problem.add_var_range(1, 2);
+        for (a, b) in [(x, y), (x, z), (y, z)] {
+            problem.add_constraint(vec![a, b], Box::new(|vals| vals.next() != vals.next()));
+        }
+
+        let problem = problem
+            .normalize_problem()
+            .constraint_propagation()
+            .unwrap();
+        assert_eq!(problem.domains[x.id].values, vec![1, 2, 3]);
+
+        let problem = problem.make_neighborhood_inverse_consistency().unwrap();
+        assert_eq!(problem.domains[x.id].values, vec![3]);
+        assert_eq!(problem.domains[y.id].values, vec![1, 2]);
+        assert_eq!(problem.domains[z.id].values, vec![1, 2]);
+    }
+
+    #[test]
+    fn test_weighted_random_variable_order() {
+        let vars = [Variable { id: 0 }, Variable { id: 1 }];
+        let domains = vec![
+            Domain {
+                of: vars[0],
+                values: vec![1],
+            },
+            Domain::range(vars[1], 1, 4),
+        ];
+        let draws = |seed| {
+            let mut rng = random::Rng::new(seed);
+            (0..10_000)
+                .map(|_| solve::pick_weighted(&mut rng, &vars, &domains).id)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(draws(7), draws(7));
+        // Weights 1 and 1/4, so the smaller domain is picked 80% of the time
+        let small = draws(7).iter().filter(|&&id| id == 0).count();
+        assert!((7_500..8_500).contains(&small), "{small}");
+
+        let mut problem = RawProblem::new();
+        let vars: Vec<Variable> = (0..6).map(|_| problem.add_var_range(1, 6)).collect();
+        problem.push_constraint(Constraint::all_different(vars.clone()));
+        problem.add_constraint(vec![vars[4]], Box::new(|vals| vals.next() == Some(2)));
+        let problem = problem
+            .normalize_problem()
+            .constraint_propagation()
+            .unwrap();
+        for seed in 0..5 {
+            let solution = problem
+                .solve_with_options(&SolverOptions {
+                    variable_order: VariableOrder::WeightedRandom(seed),
+                    ..Default::default()
+                })
+                .unwrap();
+            assert!(problem.verify(&solution));
+        }
+    }
+
+    #[test]
+    fn test_compiled_problem() {
+        let build = || {
+            let mut problem = RawProblem::new();
+            let x = problem.add_var_range(1, 5);
+            let y = problem.add_var(vec![-100, 3, 100]);
+            let z = problem.add_var_range(1, 5);
+            problem.add_constraint(
+                vec![x, y],
+                Box::new(|vals| vals.next().unwrap() < vals.next().unwrap()),
+            );
+            problem.push_constraint(Constraint::present_sum(vec![x, y, z], 8));
+            problem
+                .normalize_problem()
+                .constraint_propagation()
+                .unwrap()
+        };
+
+        let expected = build().solve_backtracking();
+        let compiled = build().compile();
+        assert_eq!(compiled.solve(), expected);
+        assert_eq!(compiled.solve(), Some(vec![1, 3, 4]));
+
+        // Warm starts only change which solution is found first
+        let solution = compiled.solve_from(&[Some(2), None, Some(3)]).unwrap();
+        assert_eq!(solution, vec![2, 3, 3]);
+        assert!(build().verify(&solution));
+        // Hints outside the domain are ignored
+        assert_eq!(compiled.solve_from(&[None, Some(7), None]), expected);
+    }
+
+    #[test]
+    fn test_sum_var() {
+        let mut problem = RawProblem::new();
+        let x = problem.add_var(vec![1, 2, 10]);
+        let y = problem.add_var(vec![0, 1]);
+        let sum = problem.add_sum_var(y, x);
+        assert_eq!(problem.domains[sum.id].values, vec![1, 2, 3, 10, 11]);
+        let double = problem.add_sum_var(y, y);
+        assert_eq!(problem.domains[double.id].values, vec![0, 2]);
+
+        problem.add_constraint(vec![sum], Box::new(|vals| vals.next() == Some(11)));
+        let problem = problem
+            .normalize_problem()
+            .constraint_propagation()
+            .unwrap();
+        let solution = problem.solve_backtracking().unwrap();
+        assert_eq!(solution, vec![10, 1, 11, 2]);
+        assert_eq!(problem.solutions().count(), 1);
+    }
+
+    #[test]
+    fn test_sum_var_overflow() {
+        let mut problem = RawProblem::new();
+        let x = problem.add_var(vec![Universe::MAX - 1, Universe::MAX]);
+        let y = problem.add_var(vec![0, 1, 2]);
+        let sum = problem.add_sum_var(x, y);
+        assert_eq!(
+            problem.domains[sum.id].values,
+            vec![Universe::MAX - 1, Universe::MAX]
+        );
+        let double = problem.add_sum_var(x, x);
+        assert!(problem.domains[double.id].values.is_empty());
+
+        let mut problem = RawProblem::new();
+        let x = problem.add_var(vec![Universe::MAX - 1, Universe::MAX]);
+        let y = problem.add_var(vec![0, 1, 2]);
+        problem.add_sum_var(x, y);
+        problem.fix_var(x, Universe::MAX);
+        let problem = problem
+            .normalize_problem()
+            .constraint_propagation()
+            .unwrap();
+        assert_eq!(problem.domains[y.id].values, vec![0]);
+        let solutions: Vec<Vec<Universe>> = problem.solutions().collect();
+        assert_eq!(solutions, vec![vec![Universe::MAX, 0, Universe::MAX]]);
+        assert_eq!(problem.solve_brute_force_all(), Ok(solutions));
+    }
+
+    #[test]
+    fn test_bounded_problem_binary_search() {
+        let build = || {
+            let mut problem = RawProblem::new();
+            let x = problem.add_var_range(1, 9);
+            let y = problem.add_var_range(1, 9);
+            let total = problem.add_sum_var(x, y);
+            problem.add_constraint(
+                vec![x, y],
+                Box::new(|vals| vals.next().unwrap() * vals.next().unwrap() >= 12),
+            );
+            let problem = problem
+                .normalize_problem()
+                .constraint_propagation()
+                .unwrap();
+            (problem, total)
+        };
+
+        let (problem, total) = build();
+        let mut bounded = BoundedProblem::new(problem);
+        let (mut lo, mut hi) = (2, 18);
+        while lo < hi {
+            let mid = (lo + hi) / 2;
+            let found = bounded.tighten(total, mid) && bounded.solve().is_some();
+            bounded.relax();
+            if found {
+                hi = mid;
+            } else {
+                lo = mid + 1;
+            }
+        }
+
+        let (problem, total) = build();
+        let (optimum, _) = problem.solve_optimize(total, Sense::Minimize).unwrap();
+        assert_eq!(lo, optimum);
+        assert_eq!(optimum, 7);
+
+        // Bounds stack up and come off in reverse
+        assert!(bounded.tighten(total, 10));
+        // Only the search finds this one infeasible, arc consistency doesn't
+        // reach through the sum
+        assert!(bounded.tighten(total, 6));
+        assert_eq!(bounded.solve(), None);
+        assert!(!bounded.tighten(total, 1));
+        assert_eq!(bounded.solve(), None);
+        bounded.relax();
+        bounded.relax();
+        let solution = bounded.solve().unwrap();
+        assert!(solution[total.id] <= 10);
+        bounded.relax();
+        bounded.relax();
+        assert_eq!(bounded.problem().domains[total.id].values.len(), 17);
+    }
+
+    #[test]
+    fn test_circuit() {
+        let mut problem = RawProblem::new();
+        let next: Vec<Variable> = (0..4).map(|_| problem.add_var_range(0, 3)).collect();
+        let circuit = Constraint::circuit(next);
+
+        assert!(Constraint::all_satisfied(
+            core::slice::from_ref(&circuit),
+            &[1, 2, 3, 0]
+        ));
+        assert!(Constraint::all_satisfied(
+            core::slice::from_ref(&circuit),
+            &[3, 0, 1, 2]
+        ));
+        // Two cycles of two nodes
+        assert!(!Constraint::all_satisfied(
+            core::slice::from_ref(&circuit),
+            &[1, 0, 3, 2]
+        ));
+        assert!(!Constraint::all_partially_satisfied(
+            core::slice::from_ref(&circuit),
+            &[Some(1), Some(0), None, None]
+        ));
+        assert!(Constraint::all_partially_satisfied(
+            core::slice::from_ref(&circuit),
+            &[Some(1), Some(2), None, None]
+        ));
+
+        problem.push_constraint(circuit);
+        let problem = problem
+            .normalize_problem()
+            .constraint_propagation()
+            .unwrap();
+        // Every ordering of the 3 nodes after node 0
+        assert_eq!(problem.solutions().count(), 6);
+
+        let mut problem = RawProblem::new();
+        let next: Vec<Variable> = (0..4).map(|_| problem.add_var_range(0, 3)).collect();
+        // Nodes listed out of id order
+        let mut shuffled = next.clone();
+        shuffled.swap(0, 2);
+        problem.push_constraint(Constraint::circuit(shuffled));
+        let problem = problem
+            .normalize_problem()
+            .constraint_propagation()
+            .unwrap();
+        // Node 0's successor is the value of the third variable and the other
+        // way around, so this is 0 -> 1 -> 2 -> 3 -> 0
+        assert!(problem.verify(&[3, 2, 1, 0]));
+        assert!(!problem.verify(&[1, 2, 3, 0]));
+    }
+
+    #[test]
+    fn test_circuit_propagator() {
+        let build = |fixed: &[(usize, Universe)]| {
+            let mut problem = RawProblem::new();
+            let next: Vec<Variable> = (0..4).map(|_| problem.add_var_range(0, 3)).collect();
+            for &(node, succ) in fixed {
+                problem.fix_var(next[node], succ);
+            }
+            problem.push_constraint(Constraint::circuit(next));
+            let problem = problem.normalize_problem();
+            assert_eq!(problem.propagators.len(), 1);
+            problem.constraint_propagation()
+        };
+
+        // 0 -> 1 -> 2 can't go back to 0 with 3 left out, and no node
+        // follows itself
+        let problem = build(&[(0, 1), (1, 2)]).unwrap();
+        assert_eq!(problem.domains[2].values, [1, 3]);
+        assert_eq!(problem.domains[3].values, [0, 1, 2]);
+        assert_eq!(problem.solutions().collect::<Vec<_>>(), [vec![1, 2, 3, 0]]);
+
+        assert!(build(&[(0, 1), (1, 0)]).is_none());
+        assert!(build(&[(0, 1), (1, 2), (2, 3)]).is_some());
+        assert!(build(&[(0, 1), (1, 2), (2, 3), (3, 0)]).is_some());
+
+        // With the nodes out of id order the propagator has to follow the
+        // constraint's order, or it prunes tours
+        let mut problem = RawProblem::new();
+        let vars: Vec<Variable> = (0..4).map(|_| problem.add_var_range(0, 3)).collect();
+        let next = vec![vars[2], vars[0], vars[3], vars[1]];
+        problem.fix_var(next[0], 1);
+        problem.push_constraint(Constraint::circuit(next.clone()));
+        let problem = problem
+            .normalize_problem()
+            .constraint_propagation()
+            .unwrap();
+        // Node 1 can't go back to node 0 while 2 and 3 are left
+        assert_eq!(problem.domains[next[1].id].values, [2, 3]);
+        assert_eq!(problem.solutions().count(), 2);
+    }
+
+    #[test]
+    fn test_search_progress() {
+        // Pigeonhole: 4 variables can't all differ with 3 values, and pairwise
+        // constraints don't show it before searching
+        let mut problem = RawProblem::new();
+        let vars: Vec<Variable> = (0..4).map(|_| problem.add_var_range(1, 3)).collect();
+        for (i, &a) in vars.iter().enumerate() {
+            for &b in &vars[i + 1..] {
+                problem.add_constraint(vec![a, b], Box::new(|vals| vals.next() != vals.next()));
+            }
+        }
+        let problem = problem
+            .normalize_problem()
+            .constraint_propagation()
+            .unwrap();
+
+        let mut estimates = Vec::new();
+        let solution = problem.solve_backtracking_with_progress(|p| estimates.push(p));
+        assert_eq!(solution, None);
+        assert!(estimates.len() > 10);
+        assert!(estimates.windows(2).all(|pair| pair[0] <= pair[1]));
+        assert!(estimates[..estimates.len() - 1].iter().all(|&p| p < 1.0));
+        assert_eq!(estimates.last(), Some(&1.0));
+    }
+
+    #[test]
+    fn test_count_eq() {
+        let x = Variable { id: 0 };
+        let count = [Constraint::count_eq(
+            vec![x, Variable { id: 1 }, Variable { id: 2 }],
+            5,
+            2,
+        )];
+        assert!(Constraint::all_satisfied(&count, &[5, 1, 5]));
+        assert!(!Constraint::all_satisfied(&count, &[5, 5, 5]));
+        assert!(!Constraint::all_satisfied(&count, &[5, 1, 2]));
+        assert!(Constraint::all_partially_satisfied(
+            &count,
+            &[Some(5), None, Some(1)]
+        ));
+        assert!(!Constraint::all_partially_satisfied(
+            &count,
+            &[Some(5), Some(5), Some(5)]
+        ));
+        assert!(!Constraint::all_partially_satisfied(
+            &count,
+            &[Some(1), None, Some(1)]
+        ));
+
+        let forced = |fixed: Universe| {
+            let mut problem = RawProblem::new();
+            let vars: Vec<Variable> = (0..4).map(|_| problem.add_var(vec![1, 5])).collect();
+            problem.fix_var(vars[0], fixed);
+            problem.fix_var(vars[1], fixed);
+            problem.push_constraint(Constraint::count_eq(vars.clone(), 5, 2));
+            let mut problem = problem.normalize_problem();
+            problem.add_propagator(Box::new(Count {
+                scope: vars,
+                value: 5,
+                count: 2,
+            }));
+            let problem = problem.constraint_propagation().unwrap();
+            (2..4)
+                .map(|i| problem.domains[i].values.clone())
+                .collect::<Vec<_>>()
+        };
+        // Two 5s already, so the rest can't be 5
+        assert_eq!(forced(5), vec![vec![1], vec![1]]);
+        // Only two left that can be 5
+        assert_eq!(forced(1), vec![vec![5], vec![5]]);
+    }
+
+    #[test]
+    fn test_solve_with_certificate() {
+        let mut problem = RawProblem::new();
+        let x = problem.add_var_range(1, 3);
+        let y = problem.add_var_range(1, 3);
+        let z = problem.add_var_range(1, 3);
+        problem.push_constraint(Constraint::all_different(vec![x, y, z]));
+        problem.push_constraint(Constraint::not_equal(x, y));
+        problem.add_constraint(
+            vec![y, z],
+            Box::new(|vals| vals.next().unwrap() > vals.next().unwrap()),
+        );
+        let problem = problem
+            .normalize_problem()
+            .constraint_propagation()
+            .unwrap();
+
+        let (solution, certificate) = problem.solve_with_certificate().unwrap();
+        assert_eq!(Some(solution.clone()), problem.solve_backtracking());
+        assert_eq!(certificate.len(), problem.constraints().count());
+        assert!(problem.verify_certificate(&solution, &certificate));
+
+        // Wrong solution, or a constraint left out
+        assert!(!problem.verify_certificate(&[1, 1, 1], &certificate));
+        assert!(!problem.verify_certificate(&solution, &certificate[1..]));
+    }
+
+    #[test]
+    fn test_solve_preferred() {
+        let mut problem = RawProblem::new();
+        let x = problem.add_var_range(1, 3);
+        let y = problem.add_var_range(1, 3);
+        let z = problem.add_var_range(1, 3);
+        problem.push_constraint(Constraint::all_different(vec![x, y, z]));
+        let problem = problem
+            .normalize_problem()
+            .constraint_propagation()
+            .unwrap();
+        assert_eq!(problem.solve_backtracking(), Some(vec![1, 2, 3]));
+
+        // x would rather be 3 and z would rather be 1, y doesn't mind
+        let cost = |var: Variable, value| match (var.id, value) {
+            (0, 3) | (2, 1) => 0,
+            (0, _) => 5,
+            (2, _) => 2,
+            _ => 0,
+        };
+        assert_eq!(problem.solve_preferred(cost), Some((0, vec![3, 2, 1])));
+
+        // Both can't get their way, x weighs more
+        let conflicting = |var: Variable, value| match (var.id, value) {
+            (0, 2) => 0,
+            (0, _) => 5,
+            (2, 2) => 0,
+            (2, _) => 2,
+            _ => 0,
+        };
+        assert_eq!(
+            problem.solve_preferred(conflicting),
+            Some((2, vec![2, 1, 3]))
+        );
+    }
+
+    #[test]
+    fn test_find_redundant() {
+        let less =
+            || -> Evaluation { Box::new(|vals| vals.next().unwrap() < vals.next().unwrap()) };
+        let mut problem = RawProblem::new();
+        let a = problem.add_var_range(1, 4);
+        let b = problem.add_var_range(1, 4);
+        let c = problem.add_var_range(1, 4);
+        problem.add_constraint(vec![a, b], less());
+        problem.add_constraint(vec![b, c], less());
+        // Follows from the other two
+        problem.add_constraint(vec![a, c], less());
+        // Same scope as a < b, but not implied
+        problem.add_constraint(vec![a, b], Box::new(|vals| vals.next() != Some(2)));
+        assert_eq!(
+            problem.normalize_problem().find_redundant(),
+            vec![vec![a, c]]
+        );
+
+        // Only one of two copies can go
+        let mut problem = RawProblem::new();
+        let a = problem.add_var_range(1, 4);
+        let b = problem.add_var_range(1, 4);
+        problem.add_constraint(vec![a, b], less());
+        problem.add_constraint(vec![a, b], less());
+        assert_eq!(
+            problem.normalize_problem().find_redundant(),
+            vec![vec![a, b]]
+        );
+    }
+
+    #[test]
+    fn test_solve_under_assumptions() {
+        let mut problem = RawProblem::new();
+        let x = problem.add_var_range(1, 3);
+        let y = problem.add_var_range(1, 3);
+        let z = problem.add_var_range(1, 3);
+        problem.push_constraint(Constraint::all_different(vec![x, y, z]));
+        problem.add_constraint(
+            vec![x, y],
+            Box::new(|vals| vals.next().unwrap() < vals.next().unwrap()),
+        );
+        let problem = problem
+            .normalize_problem()
+            .constraint_propagation()
+            .unwrap();
+        let before = problem.snapshot();
+
+        assert_eq!(
+            problem.solve_under_assumptions(&[(z, 1, true)]),
+            Some(vec![2, 3, 1])
+        );
+        assert_eq!(
+            problem.solve_under_assumptions(&[(x, 1, false), (z, 1, false)]),
+            None
+        );
+        assert_eq!(
+            problem.solve_under_assumptions(&[(y, 2, false)]),
+            Some(vec![1, 3, 2])
+        );
+        assert_eq!(problem.snapshot(), before);
+        assert_eq!(problem.solve_backtracking(), Some(vec![1, 2, 3]));
+    }
+
+    #[test]
+    fn test_solve_with_intent() {
+        // n variables pairwise different over 3 values
+        let build = |n: usize| {
+            let mut problem = RawProblem::new();
+            let vars: Vec<Variable> = (0..n).map(|_| problem.add_var_range(1, 3)).collect();
+            for (i, &a) in vars.iter().enumerate() {
+                for &b in &vars[i + 1..] {
+                    problem.add_constraint(vec![a, b], Box::new(|vals| vals.next() != vals.next()));
+                }
+            }
+            problem.normalize_problem()
+        };
+
+        for intent in [
+            SolveIntent::FindSolution,
+            SolveIntent::ProveInfeasible,
+            SolveIntent::Balanced,
+        ] {
+            let solution = build(3).solve_with(intent).unwrap();
+            let mut values = solution.clone();
+            values.sort_unstable();
+            assert_eq!(values, vec![1, 2, 3], "{intent:?}");
+            assert_eq!(build(4).solve_with(intent), None, "{intent:?}");
+        }
+    }
+
+    #[test]
+    fn test_solve_with_restarts() {
+        // n pigeons in `holes` holes
+        let build = |n: usize, holes: Universe| {
+            let mut problem = RawProblem::new();
+            let vars: Vec<Variable> = (0..n).map(|_| problem.add_var_range(1, holes)).collect();
+            for (i, &a) in vars.iter().enumerate() {
+                for &b in &vars[i + 1..] {
+                    problem.add_constraint(vec![a, b], Box::new(|vals| vals.next() != vals.next()));
+                }
+            }
+            problem
+                .normalize_problem()
+                .constraint_propagation()
+                .unwrap()
+        };
+
+        // Takes more than the first run to refute
+        let problem = build(6, 5);
+        assert!(problem.solve_backtracking_stats().1.nodes > RESTART_VALUES as u64);
+        assert_eq!(problem.solve_with_restarts(7), None);
+
+        let problem = build(6, 6);
+        for seed in 0..4 {
+            let solution = problem.solve_with_restarts(seed).unwrap();
+            assert!(problem.verify(&solution));
+        }
+    }
+
+    #[test]
+    fn test_nvalue() {
+        let scope: Vec<Variable> = (0..4).map(|id| Variable { id }).collect();
+        let at_most = [Constraint::nvalue_le(scope.clone(), 2)];
+        assert!(Constraint::all_satisfied(&at_most, &[1, 1, 1, 1]));
+        assert!(Constraint::all_satisfied(&at_most, &[1, 2, 1, 2]));
+        assert!(!Constraint::all_satisfied(&at_most, &[1, 2, 3, 2]));
+        assert!(!Constraint::all_partially_satisfied(
+            &at_most,
+            &[Some(1), Some(2), Some(3), None]
+        ));
+        assert!(Constraint::all_partially_satisfied(
+            &at_most,
+            &[Some(1), Some(2), None, None]
+        ));
+
+        let exactly = [Constraint::nvalue_eq(scope, 3)];
+        assert!(Constraint::all_satisfied(&exactly, &[1, 2, 3, 3]));
+        assert!(!Constraint::all_satisfied(&exactly, &[1, 2, 2, 2]));
+        assert!(!Constraint::all_satisfied(&exactly, &[1, 2, 3, 4]));
+        // Only one variable left for two more values
+        assert!(!Constraint::all_partially_satisfied(
+            &exactly,
+            &[Some(1), Some(1), Some(1), None]
+        ));
+        assert!(Constraint::all_partially_satisfied(
+            &exactly,
+            &[Some(1), Some(1), None, None]
+        ));
+    }
+
+    #[test]
+    fn test_slice_constraint() {
+        let less = |vals: &[Universe]| match *vals {
+            [x, y] => x < y,
+            _ => false,
+        };
+
+        let solve = |slice: bool| {
+            let mut problem = RawProblem::new();
+            let x = problem.add_var_range(1, 3);
+            let y = problem.add_var_range(1, 3);
+            if slice {
+                problem.push_constraint(Constraint::new_slice(vec![x, y], less));
+            } else {
+                problem.add_constraint(
+                    vec![x, y],
+                    Box::new(|vals| vals.next().unwrap() < vals.next().unwrap()),
+                );
+            }
+            let problem = problem
+                .normalize_problem()
+                .constraint_propagation()
+                .unwrap();
+            problem.solutions().collect::<Vec<_>>()
+        };
+        assert_eq!(solve(true), solve(false));
+        assert_eq!(solve(true).len(), 3);
+
+        // Written for two variables but given three
+        let scope = vec![Variable { id: 0 }, Variable { id: 1 }, Variable { id: 2 }];
+        let mismatched = [Constraint::new_slice(scope, less)];
+        assert!(!Constraint::all_satisfied(&mismatched, &[1, 2, 3]));
+    }
+
+    #[test]
+    fn test_stateful_propagator() {
+        use alloc::rc::Rc;
+        use core::cell::RefCell;
+
+        /// Counts down from the largest value of `var`, removing one value per
+        /// call, and logs how many calls it has seen
+        struct Countdown {
+            var: Variable,
+            calls: usize,
+            log: Rc<RefCell<Vec<usize>>>,
+        }
+        impl Propagator for Countdown {
+            fn propagate(&mut self, domains: &mut [Domain]) -> PropagationResult {
+                self.calls += 1;
+                self.log.borrow_mut().push(self.calls);
+                let values = &mut domains[self.var.id].values;
+                if values.len() > 1 {
+                    values.pop();
+                    PropagationResult::Changed
+                } else {
+                    PropagationResult::Unchanged
+                }
+            }
+        }
+
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let mut problem = RawProblem::new();
+        let x = problem.add_var_range(1, 4);
+        let mut problem = problem.normalize_problem();
+        problem.add_propagator(Box::new(Countdown {
+            var: x,
+            calls: 0,
+            log: log.clone(),
+        }));
+
+        let problem = problem.constraint_propagation().unwrap();
+        assert_eq!(problem.domains[x.id].values, vec![1]);
+        // 3 calls removing a value and a last one finding nothing to do, each
+        // seeing the count left by the one before
+        assert_eq!(*log.borrow(), vec![1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn test_symmetric_constraint_prunes_both_arcs() {
+        let mut problem = RawProblem::new();
+        let x = problem.add_var_range(0, 3);
+        let y = problem.add_var_range(3, 9);
+        // Given as (y, x) but stored as (x, y)
+        let constraint = Constraint::symmetric(
+            y,
+            x,
+            Box::new(|vals| vals.next().unwrap() + vals.next().unwrap() == 5),
+        );
+        assert_eq!(constraint.scope, vec![x, y]);
+        problem.push_constraint(constraint);
+
+        let problem = problem
+            .normalize_problem()
+            .constraint_propagation()
+            .unwrap();
+        assert_eq!(problem.domains[x.id].values, vec![0, 1, 2]);
+        assert_eq!(problem.domains[y.id].values, vec![3, 4, 5]);
+        assert_eq!(problem.solutions().count(), 3);
+    }
+
+    #[test]
+    #[should_panic(expected = "constraint on Variable { id: 999 }, but the problem only has 2")]
+    fn test_constraint_on_missing_variable() {
+        let mut problem = RawProblem::new();
+        let x = problem.add_var_range(1, 3);
+        problem.add_var_range(1, 3);
+        problem.add_constraint(
+            vec![x, Variable { id: 999 }],
+            Box::new(|vals| vals.next() != vals.next()),
+        );
+    }
+
+    #[test]
+    fn test_try_push_constraint() {
+        let mut problem = RawProblem::new();
+        let x = problem.add_var_range(1, 3);
+        let y = problem.add_var_range(1, 3);
+        let missing = Variable { id: 2 };
+
+        assert_eq!(
+            problem.try_push_constraint(Constraint::not_equal(x, missing)),
+            Err(InvalidScope::UnknownVariable {
+                var: missing,
+                num_vars: 2
+            })
+        );
+        let unsorted = Constraint::new(vec![y, x], Box::new(|vals| vals.next() < vals.next()));
+        assert_eq!(
+            problem.try_push_constraint(unsorted),
+            Err(InvalidScope::Unsorted)
+        );
+        assert_eq!(
+            problem.try_push_constraint(Constraint::not_equal(x, y)),
+            Ok(())
+        );
+
+        let problem = problem
+            .normalize_problem()
+            .constraint_propagation()
+            .unwrap();
+        assert_eq!(problem.solutions().count(), 6);
+    }
+
+    #[test]
+    fn test_traced_value_order() {
+        let mut problem = RawProblem::new();
+        let x = problem.add_var_range(1, 5);
+        let y = problem.add_var_range(1, 5);
+        let z = problem.add_var_range(1, 5);
+        // Ternary, so arc consistency leaves the domains alone
+        problem.add_constraint(vec![x, y, z], Box::new(|vals| vals.sum::<Universe>() == 15));
+        let problem = problem
+            .normalize_problem()
+            .constraint_propagation()
+            .unwrap();
+
+        let mut order = ValueOrder::middle_first(&problem);
+        order.set(&problem, z, &[5, 4, 3, 2, 1]);
+        let mut events = Vec::new();
+        let solution = problem.solve_backtracking_traced(&order, |event| events.push(event));
+        assert_eq!(solution, Some(vec![5, 5, 5]));
+
+        let tried = |of: Variable| -> Vec<Universe> {
+            events
+                .iter()
+                .filter_map(|&event| match event {
+                    SearchEvent::Assign { var, value } if var == of => Some(value),
+                    _ => None,
+                })
+                .collect()
+        };
+        assert_eq!(tried(x), [3, 2, 4, 1, 5]);
+        assert_eq!(tried(y).len(), 25);
+        assert_eq!(tried(y)[..5], [3, 2, 4, 1, 5]);
+        // z gets every value for x and y below 5, and only the first for 5, 5
+        assert_eq!(tried(z)[..5], [5, 4, 3, 2, 1]);
+        assert_eq!(tried(z).len(), 24 * 5 + 1);
+        assert_eq!(
+            events
+                .iter()
+                .filter(|event| matches!(event, SearchEvent::DeadEnd { .. }))
+                .count(),
+            4 + 24 + 24 * 5
+        );
+    }
+
+    #[test]
+    fn test_is_uniquely_solvable() {
+        let mut problem = RawProblem::new();
+        let x = problem.add_var_range(1, 3);
+        let y = problem.add_var_range(1, 3);
+        problem.add_constraint(
+            vec![x, y],
+            Box::new(|vals| vals.next().unwrap() > vals.next().unwrap() + 1),
+        );
+        // Arc consistency leaves x = 3 and y = 1
+        let determined = problem
+            .normalize_problem()
+            .constraint_propagation()
+            .unwrap();
+        assert!(determined.try_solve_by_propagation().is_some());
+        assert!(determined.is_uniquely_solvable());
+
+        let mut problem = RawProblem::new();
+        let x = problem.add_var_range(1, 2);
+        let y = problem.add_var_range(1, 2);
+        problem.push_constraint(Constraint::not_equal(x, y));
+        let two = problem
+            .normalize_problem()
+            .constraint_propagation()
+            .unwrap();
+        assert_eq!(two.solutions().count(), 2);
+        assert!(!two.is_uniquely_solvable());
+    }
+
+    #[test]
+    fn test_optimum_tie_break() {
+        let mut problem = RawProblem::new();
+        let x = problem.add_var_range(0, 2);
+        let y = problem.add_var_range(0, 2);
+        problem.push_constraint(Constraint::not_equal(x, y));
+        let total = problem.add_sum_var(x, y);
+        let problem = problem
+            .normalize_problem()
+            .constraint_propagation()
+            .unwrap();
+
+        // Both (1, 2) and (2, 1) add up to the most
+        let solve = |tie_break| {
+            problem
+                .solve_optimize_tied(total, Sense::Maximize, &tie_break)
+                .unwrap()
+        };
+        assert_eq!(solve(OptimumTieBreak::Lexicographic), (3, vec![1, 2, 3]));
+        assert_eq!(
+            problem.solve_optimize(total, Sense::Maximize),
+            Some((3, vec![1, 2, 3]))
+        );
+
+        let mut descending = ValueOrder::ascending();
+        descending.set(&problem, x, &[2, 1, 0]);
+        assert_eq!(
+            solve(OptimumTieBreak::FirstFound(descending)),
+            (3, vec![2, 1, 3])
+        );
+        let smallest_y = OptimumTieBreak::Secondary {
+            objective: y,
+            sense: Sense::Minimize,
+        };
+        assert_eq!(solve(smallest_y), (3, vec![2, 1, 3]));
+    }
+
+    #[test]
+    fn test_empty_lazy_domain() {
+        let mut problem = RawProblem::new();
+        let vars: Vec<Variable> = (0..2).map(|_| problem.add_var_range(1, 3)).collect();
+        let empty = problem.add_var_lazy(Vec::new);
+        // Only in a ternary constraint, so propagation doesn't generate it
+        problem.add_constraint(
+            vec![vars[0], vars[1], empty],
+            Box::new(|vals| vals.sum::<Universe>() > 0),
+        );
+        let problem = problem
+            .normalize_problem()
+            .constraint_propagation()
+            .unwrap();
+
+        assert_eq!(problem.solve_backtracking(), None);
+        assert_eq!(problem.solve_backtracking_iterative(), None);
+        assert_eq!(problem.solve_cbj(), None);
+        assert!(problem.solve_cbj_all().is_empty());
+        assert_eq!(problem.solutions().next(), None);
+        assert_eq!(problem.solve_brute_force(), Ok(None));
+    }
+
+    #[test]
+    fn test_with_solution() {
+        let mut problem = RawProblem::new();
+        let x = problem.add_var_range(1, 3);
+        let y = problem.add_var_lazy(|| vec![3, 1, 2]);
+        problem.push_constraint(Constraint::not_equal(x, y));
+        let problem = problem
+            .normalize_problem()
+            .constraint_propagation()
+            .unwrap();
+
+        let solution = problem.solve_backtracking().unwrap();
+        let solved = problem.with_solution(&solution);
+        for (domain, &value) in solved.domains.iter().zip(&solution) {
+            assert_eq!(domain.values, [value]);
+        }
+        assert!(solved.lazy_domains.is_empty());
+        assert_eq!(solved.try_solve_by_propagation(), Some(solution));
+    }
+
+    #[test]
+    #[should_panic(expected = "4 is not in the domain of Variable { id: 0 }")]
+    fn test_with_solution_outside_domain() {
+        let mut problem = RawProblem::new();
+        problem.add_var_range(1, 3);
+        let problem = problem
+            .normalize_problem()
+            .constraint_propagation()
+            .unwrap();
+        problem.with_solution(&[4]);
+    }
+
+    #[test]
+    fn test_ac2001_fewer_checks() {
+        use alloc::rc::Rc;
+        use core::cell::Cell;
+
+        // Chain of x0 < x1 < ... where every value has its supports at the
+        // top of the next domain, which keeps shrinking from the top
+        let propagate = |algorithm: ArcAlgorithm| {
+            let checks = Rc::new(Cell::new(0));
+            let mut problem = RawProblem::new();
+            let vars: Vec<Variable> = (0..8).map(|_| problem.add_var_range(0, 20)).collect();
+            problem.fix_var(vars[7], 7);
+            for pair in vars.windows(2) {
+                let counter = checks.clone();
+                problem.add_constraint(
+                    pair.to_vec(),
+                    Box::new(move |vals| {
+                        counter.set(counter.get() + 1);
+                        vals.next() < vals.next()
+                    }),
+                );
+            }
+
+            let mut problem = problem.normalize_problem();
+            problem.arc_order = ArcOrder::Lifo;
+            problem.arc_algorithm = algorithm;
+            let problem = problem.constraint_propagation().unwrap();
+            (problem.domains, checks.get())
+        };
+
+        let (ac3_domains, ac3_checks) = propagate(ArcAlgorithm::Ac3);
+        let (domains, checks) = propagate(ArcAlgorithm::Ac2001);
+        assert_eq!(domains, ac3_domains);
+        assert!(domains
+            .iter()
+            .enumerate()
+            .all(|(i, dom)| dom.values == [i as Universe]));
+        assert!(checks < ac3_checks, "{checks} >= {ac3_checks}");
+    }
+
+    #[test]
+    fn test_extend_domain() {
+        let build = || {
+            let mut problem = RawProblem::new();
+            let x = problem.add_var(vec![2, 1]);
+            let y = problem.add_var_range(1, 2);
+            problem.add_constraint(
+                vec![x, y],
+                Box::new(|vals| vals.next().unwrap() + vals.next().unwrap() == 5),
+            );
+            (problem, x)
+        };
+        let (problem, _) = build();
+        assert!(problem
+            .normalize_problem()
+            .constraint_propagation()
+            .is_none());
+
+        let (mut problem, x) = build();
+        problem.extend_domain(x, &[3, 1]);
+        let problem = problem.normalize_problem();
+        assert_eq!(problem.domains[x.id].values, [1, 2, 3]);
+        let problem = problem.constraint_propagation().unwrap();
+        assert_eq!(problem.solve_backtracking(), Some(vec![3, 2]));
+    }
+
+    #[test]
+    fn test_extend_propagated_domain() {
+        let mut problem = RawProblem::new();
+        let x = problem.add_var_range(1, 2);
+        let y = problem.add_var_range(1, 3);
+        problem.add_constraint(vec![x, y], Box::new(|vals| vals.next() < vals.next()));
+        let mut problem = problem
+            .normalize_problem()
+            .constraint_propagation()
+            .unwrap();
+        assert_eq!(problem.domains[y.id].values, [2, 3]);
+
+        // Nothing in y is above 3, so only 0 stays
+        assert!(problem.extend_domain(x, &[3, 0]));
+        assert_eq!(problem.domains[x.id].values, [0, 1, 2]);
+        assert_eq!(problem.solutions().next(), Some(vec![0, 2]));
+        // y = 1 was removed before x had 0, and doesn't come back
+        assert_eq!(problem.domains[y.id].values, [2, 3]);
+        assert_eq!(problem.solutions().count(), 5);
+    }
+
+    #[test]
+    fn test_arc_consistency_reaches_fixpoint() {
+        // Chain x0 < x1 < ... < x9 over the same range, revised in an order
+        // where every arc gets pruned again by the ones after it
+        let mut problem = RawProblem::new();
+        let vars: Vec<Variable> = (0..10).map(|_| problem.add_var_range(0, 12)).collect();
+        for pair in vars.windows(2) {
+            problem.add_constraint(pair.to_vec(), Box::new(|vals| vals.next() < vals.next()));
+        }
+        let mut problem = problem.normalize_problem();
+        problem.arc_order = ArcOrder::Lifo;
+        let mut problem = problem.constraint_propagation().unwrap();
+        assert!(problem.is_at_fixpoint());
+        assert_eq!(problem.domains[0].values, [0, 1, 2, 3]);
+        assert_eq!(problem.domains[9].values, [9, 10, 11, 12]);
+
+        // Putting back a value without support breaks it
+        problem.domains[9].values.insert(0, 0);
+        assert!(!problem.is_at_fixpoint());
+    }
+
+    #[test]
+    fn test_arc_requeue_filters() {
+        type Requeue =
+            fn((Variable, Variable), (Variable, Variable), &dyn Fn(&[Variable]) -> bool) -> bool;
+        // Same chain as test_arc_consistency_reaches_fixpoint
+        let mut problem = RawProblem::new();
+        let vars: Vec<Variable> = (0..10).map(|_| problem.add_var_range(0, 12)).collect();
+        for pair in vars.windows(2) {
+            problem.add_constraint(pair.to_vec(), Box::new(|vals| vals.next() < vals.next()));
+        }
+        let mut problem = problem
+            .normalize_problem()
+            .constraint_propagation()
+            .unwrap();
+        let arcs: Vec<(Variable, Variable)> = vars
+            .windows(2)
+            .flat_map(|pair| [(pair[0], pair[1]), (pair[1], pair[0])])
+            .collect();
+
+        // Arc consistency from the full domains, counting the arcs requeued
+        let run = |filter: Requeue| {
+            let mut domains: Vec<Domain> =
+                vars.iter().map(|&var| Domain::range(var, 0, 12)).collect();
+            let requeued = core::cell::Cell::new(0);
+            let consistent = propagate::arc_consistency_requeuing(
+                &problem.variables,
+                &mut domains,
+                arcs.clone(),
+                ArcOrder::Lifo,
+                |scope| problem.constraints_on(scope),
+                |arc, revised, constrained| {
+                    let again = filter(arc, revised, constrained);
+                    requeued.set(requeued.get() + again as usize);
+                    again
+                },
+            );
+            assert!(consistent);
+            (domains, requeued.get())
+        };
+        let (fixed, requeued) = run(propagate::requeue);
+        let (old_fixed, old_requeued) = run(propagate::old_requeue);
+        // Only the first half of the old filter, the arcs (z, x) with z < x
+        let (half_fixed, _) =
+            run(|(z, xx), (x, y), constrained| z != y && xx == x && constrained(&[z, x]));
+
+        problem.domains = fixed;
+        assert!(problem.is_at_fixpoint());
+        // The old filter requeued a superset, so it got there too but with
+        // wasted revisions
+        problem.domains = old_fixed;
+        assert!(problem.is_at_fixpoint());
+        assert!(old_requeued > requeued, "{old_requeued} {requeued}");
+        // Without requeuing the arcs from the other side, lower bounds going
+        // up along the chain are never passed on
+        problem.domains = half_fixed;
+        assert!(!problem.is_at_fixpoint());
+    }
+
+    #[test]
+    fn test_satisfied_fraction() {
+        let mut problem = RawProblem::new();
+        let vars: Vec<Variable> = (0..4).map(|_| problem.add_var_range(1, 4)).collect();
+        for (i, &x) in vars.iter().enumerate() {
+            for &y in &vars[i + 1..] {
+                problem.push_constraint(Constraint::not_equal(x, y));
+            }
+        }
+        let problem = problem.normalize_problem();
+
+        assert_eq!(
+            problem.satisfied_fraction(&[Some(1), Some(2), Some(3), Some(4)]),
+            1.0
+        );
+        // Only the constraint between the first two breaks
+        assert_eq!(
+            problem.satisfied_fraction(&[Some(1), Some(1), Some(3), Some(4)]),
+            5.0 / 6.0
+        );
+        // Constraints reaching the unassigned variable aren't counted
+        assert_eq!(
+            problem.satisfied_fraction(&[Some(1), Some(1), None, Some(4)]),
+            2.0 / 3.0
+        );
+        assert_eq!(problem.satisfied_fraction(&[None; 4]), 1.0);
+    }
+
+    #[test]
+    fn test_solve_to_dot() {
+        let mut problem = RawProblem::new();
+        let x = problem.add_var_range(0, 1);
+        let y = problem.add_var_range(0, 1);
+        let z = problem.add_var_range(0, 1);
+        problem.push_constraint(Constraint::not_equal(x, y));
+        problem.push_constraint(Constraint::not_equal(y, z));
+        let problem = problem
+            .normalize_problem()
+            .constraint_propagation()
+            .unwrap();
+
+        // x = 0, then y = 0 fails and y = 1, z = 0 is the solution
+        let dot = problem.solve_to_dot();
+        assert!(dot.starts_with("digraph search {"));
+        assert_eq!(dot.matches(" -> ").count(), 4);
+        assert_eq!(dot.matches("label=\"fail\"").count(), 1);
+        assert!(dot.contains("n1 -> n2 [label=\"x1 = 0\"]"));
+        assert!(dot.contains("n4 [label=\"solution\", color=green]"));
+    }
+
+    #[test]
+    fn test_next_solution_into() {
+        let mut problem = RawProblem::new();
+        let x = problem.add_var_range(1, 3);
+        let y = problem.add_var_range(1, 3);
+        let z = problem.add_var_range(1, 3);
+        problem.push_constraint(Constraint::not_equal(x, y));
+        problem.push_constraint(Constraint::not_equal(y, z));
+        let problem = problem
+            .normalize_problem()
+            .constraint_propagation()
+            .unwrap();
+
+        let expected: Vec<Vec<Universe>> = problem.solutions().collect();
+        assert_eq!(expected.len(), 12);
+
+        let mut solutions = problem.solutions();
+        let mut buf = Vec::new();
+        assert!(solutions.next_solution_into(&mut buf));
+        let allocation = buf.as_ptr();
+        let mut found = vec![buf.clone()];
+        while solutions.next_solution_into(&mut buf) {
+            assert_eq!(buf.as_ptr(), allocation);
+            found.push(buf.clone());
+        }
+        assert_eq!(found, expected);
+        // The last solution stays in the buffer
+        assert_eq!(buf, expected[11]);
+    }
+
+    #[test]
+    fn test_bounds_consistency() {
+        let mut problem = RawProblem::new();
+        let x = problem.add_var_range(1, 5);
+        let y = problem.add_var_range(1, 5);
+        let z = problem.add_var_range(1, 5);
+        problem.push_constraint(Constraint::less_than(x, y));
+        // Declared against the order of the ids
+        problem.push_constraint(Constraint::less_equal(z, x));
+        let problem = problem.normalize_problem().make_bounds_consistency();
+        assert_eq!(problem.domains[x.id].values, [1, 2, 3, 4]);
+        assert_eq!(problem.domains[y.id].values, [2, 3, 4, 5]);
+        assert_eq!(problem.domains[z.id].values, [1, 2, 3, 4]);
+
+        // Arc consistency finds nothing more for these
+        let before = problem.domains.clone();
+        let problem = problem.make_propagators_fixpoint().unwrap();
+        assert_eq!(problem.domains, before);
+
+        let mut problem = RawProblem::new();
+        let x = problem.add_var_range(1, 3);
+        let y = problem.add_var_range(1, 3);
+        problem.push_constraint(Constraint::less_than(x, y));
+        problem.push_constraint(Constraint::less_than(y, x));
+        let problem = problem.normalize_problem().make_bounds_consistency();
+        assert_eq!(problem.domains[y.id].values, []);
+        assert!(problem.constraint_propagation().is_none());
+    }
+
+    #[test]
+    fn test_no_overlap() {
+        // Durations 3, 2 and 4, listed out of the order of the ids
+        let tasks = [Constraint::no_overlap(
+            vec![Variable { id: 2 }, Variable { id: 0 }, Variable { id: 1 }],
+            vec![4, 3, 2],
+        )];
+        assert!(Constraint::all_satisfied(&tasks, &[0, 3, 5]));
+        assert!(Constraint::all_satisfied(&tasks, &[6, 4, 0]));
+        // The first task runs until 3
+        assert!(!Constraint::all_satisfied(&tasks, &[0, 2, 5]));
+        assert!(!Constraint::all_satisfied(&tasks, &[0, 3, 4]));
+        assert!(Constraint::all_partially_satisfied(
+            &tasks,
+            &[Some(0), None, Some(3)]
+        ));
+        assert!(!Constraint::all_partially_satisfied(
+            &tasks,
+            &[None, Some(1), Some(0)]
+        ));
+
+        let mut problem = RawProblem::new();
+        let starts: Vec<Variable> = (0..3).map(|_| problem.add_var_range(0, 6)).collect();
+        problem.fix_var(starts[0], 2);
+        // Listed out of order, which the propagator normalization adds has to
+        // keep matched with the durations
+        problem.push_constraint(Constraint::no_overlap(
+            vec![starts[2], starts[0], starts[1]],
+            vec![4, 3, 2],
+        ));
+        let problem = problem.normalize_problem();
+        assert_eq!(problem.propagators.len(), 1);
+        let problem = problem.constraint_propagation().unwrap();
+        // Nothing fits before the first task but the second one
+        assert_eq!(problem.domains[1].values, [0, 5, 6]);
+        assert_eq!(problem.domains[2].values, [5, 6]);
+        assert_eq!(problem.solutions().count(), 2);
+    }
+
+    #[test]
+    fn test_no_overlap_long_tasks() {
+        let tasks = [Constraint::no_overlap(
+            vec![Variable { id: 0 }, Variable { id: 1 }],
+            vec![u32::MAX, 1],
+        )];
+        // The first task covers everything after it, without wrapping around
+        assert!(!Constraint::all_satisfied(&tasks, &[0, 5]));
+        assert!(!Constraint::all_satisfied(&tasks, &[0, Universe::MAX]));
+        assert!(Constraint::all_satisfied(&tasks, &[0, -1]));
+
+        let mut problem = RawProblem::new();
+        let starts = [
+            problem.add_var(vec![Universe::MAX - 1]),
+            problem.add_var(vec![0, Universe::MAX - 2, Universe::MAX]),
+        ];
+        problem.push_constraint(Constraint::no_overlap(starts.to_vec(), vec![5, 2]));
+        let problem = problem
+            .normalize_problem()
+            .constraint_propagation()
+            .unwrap();
+        assert_eq!(problem.domains[1].values, [0]);
+    }
+
+    #[test]
+    fn test_solve_top_k() {
+        let mut problem = RawProblem::new();
+        let x = problem.add_var_range(1, 4);
+        let y = problem.add_var_range(1, 4);
+        let z = problem.add_var_range(1, 4);
+        problem.push_constraint(Constraint::all_different(vec![x, y, z]));
+        let problem = problem
+            .normalize_problem()
+            .constraint_propagation()
+            .unwrap();
+        // z doesn't count, so solutions differing only there tie
+        let score = |vals: &[Universe]| (vals[0] * 2 - vals[1]) as i64;
+
+        let mut all: Vec<(Vec<Universe>, i64)> = problem
+            .solutions()
+            .map(|solution| {
+                let s = score(&solution);
+                (solution, s)
+            })
+            .collect();
+        // Stable, so ties stay in lexicographic order
+        all.sort_by_key(|&(_, s)| core::cmp::Reverse(s));
+        for k in [0, 1, 5, 24, 30] {
+            assert_eq!(problem.solve_top_k(k, score), all[..k.min(24)], "{k}");
+        }
+        assert_eq!(
+            problem.solve_top_k(2, score),
+            [(vec![4, 1, 2], 7), (vec![4, 1, 3], 7)]
+        );
+    }
+
+    #[test]
+    fn test_solution() {
+        use alloc::string::ToString;
+
+        let mut problem = RawProblem::new();
+        let x = problem.add_var_range(1, 3);
+        let y = problem.add_var_range(1, 3);
+        problem.push_constraint(Constraint::less_than(y, x));
+        let problem = problem
+            .normalize_problem()
+            .constraint_propagation()
+            .unwrap();
+
+        let solution: Solution = problem.solve_backtracking().unwrap().into();
+        assert_eq!(solution.value_of(x), 2);
+        assert_eq!(solution.value_of(y), 1);
+        assert!(solution.satisfies(&problem));
+        assert!(!Solution(vec![1, 2]).satisfies(&problem));
+        assert_eq!(solution.as_map(), problem.solve_map().unwrap());
+        assert_eq!(solution.to_string(), "x0 = 2, x1 = 1");
+        assert_eq!(diff_solutions(&solution, &[3, 1]), [(x, 2, 3)]);
+        assert_eq!(Vec::from(solution), [2, 1]);
+    }
+}
@@ -0,0 +1,178 @@
+//! Local search over complete assignments, which can find solutions of big
+//! problems quickly but never proves there is none
+
+use alloc::{vec, vec::Vec};
+
+use crate::{random::Rng, Constraint, NormalizedProblem, Universe};
+
+/// How the temperature of [`NormalizedProblem::solve_simulated_annealing`]
+/// goes down: it starts at `initial_temperature` and is multiplied by
+/// `cooling` after every move, for `iterations` moves
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AnnealingSchedule {
+    pub initial_temperature: f64,
+    pub cooling: f64,
+    pub iterations: usize,
+}
+impl Default for AnnealingSchedule {
+    fn default() -> Self {
+        AnnealingSchedule {
+            initial_temperature: 2.0,
+            cooling: 0.9999,
+            iterations: 100_000,
+        }
+    }
+}
+
+/// `e^x` for `x <= 0`, since `f64::exp` needs std. Halves `x` until a few
+/// terms of the Taylor series are enough, then squares the result back
+fn exp(x: f64) -> f64 {
+    if x < -50.0 {
+        return 0.0;
+    }
+    let mut halvings = 0;
+    let mut x = x;
+    while x < -0.0625 {
+        x /= 2.0;
+        halvings += 1;
+    }
+    let mut result = 1.0 + x * (1.0 + x / 2.0 * (1.0 + x / 3.0 * (1.0 + x / 4.0)));
+    for _ in 0..halvings {
+        result *= result;
+    }
+    result
+}
+
+impl NormalizedProblem {
+    /// Looks for a solution by changing one variable at a time in a random
+    /// complete assignment, minimizing the number of violated constraints.
+    /// Moves that violate more are still taken with probability
+    /// `e^(-increase / temperature)`, so the search can climb out of local
+    /// minima while the temperature is high. Returns `None` if no solution is
+    /// reached within the iterations of `schedule`, even if there is one.
+    /// Custom propagators are ignored
+    pub fn solve_simulated_annealing(
+        &self,
+        schedule: &AnnealingSchedule,
+        rng: &mut Rng,
+    ) -> Option<Vec<Universe>> {
+        let n = self.variables.len();
+        let domains: Vec<&[Universe]> = (0..n).map(|i| self.values(i)).collect();
+        if domains.iter().any(|values| values.is_empty()) {
+            return None;
+        }
+        let constraints: Vec<&Constraint> = self.constraints.values().flatten().collect();
+        let mut touching = vec![Vec::new(); n];
+        for (i, constraint) in constraints.iter().enumerate() {
+            for var in &constraint.scope {
+                touching[var.id].push(i);
+            }
+        }
+        let violated = |constraint: &Constraint, assignment: &[Universe]| {
+            !(constraint.evaluate)(&mut constraint.scope.iter().map(|var| assignment[var.id]))
+        };
+
+        let mut assignment: Vec<Universe> = domains
+            .iter()
+            .map(|values| values[rng.below(values.len())])
+            .collect();
+        let mut energy = constraints
+            .iter()
+            .filter(|constraint| violated(constraint, &assignment))
+            .count();
+        let movable: Vec<usize> = (0..n).filter(|&i| domains[i].len() > 1).collect();
+        let mut temperature = schedule.initial_temperature;
+
+        for _ in 0..schedule.iterations {
+            if energy == 0 || movable.is_empty() {
+                break;
+            }
+            let var = movable[rng.below(movable.len())];
+            let values = domains[var];
+            let old = assignment[var];
+            // Any value but the current one, the last standing in for it
+            let mut value = values[rng.below(values.len() - 1)];
+            if value == old {
+                value = values[values.len() - 1];
+            }
+
+            let violations = |assignment: &[Universe]| {
+                touching[var]
+                    .iter()
+                    .filter(|&&i| violated(constraints[i], assignment))
+                    .count()
+            };
+            let before = violations(&assignment);
+            assignment[var] = value;
+            let after = violations(&assignment);
+
+            let increase = after as f64 - before as f64;
+            if increase <= 0.0 || rng.chance(exp(-increase / temperature)) {
+                energy = energy + after - before;
+            } else {
+                assignment[var] = old;
+            }
+            temperature *= schedule.cooling;
+        }
+
+        (energy == 0).then_some(assignment)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::{boxed::Box, vec};
+
+    use super::*;
+    use crate::{RawProblem, Variable};
+
+    #[test]
+    fn test_exp() {
+        let expected = [
+            (0.0, 1.0),
+            (-0.3, 0.7408182),
+            (-1.0, 0.3678794),
+            (-2.5, 0.08208500),
+            (-10.0, 0.00004539993),
+        ];
+        for (x, e_x) in expected {
+            assert!((exp(x) / e_x - 1.0).abs() < 1e-5, "{x}");
+        }
+    }
+
+    #[test]
+    fn test_simulated_annealing_queens() {
+        let n = 12;
+        let mut problem = RawProblem::new();
+        let rows: Vec<Variable> = (0..n)
+            .map(|_| problem.add_var_range(0, n as Universe - 1))
+            .collect();
+        for (i, &a) in rows.iter().enumerate() {
+            for (j, &b) in rows.iter().enumerate().skip(i + 1) {
+                let distance = (j - i) as Universe;
+                problem.add_constraint(
+                    vec![a, b],
+                    Box::new(move |vals| {
+                        let (x, y) = (vals.next().unwrap(), vals.next().unwrap());
+                        x != y && (x - y).abs() != distance
+                    }),
+                );
+            }
+        }
+        let problem = problem.normalize_problem();
+
+        // A run may stay stuck until the budget runs out, but not every one
+        let mut solved = 0;
+        for seed in 0..5 {
+            let mut rng = Rng::new(seed);
+            if let Some(solution) =
+                problem.solve_simulated_annealing(&AnnealingSchedule::default(), &mut rng)
+            {
+                let assignment: Vec<Option<Universe>> = solution.into_iter().map(Some).collect();
+                assert_eq!(problem.satisfied_fraction(&assignment), 1.0);
+                solved += 1;
+            }
+        }
+        assert!(solved >= 3, "{solved}");
+    }
+}
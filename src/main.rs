@@ -1,39 +1,112 @@
-use constraint::sudoku::{self, Sudoku};
+use std::{
+    io::{self, Read},
+    process::ExitCode,
+    time::Instant,
+};
 
-fn main() {
-    // let mut sudoku = Sudoku::new();
-    // sudoku.add_num(1, 0, 0);
-    // sudoku.add_num(2, 1, 1);
+use constraint::sudoku::Sudoku;
 
-    let sudoku = Sudoku::from_slice(&[
-        3, 0, 6, 5, 0, 8, 4, 0, 0, 5, 2, 0, 0, 0, 0, 0, 0, 0, 0, 8, 7, 0, 0, 0, 0, 3, 1, 0, 0, 3,
-        0, 1, 0, 0, 8, 0, 9, 0, 0, 8, 6, 3, 0, 0, 5, 0, 5, 0, 0, 9, 0, 6, 0, 0, 1, 3, 0, 0, 0, 0,
-        2, 5, 0, 0, 0, 0, 0, 0, 0, 0, 7, 4, 0, 0, 5, 2, 0, 6, 3, 0, 0,
-    ]);
+const USAGE: &str = "usage: constraint [--solver=cbj|backtracking|mac] [FILE]
 
-    // let sudoku = Sudoku::from_slice(&[
-    //     3, 1, 6, 5, 7, 8, 4, 9, 2, 5, 2, 9, 1, 3, 4, 7, 6, 8, 4, 8, 7, 6, 2, 9, 5, 3, 1, 2, 6, 3,
-    //     0, 1, 5, 9, 8, 7, 9, 7, 4, 8, 6, 0, 1, 2, 5, 8, 5, 1, 7, 9, 2, 6, 4, 3, 1, 3, 8, 0, 4, 7,
-    //     2, 0, 6, 6, 9, 2, 3, 5, 1, 8, 7, 4, 7, 4, 5, 0, 8, 6, 3, 1, 0,
-    // ]);
+Solves the Sudoku in FILE, or read from stdin if there is none. The puzzle is
+81 digits row by row, with 0 or . for empty cells, whitespace is ignored";
 
-    // let sudoku = Sudoku::from_slice(&[1; 81]);
+#[derive(Debug, Clone, Copy)]
+enum Solver {
+    Cbj,
+    Backtracking,
+    /// Maintaining arc consistency after each assignment
+    Mac,
+}
+
+struct Args {
+    solver: Solver,
+    path: Option<String>,
+}
 
-    println!("{}", sudoku);
+fn parse_args(args: impl Iterator<Item = String>) -> Result<Args, String> {
+    let mut parsed = Args {
+        solver: Solver::Cbj,
+        path: None,
+    };
+    for arg in args {
+        if let Some(solver) = arg.strip_prefix("--solver=") {
+            parsed.solver = match solver {
+                "cbj" => Solver::Cbj,
+                "backtracking" => Solver::Backtracking,
+                "mac" => Solver::Mac,
+                _ => return Err(format!("unknown solver {solver:?}")),
+            };
+        } else if arg.starts_with('-') {
+            return Err(format!("unknown option {arg:?}"));
+        } else if parsed.path.replace(arg).is_some() {
+            return Err("more than one file given".to_owned());
+        }
+    }
+    Ok(parsed)
+}
 
-    let problem = sudoku.to_constraint_problem();
-    println!("{:?}", problem);
+fn main() -> ExitCode {
+    let args = match parse_args(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(message) => {
+            eprintln!("{message}\n\n{USAGE}");
+            return ExitCode::from(2);
+        }
+    };
 
-    let problem = problem.normalize_problem();
-    println!("NORMALIZED");
+    let input = match &args.path {
+        Some(path) => std::fs::read_to_string(path),
+        None => {
+            let mut input = String::new();
+            io::stdin().read_to_string(&mut input).map(|_| input)
+        }
+    };
+    let input = match input {
+        Ok(input) => input,
+        Err(err) => {
+            eprintln!("couldn't read the puzzle: {err}");
+            return ExitCode::from(2);
+        }
+    };
+    let sudoku: Sudoku = match input.parse() {
+        Ok(sudoku) => sudoku,
+        Err(err) => {
+            eprintln!("invalid puzzle: {err}");
+            return ExitCode::from(2);
+        }
+    };
 
-    let problem = problem.constraint_propagation().unwrap();
-    println!("CONSTRAINTS");
+    let start = Instant::now();
+    let mut stats = None;
+    let solution = sudoku
+        .to_constraint_problem()
+        .normalize_problem()
+        .constraint_propagation()
+        .and_then(|problem| match args.solver {
+            Solver::Cbj => problem.solve_cbj(),
+            Solver::Backtracking => {
+                let (solution, search) = problem.solve_backtracking_stats();
+                stats = Some(search);
+                solution
+            }
+            Solver::Mac => problem.solve_with_options(&Default::default()),
+        });
+    let elapsed = start.elapsed();
 
-    // let solution = problem.solve_backtracking().unwrap();
-    let solution = problem.solve_cbj().unwrap();
-    println!("SOLVED");
+    let Some(solution) = solution else {
+        eprintln!("the puzzle has no solution");
+        return ExitCode::FAILURE;
+    };
     let solution_board =
         Sudoku::from_slice(&solution.iter().map(|&x| x as u8).collect::<Vec<u8>>());
-    println!("{}", solution_board);
+    print!("{solution_board}");
+    println!();
+    println!("solver: {:?}", args.solver);
+    println!("time: {elapsed:?}");
+    if let Some(stats) = stats {
+        println!("nodes: {}", stats.nodes);
+        println!("backtracks: {}", stats.backtracks);
+    }
+    ExitCode::SUCCESS
 }
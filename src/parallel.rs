@@ -0,0 +1,352 @@
+//! Arc consistency on the connected components of the constraint graph,
+//! spread over as many threads as the machine runs at once. Components share
+//! no variables, so each one reaches its fixpoint without looking at the
+//! others
+
+use alloc::{collections::BTreeMap, vec::Vec};
+use core::num::NonZeroUsize;
+
+use crate::{
+    propagate::ArcConsistency, ArcAlgorithm, ArcOrder, Constraint, Domain, NormalizedProblem,
+    PropagatedProblem, PropagationResult, Propagator, SyncEvaluation, Variable,
+};
+
+/// The closures of the constraints over a scope, each with whether it is
+/// symmetric, to rebuild them on another thread
+type SyncConstraints = Vec<(SyncEvaluation, bool)>;
+
+/// Groups the variables connected through `scopes`, each group sorted by
+/// id and the groups by their first variable. Variables in no scope are left
+/// out
+fn components(num_vars: usize, scopes: &[Vec<Variable>]) -> Vec<Vec<Variable>> {
+    // Union-find with path halving
+    let mut parent: Vec<usize> = (0..num_vars).collect();
+    fn find(parent: &mut [usize], mut i: usize) -> usize {
+        while parent[i] != i {
+            parent[i] = parent[parent[i]];
+            i = parent[i];
+        }
+        i
+    }
+    for scope in scopes {
+        for pair in scope.windows(2) {
+            let a = find(&mut parent, pair[0].id);
+            let b = find(&mut parent, pair[1].id);
+            parent[a] = b;
+        }
+    }
+
+    let mut groups: BTreeMap<usize, Vec<Variable>> = BTreeMap::new();
+    for scope in scopes {
+        for &var in scope {
+            groups
+                .entry(find(&mut parent, var.id))
+                .or_default()
+                .push(var);
+        }
+    }
+    let mut groups: Vec<Vec<Variable>> = groups
+        .into_values()
+        .map(|mut group| {
+            group.sort_unstable();
+            group.dedup();
+            group
+        })
+        .collect();
+    groups.sort_unstable();
+    groups
+}
+
+/// What a thread needs to propagate one component, with the variables
+/// numbered from 0 in the order of `variables`
+struct Component {
+    variables: Vec<Variable>,
+    domains: Vec<Domain>,
+    constraints: Vec<(Vec<Variable>, SyncConstraints)>,
+}
+impl Component {
+    /// The domains of the component at the fixpoint, or `None` if one was
+    /// emptied
+    fn propagate(self, order: ArcOrder, algorithm: ArcAlgorithm) -> Option<Vec<Domain>> {
+        let Component {
+            variables,
+            mut domains,
+            constraints,
+        } = self;
+        let constraints: BTreeMap<Vec<Variable>, Vec<Constraint>> = constraints
+            .into_iter()
+            .map(|(scope, evaluations)| {
+                let constraints = evaluations
+                    .into_iter()
+                    .map(|(evaluate, symmetric)| {
+                        let mut constraint =
+                            Constraint::new_sync(scope.clone(), move |vals| evaluate(vals));
+                        constraint.symmetric = symmetric;
+                        constraint
+                    })
+                    .collect();
+                (scope, constraints)
+            })
+            .collect();
+        let local: Vec<Variable> = (0..variables.len()).map(|id| Variable { id }).collect();
+        let consistent = ArcConsistency {
+            variables: &local,
+            constraints: &constraints,
+            order,
+            algorithm,
+        }
+        .propagate(&mut domains)
+            != PropagationResult::Infeasible;
+        consistent.then_some(domains)
+    }
+}
+
+impl NormalizedProblem {
+    /// The connected components of the binary constraints, those that can
+    /// go to another thread and the variables of those that can't, realizing
+    /// the domains of all of them
+    fn split_components(&mut self) -> (Vec<Component>, Vec<Variable>) {
+        let binary: Vec<Vec<Variable>> = self
+            .constraints
+            .keys()
+            .filter(|scope| scope.len() == 2)
+            .cloned()
+            .collect();
+        self.realize(binary.iter().flatten().copied().collect::<Vec<_>>());
+
+        let mut component_of = BTreeMap::new();
+        let groups = components(self.variables.len(), &binary);
+        for (i, group) in groups.iter().enumerate() {
+            for var in group {
+                component_of.insert(*var, i);
+            }
+        }
+        let mut scopes: Vec<Vec<&Vec<Variable>>> = groups.iter().map(|_| Vec::new()).collect();
+        for scope in &binary {
+            scopes[component_of[&scope[0]]].push(scope);
+        }
+
+        let mut sendable = Vec::new();
+        let mut local = Vec::new();
+        for (group, scopes) in groups.into_iter().zip(scopes) {
+            let position = |var: &Variable| group.binary_search(var).unwrap();
+            let constraints: Option<Vec<_>> = scopes
+                .iter()
+                .map(|&scope| {
+                    let evaluations = self.constraints[scope]
+                        .iter()
+                        .map(|constraint| Some((constraint.sync.clone()?, constraint.symmetric)))
+                        .collect::<Option<Vec<_>>>()?;
+                    let scope = scope.iter().map(|var| Variable { id: position(var) });
+                    Some((scope.collect(), evaluations))
+                })
+                .collect();
+            match constraints {
+                Some(constraints) => sendable.push(Component {
+                    domains: group
+                        .iter()
+                        .enumerate()
+                        .map(|(id, var)| Domain {
+                            of: Variable { id },
+                            values: self.domains[var.id].values.clone(),
+                        })
+                        .collect(),
+                    variables: group,
+                    constraints,
+                }),
+                None => local.extend(group),
+            }
+        }
+        (sendable, local)
+    }
+
+    /// Arc consistency like [`NormalizedProblem::constraint_propagation`]
+    /// runs it, with the connected components of the binary constraints
+    /// spread over [`std::thread::available_parallelism`] threads.
+    /// Components with a constraint that wasn't built with
+    /// [`Constraint::new_sync`] or a built-in binary constructor can't be
+    /// sent to another thread, so they are propagated on this one. Custom
+    /// propagators aren't run
+    pub(crate) fn make_arc_consistency_parallel(mut self) -> Option<Self> {
+        let (sendable, mut local) = self.split_components();
+
+        // Spread the components over as many threads as can run at once,
+        // each propagating its share one after another
+        let workers = std::thread::available_parallelism().map_or(1, NonZeroUsize::get);
+        let mut batches: Vec<Vec<Component>> = (0..workers.min(sendable.len()))
+            .map(|_| Vec::new())
+            .collect();
+        for (i, component) in sendable.into_iter().enumerate() {
+            batches[i % workers].push(component);
+        }
+
+        let order = self.arc_order;
+        let algorithm = self.arc_algorithm;
+        let (locally_consistent, results) = std::thread::scope(|threads| {
+            let handles: Vec<_> = batches
+                .into_iter()
+                .map(|batch| {
+                    threads.spawn(move || {
+                        batch
+                            .into_iter()
+                            .map(|component| {
+                                let variables = component.variables.clone();
+                                (variables, component.propagate(order, algorithm))
+                            })
+                            .collect::<Vec<_>>()
+                    })
+                })
+                .collect();
+            // The rest are independent of the threads' components
+            local.sort_unstable();
+            let mut arc_consistency = ArcConsistency {
+                variables: &local,
+                constraints: &self.constraints,
+                order,
+                algorithm,
+            };
+            let consistent =
+                arc_consistency.propagate(&mut self.domains) != PropagationResult::Infeasible;
+            let results: Vec<_> = handles
+                .into_iter()
+                .flat_map(|handle| handle.join().unwrap())
+                .collect();
+            (consistent, results)
+        });
+
+        if !locally_consistent {
+            return None;
+        }
+        for (variables, domains) in results {
+            for (var, domain) in variables.into_iter().zip(domains?) {
+                self.domains[var.id].values = domain.values;
+            }
+        }
+        Some(self)
+    }
+
+    /// Same as [`NormalizedProblem::constraint_propagation`], with arc
+    /// consistency run on several threads, each taking some of the
+    /// independent parts of the problem.
+    /// Only constraints built with [`Constraint::new_sync`], like those of
+    /// [`crate::RawProblem::add_constraint_sync`], and the built-in binary
+    /// ones like [`Constraint::less_than`] can be checked on other threads
+    pub fn constraint_propagation_parallel(self) -> Option<PropagatedProblem> {
+        let problem = self
+            .make_node_consistency()
+            .make_bounds_consistency()
+            .make_arc_consistency_parallel()?;
+        // Arc consistency is at its fixpoint already, and only has to run
+        // again if the propagators change something
+        if problem.propagators.is_empty() {
+            Some(problem.into_propagated())
+        } else {
+            problem
+                .make_propagators_fixpoint()
+                .map(NormalizedProblem::into_propagated)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::{boxed::Box, vec};
+
+    use super::*;
+    use crate::{RawProblem, Universe};
+
+    #[test]
+    fn test_components() {
+        let var = |id| Variable { id };
+        let scopes = vec![
+            vec![var(0), var(3)],
+            vec![var(3), var(5)],
+            vec![var(1), var(2)],
+        ];
+        assert_eq!(
+            components(6, &scopes),
+            vec![vec![var(0), var(3), var(5)], vec![var(1), var(2)]]
+        );
+    }
+
+    #[test]
+    fn test_parallel_matches_sequential() {
+        // x0 < x1 < x2 and, apart from them, x3 + x4 == 5 with x4 even
+        let build = |sync: bool| {
+            let mut problem = RawProblem::new();
+            let vars: Vec<Variable> = (0..5).map(|_| problem.add_var_range(0, 4)).collect();
+            let less = |vals: &mut dyn Iterator<Item = Universe>| vals.next() < vals.next();
+            let sum = |vals: &mut dyn Iterator<Item = Universe>| vals.sum::<Universe>() == 5;
+            let even = |vals: &mut dyn Iterator<Item = Universe>| vals.next().unwrap() % 2 == 0;
+            problem.add_constraint_sync(vars[0..2].to_vec(), less);
+            problem.add_constraint_sync(vars[1..3].to_vec(), less);
+            problem.add_constraint(vec![vars[4]], Box::new(even));
+            if sync {
+                problem.add_constraint_sync(vars[3..5].to_vec(), sum);
+            } else {
+                // This component stays on the calling thread
+                problem.add_constraint(vars[3..5].to_vec(), Box::new(sum));
+            }
+            problem.normalize_problem()
+        };
+
+        let sequential = build(true).constraint_propagation().unwrap();
+        assert_eq!(sequential.domains[0].values, [0, 1, 2]);
+        assert_eq!(sequential.domains[3].values, [1, 3]);
+        for sync in [true, false] {
+            let parallel = build(sync).constraint_propagation_parallel().unwrap();
+            assert_eq!(parallel.domains, sequential.domains);
+            assert_eq!(parallel.solutions().count(), sequential.solutions().count());
+        }
+
+        // The same with the built-in constructors, which go to threads too
+        let mut problem = RawProblem::new();
+        let vars: Vec<Variable> = (0..5).map(|_| problem.add_var_range(0, 4)).collect();
+        problem.push_constraint(Constraint::less_than(vars[0], vars[1]));
+        problem.push_constraint(Constraint::less_equal(vars[1], vars[2]));
+        problem.push_constraint(Constraint::not_equal(vars[2], vars[1]));
+        let sum = |vals: &mut dyn Iterator<Item = Universe>| vals.sum::<Universe>() == 5;
+        problem.push_constraint(Constraint::symmetric_sync(vars[4], vars[3], sum));
+        let even = |vals: &mut dyn Iterator<Item = Universe>| vals.next().unwrap() % 2 == 0;
+        problem.add_constraint(vec![vars[4]], Box::new(even));
+        let mut problem = problem.normalize_problem();
+        let (sendable, local) = problem.split_components();
+        assert_eq!(sendable.len(), 2);
+        assert!(local.is_empty());
+        let parallel = problem.constraint_propagation_parallel().unwrap();
+        assert_eq!(parallel.domains, sequential.domains);
+    }
+
+    #[test]
+    fn test_parallel_many_components() {
+        // More components than threads, so each thread takes several
+        let build = || {
+            let mut problem = RawProblem::new();
+            let vars: Vec<Variable> = (0..128).map(|_| problem.add_var_range(0, 9)).collect();
+            for (i, pair) in vars.chunks(2).enumerate() {
+                let sum = i as Universe % 10;
+                problem
+                    .add_constraint_sync(pair.to_vec(), move |vals| vals.sum::<Universe>() == sum);
+            }
+            problem.normalize_problem()
+        };
+        let sequential = build().constraint_propagation().unwrap();
+        let parallel = build().constraint_propagation_parallel().unwrap();
+        assert_eq!(parallel.domains, sequential.domains);
+        assert_eq!(parallel.domains[2].values, [0, 1]);
+    }
+
+    #[test]
+    fn test_parallel_infeasible() {
+        let mut problem = RawProblem::new();
+        let vars: Vec<Variable> = (0..4).map(|_| problem.add_var_range(0, 1)).collect();
+        problem.add_constraint_sync(vars[0..2].to_vec(), |vals| vals.next() != vals.next());
+        // x2 < x3 < x2 can't hold
+        problem.add_constraint_sync(vars[2..4].to_vec(), |vals| vals.next() < vals.next());
+        problem.add_constraint_sync(vars[2..4].to_vec(), |vals| vals.next() > vals.next());
+        assert!(problem
+            .normalize_problem()
+            .constraint_propagation_parallel()
+            .is_none());
+    }
+}
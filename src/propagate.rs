@@ -0,0 +1,1281 @@
+//! Filtering the domains before search: node and arc consistency, custom
+//! propagators and singleton arc consistency
+
+use alloc::{
+    boxed::Box,
+    collections::{BTreeMap, BinaryHeap},
+    vec,
+    vec::Vec,
+};
+use core::cmp::{Ordering, Reverse};
+use hashbrown::HashMap;
+
+use crate::{
+    Constraint, ConstraintKind, Domain, NormalizedProblem, PropagatedProblem, Universe, Variable,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PropagationResult {
+    Unchanged,
+    Changed,
+    Infeasible,
+}
+
+/// Filters values out of the domains. Propagators are run together with the
+/// built in arc consistency until none of them changes anything
+pub trait Propagator {
+    /// Takes `&mut self` so propagators can keep state between calls, like
+    /// what they found last time to only recompute what changed since
+    fn propagate(&mut self, domains: &mut [Domain]) -> PropagationResult;
+}
+
+/// Propagator for [`Constraint::exactly_one`] and [`Constraint::at_most_one`]
+/// over {0, 1} variables: once one variable is set to 1 the rest are forced
+/// to 0, and for exactly one, once all but one are 0 the last is forced to 1
+pub struct OneHot {
+    pub scope: Vec<Variable>,
+    pub exactly: bool,
+}
+impl OneHot {
+    pub fn exactly_one(scope: Vec<Variable>) -> Self {
+        Self {
+            scope,
+            exactly: true,
+        }
+    }
+    pub fn at_most_one(scope: Vec<Variable>) -> Self {
+        Self {
+            scope,
+            exactly: false,
+        }
+    }
+}
+impl Propagator for OneHot {
+    fn propagate(&mut self, domains: &mut [Domain]) -> PropagationResult {
+        let is_one = |domains: &[Domain], var: &Variable| domains[var.id].values == [1];
+        let can_be_one = |domains: &[Domain], var: &Variable| domains[var.id].values.contains(&1);
+
+        let mut result = PropagationResult::Unchanged;
+        match self.scope.iter().filter(|var| is_one(domains, var)).count() {
+            0 => {
+                let mut candidates = self.scope.iter().filter(|var| can_be_one(domains, var));
+                match (candidates.next(), candidates.next()) {
+                    (None, _) if self.exactly => return PropagationResult::Infeasible,
+                    (Some(&last), None) if self.exactly => {
+                        let values = &mut domains[last.id].values;
+                        if values.len() > 1 {
+                            values.retain(|&v| v == 1);
+                            result = PropagationResult::Changed;
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            1 => {
+                for var in &self.scope {
+                    if !is_one(domains, var) && can_be_one(domains, var) {
+                        domains[var.id].values.retain(|&v| v != 1);
+                        result = PropagationResult::Changed;
+                    }
+                }
+            }
+            _ => return PropagationResult::Infeasible,
+        }
+        result
+    }
+}
+
+/// Propagator for [`Constraint::count_eq`]: once `count` variables are
+/// fixed to `value` the rest can't take it, and once only `count` can take it
+/// they all must
+pub struct Count {
+    pub scope: Vec<Variable>,
+    pub value: Universe,
+    pub count: usize,
+}
+impl Propagator for Count {
+    fn propagate(&mut self, domains: &mut [Domain]) -> PropagationResult {
+        let is_value = |domains: &[Domain], var: &Variable| domains[var.id].values == [self.value];
+        let can_be_value =
+            |domains: &[Domain], var: &Variable| domains[var.id].values.contains(&self.value);
+
+        let fixed = self
+            .scope
+            .iter()
+            .filter(|var| is_value(domains, var))
+            .count();
+        let possible = self
+            .scope
+            .iter()
+            .filter(|var| can_be_value(domains, var))
+            .count();
+        if fixed > self.count || possible < self.count {
+            return PropagationResult::Infeasible;
+        }
+
+        let mut result = PropagationResult::Unchanged;
+        for var in &self.scope {
+            if is_value(domains, var) || !can_be_value(domains, var) {
+                continue;
+            }
+            let values = &mut domains[var.id].values;
+            if fixed == self.count {
+                values.retain(|&v| v != self.value);
+                result = PropagationResult::Changed;
+            } else if possible == self.count {
+                values.retain(|&v| v == self.value);
+                result = PropagationResult::Changed;
+            }
+        }
+        result
+    }
+}
+
+/// Propagator for [`Constraint::no_overlap`]: once the start of a task is
+/// fixed, the other tasks can't start anywhere they would overlap it
+pub struct NoOverlap {
+    pub starts: Vec<Variable>,
+    pub durations: Vec<u32>,
+}
+impl NoOverlap {
+    /// Panics if there isn't one duration per start
+    pub fn new(starts: Vec<Variable>, durations: Vec<u32>) -> Self {
+        assert_eq!(starts.len(), durations.len());
+        NoOverlap { starts, durations }
+    }
+}
+impl Propagator for NoOverlap {
+    fn propagate(&mut self, domains: &mut [Domain]) -> PropagationResult {
+        let mut result = PropagationResult::Unchanged;
+        for (i, &fixed) in self.starts.iter().enumerate() {
+            let &[start] = domains[fixed.id].values.as_slice() else {
+                continue;
+            };
+            // Ends can go past Universe::MAX, so everything is in i64
+            let start = i64::from(start);
+            let end = start + i64::from(self.durations[i]);
+            for (j, &other) in self.starts.iter().enumerate().filter(|&(j, _)| j != i) {
+                let duration = i64::from(self.durations[j]);
+                let values = &mut domains[other.id].values;
+                let before = values.len();
+                values.retain(|&v| i64::from(v) + duration <= start || end <= i64::from(v));
+                if values.is_empty() {
+                    return PropagationResult::Infeasible;
+                }
+                if values.len() != before {
+                    result = PropagationResult::Changed;
+                }
+            }
+        }
+        result
+    }
+}
+
+/// Propagator for [`Constraint::circuit`]: no node is its own successor, and
+/// the end of each chain of fixed successors can't go back to the start of
+/// the chain unless it covers every node
+pub struct Circuit {
+    pub next: Vec<Variable>,
+}
+impl Propagator for Circuit {
+    fn propagate(&mut self, domains: &mut [Domain]) -> PropagationResult {
+        let n = self.next.len();
+        let mut result = PropagationResult::Unchanged;
+        let mut remove = |domains: &mut [Domain], node: usize, succ: usize| {
+            let values = &mut domains[self.next[node].id].values;
+            let before = values.len();
+            values.retain(|&v| v != succ as Universe);
+            if values.len() != before {
+                result = PropagationResult::Changed;
+            }
+            !values.is_empty()
+        };
+        if !(0..n).all(|node| remove(domains, node, node)) {
+            return PropagationResult::Infeasible;
+        }
+
+        let fixed = |domains: &[Domain], node: usize| match domains[self.next[node].id].values[..] {
+            [succ] => usize::try_from(succ).ok().filter(|&succ| succ < n),
+            _ => None,
+        };
+        let mut has_predecessor = vec![false; n];
+        for node in 0..n {
+            if let Some(succ) = fixed(domains, node) {
+                has_predecessor[succ] = true;
+            }
+        }
+        if has_predecessor.iter().all(|&has| has) {
+            // Every successor is fixed, so they must make a single cycle
+            let mut node = 0;
+            for length in 1..=n {
+                node = fixed(domains, node).unwrap();
+                if node == 0 {
+                    return if length == n {
+                        PropagationResult::Unchanged
+                    } else {
+                        PropagationResult::Infeasible
+                    };
+                }
+            }
+            return PropagationResult::Infeasible;
+        }
+
+        let mut visited = 0;
+        for start in (0..n).filter(|&node| !has_predecessor[node]) {
+            let mut end = start;
+            let mut length = 1;
+            while let Some(succ) = fixed(domains, end) {
+                end = succ;
+                length += 1;
+                if length > n {
+                    return PropagationResult::Infeasible;
+                }
+            }
+            visited += length;
+            if length < n && !remove(domains, end, start) {
+                return PropagationResult::Infeasible;
+            }
+        }
+        // Nodes on a cycle of fixed successors that leaves others out aren't
+        // in any chain, and nodes in two chains have two predecessors
+        if visited != n {
+            return PropagationResult::Infeasible;
+        }
+        result
+    }
+}
+
+/// Propagator for all the variables of `scope` having different values, as
+/// strong as it gets: keeps exactly the values that are part of some way of
+/// giving every variable its own value (a matching of variables to values).
+/// This catches Hall sets, like three variables sharing two values, which
+/// arc consistency over the pairs can't see
+pub struct AllDifferent {
+    pub scope: Vec<Variable>,
+    /// Value of each variable of the scope in the matching found by the last
+    /// call, so the next one only repairs what the domains lost since
+    matching: Vec<Option<Universe>>,
+}
+impl AllDifferent {
+    pub fn new(scope: Vec<Variable>) -> Self {
+        let matching = vec![None; scope.len()];
+        Self { scope, matching }
+    }
+}
+fn augment<'a>(
+    i: usize,
+    candidates: &impl Fn(usize) -> &'a [Universe],
+    owner: &mut BTreeMap<Universe, usize>,
+    visited: &mut Vec<Universe>,
+) -> bool {
+    for &value in candidates(i) {
+        if visited.contains(&value) {
+            continue;
+        }
+        visited.push(value);
+        let free = match owner.get(&value) {
+            None => true,
+            Some(&other) => augment(other, candidates, owner, visited),
+        };
+        if free {
+            owner.insert(value, i);
+            return true;
+        }
+    }
+    false
+}
+impl Propagator for AllDifferent {
+    fn propagate(&mut self, domains: &mut [Domain]) -> PropagationResult {
+        let scope = &self.scope;
+        let values = |i: usize| -> &[Universe] { &domains[scope[i].id].values };
+
+        // Kuhn's augmenting paths, starting from what is left of the cached
+        // matching. `owner` holds which variable has each value
+        let mut owner: BTreeMap<Universe, usize> = BTreeMap::new();
+        for (i, matched) in self.matching.iter_mut().enumerate() {
+            match *matched {
+                Some(value) if values(i).contains(&value) => {
+                    owner.insert(value, i);
+                }
+                _ => *matched = None,
+            }
+        }
+        for i in 0..scope.len() {
+            if self.matching[i].is_none() && !augment(i, &values, &mut owner, &mut Vec::new()) {
+                return PropagationResult::Infeasible;
+            }
+        }
+        for (&value, &i) in &owner {
+            self.matching[i] = Some(value);
+        }
+
+        // A value is supported if the variable it displaces can be matched to
+        // something else
+        let mut removals = Vec::new();
+        for i in 0..scope.len() {
+            for &value in values(i) {
+                if self.matching[i] == Some(value) {
+                    continue;
+                }
+                let mut trial = owner.clone();
+                trial.remove(&self.matching[i].unwrap());
+                let Some(displaced) = trial.insert(value, i) else {
+                    continue;
+                };
+                let forced = |j: usize| -> &[Universe] {
+                    if j == i {
+                        core::slice::from_ref(&value)
+                    } else {
+                        values(j)
+                    }
+                };
+                if !augment(displaced, &forced, &mut trial, &mut vec![value]) {
+                    removals.push((i, value));
+                }
+            }
+        }
+
+        if removals.is_empty() {
+            return PropagationResult::Unchanged;
+        }
+        for (i, value) in removals {
+            domains[self.scope[i].id].values.retain(|&v| v != value);
+        }
+        PropagationResult::Changed
+    }
+}
+
+/// Maximal groups of at least 3 variables that are pairwise constrained by
+/// [`Constraint::not_equal`], so they're an all-different together
+pub(crate) fn not_equal_cliques(
+    constraints: &BTreeMap<Vec<Variable>, Vec<Constraint>>,
+) -> Vec<Vec<Variable>> {
+    use crate::ConstraintKind;
+
+    let mut neighbors: BTreeMap<Variable, Vec<Variable>> = BTreeMap::new();
+    for (scope, constraints) in constraints {
+        let &[a, b] = scope.as_slice() else {
+            continue;
+        };
+        if constraints
+            .iter()
+            .any(|c| c.kind == Some(ConstraintKind::NotEqual))
+        {
+            neighbors.entry(a).or_default().push(b);
+            neighbors.entry(b).or_default().push(a);
+        }
+    }
+
+    // Bron-Kerbosch with pivoting
+    fn extend(
+        neighbors: &BTreeMap<Variable, Vec<Variable>>,
+        clique: &mut Vec<Variable>,
+        mut candidates: Vec<Variable>,
+        mut excluded: Vec<Variable>,
+        cliques: &mut Vec<Vec<Variable>>,
+    ) {
+        if candidates.is_empty() && excluded.is_empty() {
+            if clique.len() >= 3 {
+                let mut found = clique.clone();
+                found.sort_unstable();
+                cliques.push(found);
+            }
+            return;
+        }
+        let adjacent = |var: &Variable| &neighbors[var];
+        let pivot = candidates.iter().chain(&excluded).copied().max_by_key(|p| {
+            candidates
+                .iter()
+                .filter(|v| adjacent(p).contains(v))
+                .count()
+        });
+        let pivot_neighbors = pivot.map_or(&[][..], |p| adjacent(&p));
+        let branches: Vec<Variable> = candidates
+            .iter()
+            .copied()
+            .filter(|v| !pivot_neighbors.contains(v))
+            .collect();
+        for var in branches {
+            clique.push(var);
+            let keep = |vars: &[Variable]| {
+                vars.iter()
+                    .copied()
+                    .filter(|v| adjacent(&var).contains(v))
+                    .collect()
+            };
+            extend(
+                neighbors,
+                clique,
+                keep(&candidates),
+                keep(&excluded),
+                cliques,
+            );
+            clique.pop();
+            candidates.retain(|&v| v != var);
+            excluded.push(var);
+        }
+    }
+
+    let mut cliques = Vec::new();
+    let all = neighbors.keys().copied().collect();
+    extend(&neighbors, &mut Vec::new(), all, Vec::new(), &mut cliques);
+    cliques.sort_unstable();
+    cliques
+}
+
+impl NormalizedProblem {
+    pub fn add_propagator(&mut self, propagator: Box<dyn Propagator>) {
+        self.propagators.push(propagator);
+    }
+
+    pub fn constraint_propagation(self) -> Option<PropagatedProblem> {
+        self.make_node_consistency()
+            .make_bounds_consistency()
+            .make_propagators_fixpoint()
+            .map(Self::into_propagated)
+    }
+    /// Just the domains [`Self::constraint_propagation`] leaves, with the
+    /// lazy ones generated, or `None` if it finds the problem infeasible
+    pub fn propagate_domains(self) -> Option<Vec<Domain>> {
+        let mut problem = self.constraint_propagation()?;
+        problem.realize_all();
+        Some(problem.domains)
+    }
+    /// Last step of [`NormalizedProblem::constraint_propagation`], once every
+    /// propagator reached its fixpoint
+    pub(crate) fn into_propagated(self) -> PropagatedProblem {
+        let NormalizedProblem {
+            variables,
+            domains,
+            lazy_domains,
+            constraints,
+            propagators,
+            ..
+        } = self.sort_domains();
+        PropagatedProblem {
+            variables,
+            domains,
+            lazy_domains,
+            consistency_level: ConsistencyLevel::ArcConsistent,
+            constraints: Self::sort_constraints(constraints.into_iter().collect()),
+            propagators,
+        }
+    }
+
+    pub(crate) fn make_node_consistency(mut self) -> Self {
+        let unary: Vec<Variable> = self
+            .constraints
+            .keys()
+            .filter(|scope| scope.len() == 1)
+            .map(|scope| scope[0])
+            .collect();
+        self.realize(unary);
+
+        for i in 0..self.variables.len() {
+            let var = self.variables[i];
+            let domain = &mut self.domains[i].values;
+
+            if let Some(constraints) = self.constraints.remove(&vec![var]) {
+                domain.retain(|&vx| Constraint::all_satisfied(&constraints, &[vx]));
+            }
+        }
+
+        self
+    }
+    /// Cheap pass before arc consistency over the constraints built by
+    /// [`Constraint::less_than`] and [`Constraint::less_equal`], removing the
+    /// values out of the bounds the other variable allows, like `x` keeping
+    /// only values below the largest value of `y` for `x < y`
+    pub(crate) fn make_bounds_consistency(mut self) -> Self {
+        // (lesser, greater, strict)
+        let orderings: Vec<(Variable, Variable, bool)> = self
+            .constraints
+            .iter()
+            .flat_map(|(scope, constraints)| {
+                constraints.iter().filter_map(|constraint| {
+                    let (lesser, strict) = match constraint.kind {
+                        Some(ConstraintKind::LessThan(lesser)) => (lesser, true),
+                        Some(ConstraintKind::LessEq(lesser)) => (lesser, false),
+                        _ => return None,
+                    };
+                    let greater = if scope[0] == lesser {
+                        scope[1]
+                    } else {
+                        scope[0]
+                    };
+                    Some((lesser, greater, strict))
+                })
+            })
+            .collect();
+        self.realize(orderings.iter().flat_map(|&(a, b, _)| [a, b]));
+
+        // Every round that changes something removes a value, so it ends
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for &(lesser, greater, strict) in &orderings {
+                let sizes = |domains: &[Domain]| {
+                    domains[lesser.id].values.len() + domains[greater.id].values.len()
+                };
+                let before = sizes(&self.domains);
+                if let Some(&max) = self.domains[greater.id].values.iter().max() {
+                    self.domains[lesser.id]
+                        .values
+                        .retain(|&v| v < max || !strict && v == max);
+                }
+                if let Some(&min) = self.domains[lesser.id].values.iter().min() {
+                    self.domains[greater.id]
+                        .values
+                        .retain(|&v| v > min || !strict && v == min);
+                }
+                changed |= sizes(&self.domains) != before;
+            }
+        }
+        self
+    }
+    pub(crate) fn make_propagators_fixpoint(mut self) -> Option<Self> {
+        // Arc consistency only reads the domains of binary constraints, but
+        // custom propagators could read any of them
+        let needed: Vec<Variable> = if self.propagators.is_empty() {
+            self.constraints
+                .keys()
+                .filter(|scope| scope.len() == 2)
+                .flatten()
+                .copied()
+                .collect()
+        } else {
+            self.variables.clone()
+        };
+        self.realize(needed);
+
+        let mut arc_consistency = ArcConsistency {
+            variables: &self.variables,
+            constraints: &self.constraints,
+            order: self.arc_order,
+            algorithm: self.arc_algorithm,
+        };
+        let mut propagators = core::mem::take(&mut self.propagators);
+
+        let feasible =
+            'fixpoint: loop {
+                let mut changed = false;
+                let all = core::iter::once(&mut arc_consistency as &mut dyn Propagator).chain(
+                    propagators
+                        .iter_mut()
+                        .map(|propagator| propagator.as_mut() as &mut dyn Propagator),
+                );
+                for propagator in all {
+                    match propagator.propagate(&mut self.domains) {
+                        PropagationResult::Infeasible => break 'fixpoint false,
+                        PropagationResult::Changed => changed = true,
+                        PropagationResult::Unchanged => {}
+                    }
+                    if self.domains.iter().any(|dom| {
+                        dom.values.is_empty() && !self.lazy_domains.contains_key(&dom.of)
+                    }) {
+                        break 'fixpoint false;
+                    }
+                }
+                if !changed {
+                    break true;
+                }
+            };
+
+        self.propagators = propagators;
+        feasible.then_some(self)
+    }
+    fn sort_domains(mut self) -> Self {
+        for domain in self.domains.iter_mut() {
+            domain.values.sort_unstable()
+        }
+        self
+    }
+    fn sort_constraints(
+        mut constraints: Vec<(Vec<Variable>, Vec<Constraint>)>,
+    ) -> Vec<(Vec<Variable>, Vec<Constraint>)> {
+        constraints.sort_unstable_by(|(scope_a, _), (scope_b, _)| cmp_scopes(scope_a, scope_b));
+        constraints
+    }
+}
+
+/// Arc consistency over the binary constraints of a problem
+pub(crate) struct ArcConsistency<'a> {
+    pub(crate) variables: &'a [Variable],
+    pub(crate) constraints: &'a BTreeMap<Vec<Variable>, Vec<Constraint>>,
+    pub(crate) order: ArcOrder,
+    pub(crate) algorithm: ArcAlgorithm,
+}
+impl Propagator for ArcConsistency<'_> {
+    fn propagate(&mut self, domains: &mut [Domain]) -> PropagationResult {
+        let mut worklist = Vec::new();
+        for &x in self.variables {
+            for &y in self.variables {
+                if self.constraints.get(&vec![x, y]).is_some()
+                    || self.constraints.get(&vec![y, x]).is_some()
+                {
+                    worklist.push((x, y));
+                }
+            }
+        }
+
+        let sizes_before: Vec<usize> = domains.iter().map(|dom| dom.values.len()).collect();
+        let constraints_on = |scope: &[Variable]| self.constraints.get(scope).map(Vec::as_slice);
+        let consistent = match self.algorithm {
+            ArcAlgorithm::Ac3 => arc_consistency(
+                self.variables,
+                domains,
+                worklist,
+                self.order,
+                constraints_on,
+            ),
+            ArcAlgorithm::Ac2001 => arc_consistency_2001(
+                self.variables,
+                domains,
+                worklist,
+                self.order,
+                constraints_on,
+            ),
+        };
+
+        if !consistent {
+            PropagationResult::Infeasible
+        } else if domains
+            .iter()
+            .zip(sizes_before)
+            .any(|(dom, size)| dom.values.len() != size)
+        {
+            PropagationResult::Changed
+        } else {
+            PropagationResult::Unchanged
+        }
+    }
+}
+
+/// Orders scopes by their last variable, then their second to last, and so on
+pub(crate) fn cmp_scopes(scope_a: &[Variable], scope_b: &[Variable]) -> Ordering {
+    let mut rev_a = scope_a.iter().rev();
+    let mut rev_b = scope_b.iter().rev();
+    loop {
+        let a = rev_a.next().map(|v| v.id);
+        let b = rev_b.next().map(|v| v.id);
+
+        match (a, b) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(a), Some(b)) => {
+                if a == b {
+                    continue;
+                } else {
+                    return a.cmp(&b);
+                }
+            }
+        }
+    }
+}
+
+/// Order in which arc consistency revises the arcs waiting in its worklist.
+/// The domains it ends with are the same either way, only the work changes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ArcOrder {
+    /// Most recently added arc first
+    Lifo,
+    /// Arcs `(x, y)` where `y` has the smallest domain first, as those are the
+    /// most likely to remove values from `x`
+    #[default]
+    SmallestDomainFirst,
+}
+
+/// How arc consistency revises an arc. The domains it ends with are the same
+/// either way, only the work changes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ArcAlgorithm {
+    /// AC-3: looks for a support of each value from scratch every time
+    #[default]
+    Ac3,
+    /// AC-2001: remembers the last support found for each value, and only
+    /// looks past it once that support is removed
+    Ac2001,
+}
+
+/// Arcs waiting to be revised, handed out in some [`ArcOrder`]
+enum Worklist {
+    Lifo(Vec<(Variable, Variable)>),
+    /// Keyed by the domain size of `y` when the arc was added, then the ids
+    SmallestDomainFirst(BinaryHeap<Reverse<(usize, usize, usize)>>),
+}
+impl Worklist {
+    fn new(initial: Vec<(Variable, Variable)>, order: ArcOrder, domains: &[Domain]) -> Self {
+        match order {
+            ArcOrder::Lifo => Worklist::Lifo(initial),
+            ArcOrder::SmallestDomainFirst => {
+                let mut worklist = Worklist::SmallestDomainFirst(BinaryHeap::new());
+                for arc in initial {
+                    worklist.push(arc, domains);
+                }
+                worklist
+            }
+        }
+    }
+    fn push(&mut self, (x, y): (Variable, Variable), domains: &[Domain]) {
+        match self {
+            Worklist::Lifo(arcs) => arcs.push((x, y)),
+            Worklist::SmallestDomainFirst(arcs) => {
+                arcs.push(Reverse((domains[y.id].values.len(), x.id, y.id)))
+            }
+        }
+    }
+    fn pop(&mut self) -> Option<(Variable, Variable)> {
+        match self {
+            Worklist::Lifo(arcs) => arcs.pop(),
+            Worklist::SmallestDomainFirst(arcs) => arcs
+                .pop()
+                .map(|Reverse((_, x, y))| (Variable { id: x }, Variable { id: y })),
+        }
+    }
+}
+
+/// Runs AC-3 starting from the arcs in `initial`, finding constraints
+/// through `constraints_on`. Returns false if some domain was emptied
+fn arc_consistency<'a>(
+    variables: &[Variable],
+    domains: &mut [Domain],
+    initial: Vec<(Variable, Variable)>,
+    order: ArcOrder,
+    constraints_on: impl Fn(&[Variable]) -> Option<&'a [Constraint]>,
+) -> bool {
+    arc_consistency_requeuing(variables, domains, initial, order, constraints_on, requeue)
+}
+
+/// Whether the arc `(z, xx)` goes back on the worklist after revising
+/// `(x, y)` reduced the domain of `x`: the arcs into `x` from its other
+/// neighbors, whose supports in `x` may be gone
+pub(crate) fn requeue(
+    (z, xx): (Variable, Variable),
+    (x, y): (Variable, Variable),
+    constrained: &dyn Fn(&[Variable]) -> bool,
+) -> bool {
+    z != y && xx == x && (constrained(&[z, x]) || constrained(&[x, z]))
+}
+
+/// The filter [`requeue`] replaced, whose missing parentheses requeued the
+/// arcs out of every neighbor `z` of `x`, kept to test against
+#[cfg(test)]
+pub(crate) fn old_requeue(
+    (z, xx): (Variable, Variable),
+    (x, y): (Variable, Variable),
+    constrained: &dyn Fn(&[Variable]) -> bool,
+) -> bool {
+    z != y && xx == x && constrained(&[z, x]) || constrained(&[x, z])
+}
+
+/// [`arc_consistency`] with `requeue` choosing the arcs to revise again
+pub(crate) fn arc_consistency_requeuing<'a>(
+    variables: &[Variable],
+    domains: &mut [Domain],
+    initial: Vec<(Variable, Variable)>,
+    order: ArcOrder,
+    constraints_on: impl Fn(&[Variable]) -> Option<&'a [Constraint]>,
+    requeue: impl Fn((Variable, Variable), (Variable, Variable), &dyn Fn(&[Variable]) -> bool) -> bool,
+) -> bool {
+    let mut worklist = Worklist::new(initial, order, domains);
+    let constrained = |scope: &[Variable]| constraints_on(scope).is_some();
+
+    // Using AC-3 from https://en.wikipedia.org/wiki/AC-3_algorithm
+    let mut vars_cartesian_product = Vec::with_capacity(variables.len() * variables.len());
+    for &var1 in variables {
+        for &var2 in variables {
+            vars_cartesian_product.push((var1, var2));
+        }
+    }
+
+    while let Some(arc) = worklist.pop() {
+        let (x, y) = arc;
+
+        match arc_reduce(domains, x, y, &constraints_on) {
+            Revision::Unchanged => {}
+            Revision::Emptied => return false,
+            Revision::Reduced => {
+                for &arc in vars_cartesian_product
+                    .iter()
+                    .filter(|&&other| requeue(other, (x, y), &constrained))
+                {
+                    worklist.push(arc, domains);
+                }
+            }
+        }
+    }
+
+    true
+}
+
+/// Runs AC-2001 starting from the arcs in `initial`, like
+/// [`arc_consistency`]. Supports are positions in the domains as they were at
+/// the start, which only lose values from then on, so a value never finds a
+/// new support before its last one
+fn arc_consistency_2001<'a>(
+    variables: &[Variable],
+    domains: &mut [Domain],
+    initial: Vec<(Variable, Variable)>,
+    order: ArcOrder,
+    constraints_on: impl Fn(&[Variable]) -> Option<&'a [Constraint]>,
+) -> bool {
+    let mut worklist = Worklist::new(initial, order, domains);
+    let original: Vec<Vec<Universe>> = domains.iter().map(|dom| dom.values.clone()).collect();
+    let mut alive: Vec<Vec<bool>> = original.iter().map(|vals| vec![true; vals.len()]).collect();
+    // For each arc (x, y), the last support in y of each value of x
+    let mut last: HashMap<(usize, usize), Vec<Option<usize>>> = HashMap::new();
+    let neighbors: Vec<Vec<Variable>> = variables
+        .iter()
+        .map(|&x| {
+            variables
+                .iter()
+                .copied()
+                .filter(|&z| z != x && constraints_on(&[x.min(z), x.max(z)]).is_some())
+                .collect()
+        })
+        .collect();
+
+    while let Some((x, y)) = worklist.pop() {
+        // Scopes are sorted by id, so the constraint may be stored as (y, x)
+        let flipped = x.id > y.id;
+        let scope = if flipped { [y, x] } else { [x, y] };
+        let Some(constraints) = constraints_on(&scope) else {
+            continue;
+        };
+        let supports = last
+            .entry((x.id, y.id))
+            .or_insert_with(|| vec![None; original[x.id].len()]);
+
+        let mut reduced = false;
+        for i in 0..original[x.id].len() {
+            if !alive[x.id][i] || supports[i].is_some_and(|j| alive[y.id][j]) {
+                continue;
+            }
+            let vx = original[x.id][i];
+            let start = supports[i].map_or(0, |j| j + 1);
+            let support = (start..original[y.id].len()).find(|&j| {
+                let vy = original[y.id][j];
+                let vals = if flipped { [vy, vx] } else { [vx, vy] };
+                alive[y.id][j] && Constraint::all_satisfied(constraints, &vals)
+            });
+            supports[i] = support;
+            if support.is_none() {
+                alive[x.id][i] = false;
+                reduced = true;
+            }
+        }
+
+        if reduced {
+            domains[x.id].values = original[x.id]
+                .iter()
+                .zip(&alive[x.id])
+                .filter_map(|(&value, &alive)| alive.then_some(value))
+                .collect();
+            if domains[x.id].values.is_empty() {
+                return false;
+            }
+            for &z in neighbors[x.id].iter().filter(|&&z| z != y) {
+                worklist.push((z, x), domains);
+            }
+        }
+    }
+
+    true
+}
+
+/// What revising an arc did to the domain of its first variable
+#[derive(Debug, PartialEq, Eq)]
+enum Revision {
+    Unchanged,
+    Reduced,
+    Emptied,
+}
+fn arc_reduce<'a>(
+    domains: &mut [Domain],
+    x: Variable,
+    y: Variable,
+    constraints_on: impl Fn(&[Variable]) -> Option<&'a [Constraint]>,
+) -> Revision {
+    // Scopes are sorted by id, so the constraint may be stored as (y, x)
+    let scope = if x.id > y.id { [y, x] } else { [x, y] };
+    let Some(constraints) = constraints_on(&scope) else {
+        return Revision::Unchanged;
+    };
+    // Symmetric constraints take the values in either order
+    let flipped = x.id > y.id && !constraints.iter().all(|c| c.symmetric);
+    if domains[y.id].values.is_empty() {
+        // Nothing can support any value of x
+        domains[x.id].values.clear();
+        return Revision::Emptied;
+    }
+
+    let supported: Vec<Universe> = domains[x.id]
+        .values
+        .iter()
+        .copied()
+        .filter(|&vx| {
+            domains[y.id].values.iter().any(|&vy| {
+                let vals = if flipped { [vy, vx] } else { [vx, vy] };
+                Constraint::all_satisfied(constraints, &vals)
+            })
+        })
+        .collect();
+
+    if supported.is_empty() {
+        domains[x.id].values.clear();
+        Revision::Emptied
+    } else if supported.len() < domains[x.id].values.len() {
+        domains[x.id].values = supported;
+        Revision::Reduced
+    } else {
+        Revision::Unchanged
+    }
+}
+
+/// Guarantee that holds for the domains of a [`PropagatedProblem`], from
+/// weakest to strongest
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ConsistencyLevel {
+    /// Every value satisfies the unary constraints
+    NodeConsistent,
+    /// Every value also has a support in each binary constraint
+    ArcConsistent,
+    /// Assigning any single value keeps the problem arc consistent
+    SingletonArcConsistent,
+}
+
+/// Domains of a [`PropagatedProblem`] at some point, see
+/// [`PropagatedProblem::snapshot`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DomainSnapshot {
+    domains: Vec<Domain>,
+}
+
+impl PropagatedProblem {
+    /// Fixes `var` to `value` and propagates what follows from it to the
+    /// other domains, with arc consistency and the custom propagators.
+    /// Returns `None` if that makes the problem infeasible, in which case the
+    /// domains are left as they were (the state propagators keep isn't)
+    pub fn assign(&mut self, var: Variable, value: Universe) -> Option<()> {
+        self.realize_all();
+        let saved = self.domains.clone();
+        let values = &mut self.domains[var.id].values;
+        values.retain(|&v| v == value);
+
+        if !values.is_empty() && self.propagate_changes(&[var]) {
+            Some(())
+        } else {
+            self.domains = saved;
+            None
+        }
+    }
+    /// Saves the domains, to go back to them with [`Self::restore`] after
+    /// trying some assignments
+    pub fn snapshot(&self) -> DomainSnapshot {
+        DomainSnapshot {
+            domains: self.realized_domains(),
+        }
+    }
+    pub fn restore(&mut self, snapshot: DomainSnapshot) {
+        self.domains = snapshot.domains;
+        self.lazy_domains.clear();
+    }
+    /// Restores arc consistency after the domains of `changed` were reduced,
+    /// running the custom propagators with it until neither changes anything.
+    /// Returns false if some domain was emptied
+    pub(crate) fn propagate_changes(&mut self, changed: &[Variable]) -> bool {
+        self.realize_all();
+        let mut domains = core::mem::take(&mut self.domains);
+        let mut propagators = core::mem::take(&mut self.propagators);
+        let mut changed = changed.to_vec();
+
+        let consistent = loop {
+            if !self.propagate_domains(&mut domains, &changed) {
+                break false;
+            }
+            let sizes: Vec<usize> = domains.iter().map(|dom| dom.values.len()).collect();
+            let feasible = propagators.iter_mut().all(|propagator| {
+                propagator.propagate(&mut domains) != PropagationResult::Infeasible
+                    && domains.iter().all(|dom| !dom.values.is_empty())
+            });
+            if !feasible {
+                break false;
+            }
+            // Arc consistency only has to look again at what the propagators
+            // reduced
+            changed = (self.variables.iter().copied())
+                .filter(|var| domains[var.id].values.len() < sizes[var.id])
+                .collect();
+            if changed.is_empty() {
+                break true;
+            }
+        };
+
+        self.propagators = propagators;
+        self.domains = domains;
+        consistent
+    }
+    /// Same as `propagate_changes` but over some other domains for the
+    /// problem's variables, and only with arc consistency
+    pub(crate) fn propagate_domains(&self, domains: &mut [Domain], changed: &[Variable]) -> bool {
+        let mut worklist = Vec::new();
+        for (scope, _) in &self.constraints {
+            if let &[a, b] = scope.as_slice() {
+                if changed.contains(&b) {
+                    worklist.push((a, b));
+                }
+                if changed.contains(&a) {
+                    worklist.push((b, a));
+                }
+            }
+        }
+
+        self.revise_arcs(domains, worklist)
+    }
+    /// Runs arc consistency on `domains` starting from the arcs in `worklist`
+    fn revise_arcs(&self, domains: &mut [Domain], worklist: Vec<(Variable, Variable)>) -> bool {
+        arc_consistency(
+            &self.variables,
+            domains,
+            worklist,
+            ArcOrder::default(),
+            |scope| self.constraints_on(scope),
+        )
+    }
+    pub(crate) fn constraints_on(&self, scope: &[Variable]) -> Option<&[Constraint]> {
+        // Constraints are sorted by scope, so they can be found by binary search
+        self.constraints
+            .binary_search_by(|(other, _)| cmp_scopes(other, scope))
+            .ok()
+            .map(|i| self.constraints[i].1.as_slice())
+    }
+    /// Whether revising every arc once more would remove nothing, which
+    /// holds after arc consistency if it didn't stop early
+    pub fn is_at_fixpoint(&self) -> bool {
+        let mut domains = self.realized_domains();
+        self.constraints.iter().all(|(scope, _)| {
+            let &[a, b] = scope.as_slice() else {
+                return true;
+            };
+            [(a, b), (b, a)].into_iter().all(|(x, y)| {
+                arc_reduce(&mut domains, x, y, |scope| self.constraints_on(scope))
+                    == Revision::Unchanged
+            })
+        })
+    }
+    /// Adds `values` to the domain of `var`, keeping the ones that have
+    /// supports in the binary constraints. Values of other variables that
+    /// propagation removed because of the old domain don't come back, and
+    /// unary constraints were already folded into the domains so they aren't
+    /// checked: for those, extend the domain of the [`crate::RawProblem`]
+    /// and propagate again. Returns false if the problem is infeasible
+    pub fn extend_domain(&mut self, var: Variable, values: &[Universe]) -> bool {
+        self.realize_all();
+        let domain = &mut self.domains[var.id].values;
+        domain.extend_from_slice(values);
+        domain.sort_unstable();
+        domain.dedup();
+        self.consistency_level = self.consistency_level.min(ConsistencyLevel::ArcConsistent);
+
+        // Only the new values can be missing supports
+        let mut worklist = Vec::new();
+        for (scope, _) in &self.constraints {
+            match *scope.as_slice() {
+                [a, b] if a == var => worklist.push((a, b)),
+                [a, b] if b == var => worklist.push((b, a)),
+                _ => {}
+            }
+        }
+        let mut domains = core::mem::take(&mut self.domains);
+        let consistent = self.revise_arcs(&mut domains, worklist);
+        self.domains = domains;
+        consistent
+    }
+}
+
+impl PropagatedProblem {
+    /// Product of the domain sizes (saturating), which no number of solutions
+    /// can go over
+    pub fn solution_upper_bound(&self) -> u128 {
+        (0..self.domains.len()).fold(1u128, |acc, i| {
+            acc.saturating_mul(self.values(i).len() as u128)
+        })
+    }
+    /// The solution, if propagation already left a single value in every
+    /// domain. `None` means search is still needed, including when those
+    /// values break a constraint propagation couldn't check
+    pub fn try_solve_by_propagation(&self) -> Option<Vec<Universe>> {
+        let solution = (0..self.domains.len())
+            .map(|i| match *self.values(i) {
+                [val] => Some(val),
+                _ => None,
+            })
+            .collect::<Option<Vec<_>>>()?;
+        self.verify(&solution).then_some(solution)
+    }
+    /// Whether the problem has exactly one solution. Free if propagation
+    /// already solved it, otherwise searches for a second solution
+    pub fn is_uniquely_solvable(&self) -> bool {
+        self.try_solve_by_propagation().is_some() || self.solutions().take(2).count() == 1
+    }
+    /// Removes every value that makes arc consistency fail once assigned,
+    /// until there are none left. Much stronger but also much more expensive
+    /// than arc consistency. Returns `None` if the problem turns out infeasible
+    pub fn make_singleton_arc_consistency(mut self) -> Option<Self> {
+        self.realize_all();
+        loop {
+            let mut changed = Vec::new();
+            for i in 0..self.variables.len() {
+                let var = self.variables[i];
+                for value in self.domains[i].values.clone() {
+                    let mut domains = self.domains.clone();
+                    domains[i].values = vec![value];
+                    if !self.propagate_domains(&mut domains, &[var]) {
+                        self.domains[i].values.retain(|&v| v != value);
+                        changed.push(var);
+                    }
+                }
+                if self.domains[i].values.is_empty() {
+                    return None;
+                }
+            }
+
+            if changed.is_empty() {
+                break;
+            }
+            if !self.propagate_changes(&changed) {
+                return None;
+            }
+        }
+
+        self.consistency_level = ConsistencyLevel::SingletonArcConsistent;
+        Some(self)
+    }
+    /// Removes every value that can't be extended to the variables sharing a
+    /// constraint with its variable all at once (neighborhood inverse
+    /// consistency), until there are none left. Each check is a search over
+    /// the neighborhood, so it only pays off on sparse problems. Returns
+    /// `None` if the problem turns out infeasible
+    pub fn make_neighborhood_inverse_consistency(mut self) -> Option<Self> {
+        self.realize_all();
+        let mut neighborhoods: Vec<Vec<Variable>> =
+            self.variables.iter().map(|&var| vec![var]).collect();
+        for (scope, _) in &self.constraints {
+            for var in scope {
+                neighborhoods[var.id].extend_from_slice(scope);
+            }
+        }
+        for neighborhood in &mut neighborhoods {
+            neighborhood.sort_unstable();
+            neighborhood.dedup();
+        }
+
+        loop {
+            let mut changed = Vec::new();
+            for i in 0..self.variables.len() {
+                let var = self.variables[i];
+                for value in self.domains[i].values.clone() {
+                    let mut domains = self.domains.clone();
+                    domains[i].values = vec![value];
+                    if !self.extends_to(&neighborhoods[i], &domains) {
+                        self.domains[i].values.retain(|&v| v != value);
+                        changed.push(var);
+                    }
+                }
+                if self.domains[i].values.is_empty() {
+                    return None;
+                }
+            }
+
+            if changed.is_empty() {
+                break;
+            }
+            if !self.propagate_changes(&changed) {
+                return None;
+            }
+        }
+
+        Some(self)
+    }
+    /// Whether `vars` (sorted) can be assigned values from `domains` that
+    /// satisfy every constraint among them
+    fn extends_to(&self, vars: &[Variable], domains: &[Domain]) -> bool {
+        // The constraints inside `vars`, by the position of their last
+        // variable, which is when they can be checked
+        let mut checks: Vec<Vec<(Vec<usize>, &[Constraint])>> = vec![Vec::new(); vars.len()];
+        for (scope, constraints) in &self.constraints {
+            let positions: Option<Vec<usize>> = scope
+                .iter()
+                .map(|var| vars.binary_search(var).ok())
+                .collect();
+            if let Some(positions) = positions {
+                checks[*positions.last().unwrap()].push((positions, constraints));
+            }
+        }
+
+        fn extend(
+            k: usize,
+            values: &mut Vec<Universe>,
+            vars: &[Variable],
+            domains: &[Domain],
+            checks: &[Vec<(Vec<usize>, &[Constraint])>],
+        ) -> bool {
+            if k == vars.len() {
+                return true;
+            }
+            for &value in &domains[vars[k].id].values {
+                values.push(value);
+                let consistent = checks[k].iter().all(|(positions, constraints)| {
+                    let vals: Vec<Universe> = positions.iter().map(|&p| values[p]).collect();
+                    Constraint::all_satisfied(constraints, &vals)
+                });
+                if consistent && extend(k + 1, values, vars, domains, checks) {
+                    return true;
+                }
+                values.pop();
+            }
+            false
+        }
+
+        extend(0, &mut Vec::new(), vars, domains, &checks)
+    }
+}
+
+/// Problem whose shared structure is propagated once, to then be instantiated
+/// many times with different clues (values fixed for some variables)
+pub struct ProblemTemplate {
+    problem: PropagatedProblem,
+    base_domains: Vec<Domain>,
+}
+impl ProblemTemplate {
+    /// Propagates the shared constraints, returns `None` if they are already
+    /// inconsistent
+    pub fn new(problem: NormalizedProblem) -> Option<Self> {
+        let mut problem = problem.constraint_propagation()?;
+        problem.realize_all();
+        Some(Self {
+            base_domains: problem.domains.clone(),
+            problem,
+        })
+    }
+    /// Returns the problem with the clues applied and propagated, ready to be
+    /// solved, or `None` if the clues are inconsistent
+    pub fn instantiate(&mut self, clues: &[(Variable, Universe)]) -> Option<&PropagatedProblem> {
+        self.problem.domains.clone_from(&self.base_domains);
+
+        for &(var, value) in clues {
+            self.problem.domains[var.id].values.retain(|&v| v == value);
+            if self.problem.domains[var.id].values.is_empty() {
+                return None;
+            }
+        }
+
+        let changed: Vec<Variable> = clues.iter().map(|&(var, _)| var).collect();
+        if self.problem.propagate_changes(&changed) {
+            Some(&self.problem)
+        } else {
+            None
+        }
+    }
+}
@@ -0,0 +1,246 @@
+//! Randomized checks that all the solvers agree with each other on small
+//! generated problems
+
+use alloc::{vec, vec::Vec};
+
+use crate::{
+    random::{planted_binary_problem, random_binary_problem, Rng},
+    Activity, AllDifferent, ArcAlgorithm, Circuit, Constraint, ConstraintWeights, Domain,
+    PropagationResult, Propagator, RawProblem, SolverOptions, TieBreak, Universe, Variable,
+    VariableOrder,
+};
+
+const INSTANCES: u64 = 300;
+
+/// Size and shape of a random binary problem: between 2 and `max_vars`
+/// variables with between 2 and `max_domain` values, any density, and
+/// tightness below `max_tightness`
+fn random_params(
+    rng: &mut Rng,
+    max_vars: usize,
+    max_domain: usize,
+    max_tightness: f64,
+) -> (usize, usize, f64, f64) {
+    let num_vars = 2 + rng.below(max_vars - 1);
+    let domain_size = 2 + rng.below(max_domain - 1);
+    let density = rng.next_f64();
+    let tightness = rng.next_f64() * max_tightness;
+    (num_vars, domain_size, density, tightness)
+}
+
+#[test]
+fn test_solvers_agree_on_random_problems() {
+    for seed in 0..INSTANCES {
+        let mut rng = Rng::new(seed);
+        let (num_vars, domain_size, density, tightness) = random_params(&mut rng, 7, 5, 0.6);
+
+        let Some(problem) =
+            random_binary_problem(&mut rng, num_vars, domain_size, density, tightness)
+                .normalize_problem()
+                .constraint_propagation()
+        else {
+            continue;
+        };
+
+        let backtracking = problem.solve_backtracking();
+        let cbj = problem.solve_cbj();
+        let brute_force = problem.solve_brute_force().unwrap();
+        let mrv = problem.solve_with_options(&SolverOptions {
+            variable_order: VariableOrder::Mrv,
+            tie_break: TieBreak::Random(seed),
+        });
+        let vsids = problem.solve_vsids(&mut Activity::new(num_vars, 0.95));
+        // Random weights reorder the variables, and learning more on a second
+        // run reorders them again
+        let mut weights = ConstraintWeights::new();
+        for scope in problem.constraints() {
+            weights.set(scope.to_vec(), rng.below(5) as u64);
+        }
+        let weighted = problem.solve_cbj_weighted(&mut weights).0;
+        let reweighted = problem.solve_cbj_weighted(&mut weights).0;
+
+        assert_eq!(backtracking.is_some(), cbj.is_some(), "seed {seed}");
+        assert_eq!(backtracking.is_some(), brute_force.is_some(), "seed {seed}");
+        assert_eq!(backtracking.is_some(), mrv.is_some(), "seed {seed}");
+        assert_eq!(backtracking.is_some(), vsids.is_some(), "seed {seed}");
+        assert_eq!(backtracking.is_some(), weighted.is_some(), "seed {seed}");
+        assert_eq!(backtracking.is_some(), reweighted.is_some(), "seed {seed}");
+        // Both search the values in increasing order, so find the same first solution
+        assert_eq!(backtracking, brute_force, "seed {seed}");
+        // Static order with arc consistency kept also finds the first solution
+        assert_eq!(
+            problem.solve_with_options(&SolverOptions::default()),
+            backtracking,
+            "seed {seed}"
+        );
+        let solutions = [backtracking, cbj, mrv, vsids, weighted, reweighted];
+        for solution in solutions.into_iter().flatten() {
+            assert!(problem.verify(&solution), "seed {seed}");
+        }
+    }
+}
+
+#[test]
+fn test_cbj_enumerates_every_solution() {
+    for seed in 0..INSTANCES {
+        let mut rng = Rng::new(seed);
+        let (num_vars, domain_size, density, tightness) = random_params(&mut rng, 6, 4, 0.6);
+
+        let Some(problem) =
+            random_binary_problem(&mut rng, num_vars, domain_size, density, tightness)
+                .normalize_problem()
+                .constraint_propagation()
+        else {
+            continue;
+        };
+
+        let mut cbj = problem.solve_cbj_all();
+        let brute_force = problem.solve_brute_force_all().unwrap();
+        assert!(cbj.is_sorted_by(|a, b| a > b), "seed {seed}");
+        cbj.reverse();
+        assert_eq!(cbj, brute_force, "seed {seed}");
+    }
+}
+
+#[test]
+fn test_solvers_find_planted_solutions() {
+    for seed in 0..INSTANCES {
+        let mut rng = Rng::new(seed);
+        let (num_vars, domain_size, density, tightness) = random_params(&mut rng, 7, 5, 0.8);
+
+        let (problem, planted) =
+            planted_binary_problem(&mut rng, num_vars, domain_size, density, tightness);
+        let problem = problem
+            .normalize_problem()
+            .constraint_propagation()
+            .unwrap_or_else(|| panic!("seed {seed}: propagation lost the planted solution"));
+        assert!(problem.verify(&planted), "seed {seed}");
+
+        let backtracking = problem
+            .solve_backtracking()
+            .expect("backtracking found nothing");
+        let cbj = problem.solve_cbj().expect("cbj found nothing");
+        assert!(problem.verify(&backtracking), "seed {seed}");
+        assert!(problem.verify(&cbj), "seed {seed}");
+    }
+}
+
+#[test]
+fn test_all_different_keeps_exactly_supported_values() {
+    for seed in 0..INSTANCES {
+        let mut rng = Rng::new(seed);
+        let num_vars = 2 + rng.below(4);
+        let mut problem = RawProblem::new();
+        let vars: Vec<Variable> = (0..num_vars)
+            .map(|_| {
+                let values = (0..5).filter(|_| rng.chance(0.5)).collect();
+                problem.add_var(values)
+            })
+            .collect();
+        // Fixing a variable mid way checks the cached matching gets repaired
+        let fixed = rng.below(num_vars);
+        problem.push_constraint(Constraint::all_different(vars.clone()));
+
+        let problem = problem.normalize_problem();
+        let mut domains: Vec<Domain> = problem.domains.clone();
+        let mut propagator = AllDifferent::new(vars.clone());
+        let solutions = problem
+            .constraint_propagation()
+            .map_or(Vec::new(), |p| p.solve_brute_force_all().unwrap());
+
+        let feasible = propagator.propagate(&mut domains) != PropagationResult::Infeasible;
+        assert_eq!(feasible, !solutions.is_empty(), "seed {seed}");
+        if !feasible {
+            continue;
+        }
+        for var in &vars {
+            let mut supported: Vec<_> = solutions.iter().map(|s| s[var.id]).collect();
+            supported.sort_unstable();
+            supported.dedup();
+            assert_eq!(domains[var.id].values, supported, "seed {seed}");
+        }
+
+        // Fix a variable to its largest value and propagate again
+        let value = *domains[fixed].values.last().unwrap();
+        domains[fixed].values = vec![value];
+        let remaining: Vec<&Vec<_>> = solutions.iter().filter(|s| s[fixed] == value).collect();
+        assert!(propagator.propagate(&mut domains) != PropagationResult::Infeasible);
+        for var in &vars {
+            let mut supported: Vec<_> = remaining.iter().map(|s| s[var.id]).collect();
+            supported.sort_unstable();
+            supported.dedup();
+            assert_eq!(domains[var.id].values, supported, "seed {seed}");
+        }
+    }
+}
+
+#[test]
+fn test_circuit_keeps_every_solution() {
+    for seed in 0..INSTANCES {
+        let mut rng = Rng::new(seed);
+        let n = 2 + rng.below(4);
+        let mut problem = RawProblem::new();
+        let next: Vec<Variable> = (0..n)
+            .map(|_| {
+                let values = (0..n as Universe).filter(|_| rng.chance(0.6)).collect();
+                problem.add_var(values)
+            })
+            .collect();
+        problem.push_constraint(Constraint::circuit(next.clone()));
+
+        let problem = problem.normalize_problem();
+        let mut domains: Vec<Domain> = problem.domains.clone();
+        let solutions = problem
+            .constraint_propagation()
+            .map_or(Vec::new(), |p| p.solve_brute_force_all().unwrap());
+
+        let feasible =
+            Circuit { next: next.clone() }.propagate(&mut domains) != PropagationResult::Infeasible;
+        assert!(feasible || solutions.is_empty(), "seed {seed}");
+        if feasible {
+            for solution in &solutions {
+                for var in &next {
+                    assert!(
+                        domains[var.id].values.contains(&solution[var.id]),
+                        "seed {seed}"
+                    );
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn test_ac2001_agrees_with_ac3() {
+    for seed in 0..INSTANCES {
+        let propagate = |algorithm| {
+            let mut rng = Rng::new(seed);
+            let (num_vars, domain_size, density, tightness) = random_params(&mut rng, 7, 6, 0.7);
+            let mut problem =
+                random_binary_problem(&mut rng, num_vars, domain_size, density, tightness)
+                    .normalize_problem();
+            problem.arc_algorithm = algorithm;
+            problem.constraint_propagation().map(|p| p.domains)
+        };
+        assert_eq!(
+            propagate(ArcAlgorithm::Ac3),
+            propagate(ArcAlgorithm::Ac2001),
+            "seed {seed}"
+        );
+    }
+}
+
+#[test]
+fn test_propagation_reaches_fixpoint() {
+    for seed in 0..INSTANCES {
+        let mut rng = Rng::new(seed);
+        let (num_vars, domain_size, density, tightness) = random_params(&mut rng, 9, 6, 0.6);
+        if let Some(problem) =
+            random_binary_problem(&mut rng, num_vars, domain_size, density, tightness)
+                .normalize_problem()
+                .constraint_propagation()
+        {
+            assert!(problem.is_at_fixpoint(), "seed {seed}");
+        }
+    }
+}
@@ -0,0 +1,166 @@
+use alloc::{boxed::Box, vec, vec::Vec};
+use hashbrown::HashSet;
+
+use crate::{RawProblem, Universe, Variable};
+
+/// Small seeded pseudo random generator (xorshift64*), so generated problems
+/// are reproducible without pulling in a dependency
+#[derive(Debug, Clone)]
+pub struct Rng {
+    state: u64,
+}
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        // Scramble the seed (splitmix64) so nearby seeds give unrelated streams,
+        // and make sure the state is never zero
+        let mut z = seed.wrapping_add(0x9E3779B97F4A7C15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        z ^= z >> 31;
+
+        Self { state: z | 1 }
+    }
+    pub fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545F4914F6CDD1D)
+    }
+    /// Returns a number in `0..n`
+    pub fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+    /// Returns a float in `[0, 1)`
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+    /// Returns true with probability `p`
+    pub fn chance(&mut self, p: f64) -> bool {
+        self.next_f64() < p
+    }
+}
+
+/// Generates a random binary CSP (model B style): every pair of variables is
+/// constrained with probability `density`, and every value pair of a
+/// constraint is forbidden with probability `tightness`. Each variable also
+/// gets a unary constraint forbidding one value with probability `density`.
+///
+/// Domains are `0..domain_size`.
+pub fn random_binary_problem(
+    rng: &mut Rng,
+    num_vars: usize,
+    domain_size: usize,
+    density: f64,
+    tightness: f64,
+) -> RawProblem {
+    generate(rng, num_vars, domain_size, density, tightness, None)
+}
+
+/// Same as [`random_binary_problem`] but hides a random solution in the
+/// problem, which is never forbidden by any constraint, so the problem is
+/// guaranteed to be satisfiable. Returns the problem and the planted solution.
+pub fn planted_binary_problem(
+    rng: &mut Rng,
+    num_vars: usize,
+    domain_size: usize,
+    density: f64,
+    tightness: f64,
+) -> (RawProblem, Vec<Universe>) {
+    let planted: Vec<Universe> = (0..num_vars)
+        .map(|_| rng.below(domain_size) as Universe)
+        .collect();
+    let problem = generate(
+        rng,
+        num_vars,
+        domain_size,
+        density,
+        tightness,
+        Some(&planted),
+    );
+    (problem, planted)
+}
+
+fn generate(
+    rng: &mut Rng,
+    num_vars: usize,
+    domain_size: usize,
+    density: f64,
+    tightness: f64,
+    planted: Option<&[Universe]>,
+) -> RawProblem {
+    let mut problem = RawProblem::new();
+    let vars: Vec<Variable> = (0..num_vars)
+        .map(|_| problem.add_var((0..domain_size as Universe).collect()))
+        .collect();
+
+    for &x in &vars {
+        if !rng.chance(density) {
+            continue;
+        }
+        let forbidden = rng.below(domain_size) as Universe;
+        if planted.is_some_and(|p| p[x.id] == forbidden) {
+            continue;
+        }
+        problem.add_constraint(
+            vec![x],
+            Box::new(move |vals| vals.next() != Some(forbidden)),
+        );
+    }
+
+    for (i, &x) in vars.iter().enumerate() {
+        for &y in &vars[i + 1..] {
+            if !rng.chance(density) {
+                continue;
+            }
+
+            let mut forbidden: HashSet<(Universe, Universe)> = HashSet::new();
+            for vx in 0..domain_size as Universe {
+                for vy in 0..domain_size as Universe {
+                    if planted.is_some_and(|p| p[x.id] == vx && p[y.id] == vy) {
+                        continue;
+                    }
+                    if rng.chance(tightness) {
+                        forbidden.insert((vx, vy));
+                    }
+                }
+            }
+
+            problem.add_constraint(
+                vec![x, y],
+                Box::new(move |vals| {
+                    let vx = vals.next().unwrap();
+                    let vy = vals.next().unwrap();
+                    !forbidden.contains(&(vx, vy))
+                }),
+            );
+        }
+    }
+
+    problem
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_same_seed_same_problem() {
+        let a = random_binary_problem(&mut Rng::new(7), 6, 4, 0.5, 0.3);
+        let b = random_binary_problem(&mut Rng::new(7), 6, 4, 0.5, 0.3);
+        let scopes_a: Vec<_> = a.constraints.iter().map(|c| c.scope.clone()).collect();
+        let scopes_b: Vec<_> = b.constraints.iter().map(|c| c.scope.clone()).collect();
+        assert_eq!(scopes_a, scopes_b);
+    }
+
+    #[test]
+    fn test_planted_solution_satisfies_constraints() {
+        let mut rng = Rng::new(3);
+        for _ in 0..20 {
+            let (problem, planted) = planted_binary_problem(&mut rng, 6, 4, 0.6, 0.5);
+            for constraint in &problem.constraints {
+                let mut vals = constraint.scope.iter().map(|var| planted[var.id]);
+                assert!((constraint.evaluate)(&mut vals));
+            }
+        }
+    }
+}
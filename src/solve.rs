@@ -0,0 +1,2134 @@
+//! Searching for solutions of a [`PropagatedProblem`]
+
+use alloc::{
+    borrow::ToOwned, boxed::Box, collections::BinaryHeap, format, rc::Rc, string::String, vec,
+    vec::Vec,
+};
+use core::{
+    cell::Cell,
+    cmp::Reverse,
+    fmt::{Display, Formatter},
+    ops::Deref,
+};
+use hashbrown::{HashMap, HashSet};
+#[cfg(feature = "std")]
+use std::time::{Duration, Instant};
+
+use crate::{
+    propagate::cmp_scopes, random, Candidate, Constraint, Domain, DomainSnapshot,
+    NormalizedProblem, PropagatedProblem, RawProblem, Universe, Variable,
+};
+
+/// Stand-in for a shared constraint, so it can be handed to a new problem
+/// every time
+fn proxy(constraint: &Rc<Constraint>) -> Constraint {
+    let full = constraint.clone();
+    let mut proxy = Constraint::new(
+        constraint.scope.clone(),
+        Box::new(move |vals| (full.evaluate)(vals)),
+    )
+    .with_cost(constraint.cost);
+    if constraint.partial.is_some() {
+        let partial = constraint.clone();
+        proxy = proxy.with_partial(Box::new(move |vals| {
+            (partial.partial.as_ref().unwrap())(vals)
+        }));
+    }
+    proxy.watched.clone_from(&constraint.watched);
+    proxy.kind.clone_from(&constraint.kind);
+    proxy.symmetric = constraint.symmetric;
+    if constraint.function.is_some() {
+        let function = constraint.clone();
+        proxy.function = Some(Box::new(move |vals| {
+            (function.function.as_ref().unwrap())(vals)
+        }));
+    }
+    proxy
+}
+
+impl NormalizedProblem {
+    /// Finds a minimal unsatisfiable subset of the constraints: removing any
+    /// one of them makes the rest satisfiable. Returns their scopes, or
+    /// nothing if the problem is satisfiable. Custom propagators are ignored.
+    ///
+    /// Uses the deletion based algorithm, solving the problem once per
+    /// constraint, so it can be slow
+    pub fn find_mus(mut self) -> Vec<Vec<Variable>> {
+        self.realize(self.variables.clone());
+
+        let all: Vec<Rc<Constraint>> = self
+            .constraints
+            .into_values()
+            .flatten()
+            .map(Rc::new)
+            .collect();
+
+        let satisfiable = |subset: &[Rc<Constraint>]| {
+            let mut problem = RawProblem::new();
+            for domain in &self.domains {
+                problem.add_var(domain.values.clone());
+            }
+            for constraint in subset {
+                problem.push_constraint(proxy(constraint));
+            }
+            problem
+                .normalize_problem()
+                .constraint_propagation()
+                .is_some_and(|problem| problem.solve_cbj().is_some())
+        };
+
+        if satisfiable(&all) {
+            return Vec::new();
+        }
+
+        // Drop every constraint the rest is still unsatisfiable without
+        let mut core = all;
+        let mut i = 0;
+        while i < core.len() {
+            let removed = core.remove(i);
+            if satisfiable(&core) {
+                core.insert(i, removed);
+                i += 1;
+            }
+        }
+
+        let mut scopes: Vec<Vec<Variable>> = core.iter().map(|c| c.scope.clone()).collect();
+        scopes.sort_by(|a, b| cmp_scopes(a, b));
+        scopes
+    }
+
+    /// Finds constraints implied by the others, which can all be removed
+    /// together without changing the solutions. Returns their scopes.
+    /// Custom propagators are ignored.
+    ///
+    /// A constraint is implied if the others together with its negation
+    /// have no solution, so this solves the problem once per constraint and
+    /// can be slow. Each implied constraint found is dropped before checking
+    /// the next one, so two copies of the same constraint aren't both
+    /// reported
+    pub fn find_redundant(mut self) -> Vec<Vec<Variable>> {
+        self.realize(self.variables.clone());
+
+        let mut kept: Vec<Rc<Constraint>> = self
+            .constraints
+            .into_values()
+            .flatten()
+            .map(Rc::new)
+            .collect();
+
+        let mut redundant = Vec::new();
+        let mut i = 0;
+        while i < kept.len() {
+            let mut problem = RawProblem::new();
+            for domain in &self.domains {
+                problem.add_var(domain.values.clone());
+            }
+            for (j, constraint) in kept.iter().enumerate() {
+                if j != i {
+                    problem.push_constraint(proxy(constraint));
+                }
+            }
+            let negated = kept[i].clone();
+            problem.add_constraint(
+                kept[i].scope.clone(),
+                Box::new(move |vals| !(negated.evaluate)(vals)),
+            );
+
+            let violable = problem
+                .normalize_problem()
+                .constraint_propagation()
+                .is_some_and(|problem| problem.solve_cbj().is_some());
+            if violable {
+                i += 1;
+            } else {
+                redundant.push(kept.remove(i).scope.clone());
+            }
+        }
+
+        redundant.sort_by(|a, b| cmp_scopes(a, b));
+        redundant
+    }
+}
+
+impl NormalizedProblem {
+    /// Assigns as many variables as possible while every constraint between
+    /// the assigned ones holds, leaving the rest `None`. Constraints reaching
+    /// an unassigned variable don't count, and neither do custom propagators.
+    ///
+    /// Branch and bound over assigning or skipping each variable, so it is
+    /// exponential like the other exact searches
+    pub fn solve_max_partial(&self) -> Vec<Option<Universe>> {
+        let n = self.variables.len();
+        // Constraints get checked once their last variable is decided
+        let mut by_last: Vec<Vec<(&[Variable], &[Constraint])>> = vec![Vec::new(); n];
+        for (scope, constraints) in &self.constraints {
+            by_last[scope[scope.len() - 1].id].push((scope, constraints));
+        }
+
+        let mut candidate = vec![None; n];
+        let mut best = vec![None; n];
+        let mut best_count = 0;
+        self.max_partial(&by_last, &mut candidate, 0, 0, &mut best, &mut best_count);
+        best
+    }
+    fn max_partial(
+        &self,
+        by_last: &[Vec<(&[Variable], &[Constraint])>],
+        candidate: &mut Candidate,
+        k: usize,
+        assigned: usize,
+        best: &mut Candidate,
+        best_count: &mut usize,
+    ) {
+        // Even assigning all the remaining variables wouldn't beat the best
+        if assigned + (candidate.len() - k) <= *best_count {
+            return;
+        }
+        if k == candidate.len() {
+            *best_count = assigned;
+            best.clone_from(candidate);
+            return;
+        }
+
+        for &val in self.values(k) {
+            candidate[k] = Some(val);
+            let consistent = by_last[k].iter().all(|(scope, constraints)| {
+                let vals: Option<Vec<Universe>> =
+                    scope.iter().map(|var| candidate[var.id]).collect();
+                vals.is_none_or(|vals| Constraint::all_satisfied(constraints, &vals))
+            });
+            if consistent {
+                self.max_partial(by_last, candidate, k + 1, assigned + 1, best, best_count);
+            }
+        }
+        candidate[k] = None;
+        self.max_partial(by_last, candidate, k + 1, assigned, best, best_count);
+    }
+}
+
+/// What [`PropagatedProblem::solve_preferred`] works out before searching
+struct Preferences<'a> {
+    /// Constraints by their last variable, checked once it is assigned
+    by_last: Vec<Vec<(&'a [Variable], &'a [Constraint])>>,
+    /// Cost of each value of each variable
+    costs: Vec<Vec<u64>>,
+    /// Cheapest the variables from each one on can possibly get
+    cheapest_rest: Vec<u64>,
+}
+
+impl PropagatedProblem {
+    /// Finds the solution with the lowest total `cost(var, value)` over its
+    /// variables, for soft preferences between values. Returns that total and
+    /// the solution, the first one in lexicographic order among ties, or
+    /// `None` if there is no solution.
+    ///
+    /// Branch and bound like [`NormalizedProblem::solve_max_partial`], bounding
+    /// with the cheapest value left for each unassigned variable
+    pub fn solve_preferred(
+        &self,
+        cost: impl Fn(Variable, Universe) -> u64,
+    ) -> Option<(u64, Vec<Universe>)> {
+        let n = self.variables.len();
+        let mut by_last: Vec<Vec<(&[Variable], &[Constraint])>> = vec![Vec::new(); n];
+        for (scope, constraints) in &self.constraints {
+            by_last[scope[scope.len() - 1].id].push((scope, constraints));
+        }
+        let costs: Vec<Vec<u64>> = (0..n)
+            .map(|i| {
+                let var = self.variables[i];
+                self.values(i).iter().map(|&val| cost(var, val)).collect()
+            })
+            .collect();
+        let mut cheapest_rest = vec![0; n + 1];
+        for i in (0..n).rev() {
+            let cheapest = costs[i].iter().copied().min().unwrap_or(0);
+            cheapest_rest[i] = cheapest_rest[i + 1] + cheapest;
+        }
+
+        let preferences = Preferences {
+            by_last,
+            costs,
+            cheapest_rest,
+        };
+        let mut candidate = vec![None; n];
+        let mut best = None;
+        self.preferred(&preferences, &mut candidate, 0, 0, &mut best);
+        best
+    }
+    fn preferred(
+        &self,
+        preferences: &Preferences,
+        candidate: &mut Candidate,
+        k: usize,
+        spent: u64,
+        best: &mut Option<(u64, Vec<Universe>)>,
+    ) {
+        let Preferences {
+            by_last,
+            costs,
+            cheapest_rest,
+        } = preferences;
+        // Even the cheapest values for the rest wouldn't beat the best
+        if best
+            .as_ref()
+            .is_some_and(|(best_cost, _)| spent + cheapest_rest[k] >= *best_cost)
+        {
+            return;
+        }
+        if k == candidate.len() {
+            *best = Some((spent, candidate.iter().map(|val| val.unwrap()).collect()));
+            return;
+        }
+
+        for (&val, &cost) in self.values(k).iter().zip(&costs[k]) {
+            candidate[k] = Some(val);
+            let consistent = by_last[k].iter().all(|(scope, constraints)| {
+                let vals: Vec<Universe> =
+                    scope.iter().map(|var| candidate[var.id].unwrap()).collect();
+                Constraint::all_satisfied(constraints, &vals)
+            });
+            if consistent {
+                self.preferred(preferences, candidate, k + 1, spent + cost, best);
+            }
+        }
+        candidate[k] = None;
+    }
+}
+
+/// Value of every variable, at its id, with helpers for reading it. Solvers
+/// return plain vectors, which convert to and from it with `into()`
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+pub struct Solution(pub Vec<Universe>);
+impl Solution {
+    pub fn value_of(&self, var: Variable) -> Universe {
+        self.0[var.id]
+    }
+    /// The values keyed by variable
+    pub fn as_map(&self) -> HashMap<Variable, Universe> {
+        self.0
+            .iter()
+            .enumerate()
+            .map(|(id, &value)| (Variable { id }, value))
+            .collect()
+    }
+    /// See [`PropagatedProblem::verify`]
+    pub fn satisfies(&self, problem: &PropagatedProblem) -> bool {
+        problem.verify(&self.0)
+    }
+}
+impl From<Vec<Universe>> for Solution {
+    fn from(values: Vec<Universe>) -> Self {
+        Solution(values)
+    }
+}
+impl From<Solution> for Vec<Universe> {
+    fn from(solution: Solution) -> Self {
+        solution.0
+    }
+}
+impl Deref for Solution {
+    type Target = [Universe];
+
+    fn deref(&self) -> &[Universe] {
+        &self.0
+    }
+}
+/// Like `x0 = 1, x1 = 3`
+impl Display for Solution {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        for (id, value) in self.0.iter().enumerate() {
+            if id > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "x{id} = {value}")?;
+        }
+        Ok(())
+    }
+}
+
+/// The variables two solutions of the same problem disagree on, with the
+/// value in `a` and then in `b`
+pub fn diff_solutions(a: &[Universe], b: &[Universe]) -> Vec<(Variable, Universe, Universe)> {
+    assert_eq!(a.len(), b.len());
+    a.iter()
+        .zip(b)
+        .enumerate()
+        .filter(|(_, (va, vb))| va != vb)
+        .map(|(id, (&va, &vb))| (Variable { id }, va, vb))
+        .collect()
+}
+
+/// Something backtracking did, see
+/// [`PropagatedProblem::solve_backtracking_traced`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SearchEvent {
+    /// Tried `value` for `var`, before checking the constraints
+    Assign { var: Variable, value: Universe },
+    /// The last value tried for `var` led to no solution
+    DeadEnd { var: Variable },
+}
+
+/// Callbacks watching a backtracking search
+#[derive(Default)]
+struct Observers<'a> {
+    progress: Option<&'a mut dyn FnMut(f64)>,
+    trace: Option<&'a mut dyn FnMut(SearchEvent)>,
+}
+
+/// Counters of how much work a search did
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct SearchStats {
+    /// Partial assignments visited
+    pub nodes: u64,
+    /// Partial assignments that turned out to be dead ends
+    pub backtracks: u64,
+}
+
+/// Values assigned so far during search, `None` for unassigned variables
+pub(crate) trait Assignment {
+    fn unassigned(len: usize) -> Self;
+    fn len(&self) -> usize;
+    fn get(&self, i: usize) -> Option<Universe>;
+    fn set(&mut self, i: usize, val: Option<Universe>);
+}
+impl Assignment for Candidate {
+    fn unassigned(len: usize) -> Self {
+        vec![None; len]
+    }
+    fn len(&self) -> usize {
+        Vec::len(self)
+    }
+    fn get(&self, i: usize) -> Option<Universe> {
+        self[i]
+    }
+    fn set(&mut self, i: usize, val: Option<Universe>) {
+        self[i] = val;
+    }
+}
+
+/// Candidate with a byte per variable, much smaller than [`Candidate`] but
+/// only usable when every value is in `0..PackedCandidate::UNASSIGNED`
+pub(crate) struct PackedCandidate(Vec<u8>);
+impl PackedCandidate {
+    const UNASSIGNED: u8 = u8::MAX;
+
+    fn fits(val: Universe) -> bool {
+        (0..Self::UNASSIGNED as Universe).contains(&val)
+    }
+}
+impl Assignment for PackedCandidate {
+    fn unassigned(len: usize) -> Self {
+        PackedCandidate(vec![Self::UNASSIGNED; len])
+    }
+    fn len(&self) -> usize {
+        self.0.len()
+    }
+    fn get(&self, i: usize) -> Option<Universe> {
+        (self.0[i] != Self::UNASSIGNED).then(|| self.0[i].into())
+    }
+    fn set(&mut self, i: usize, val: Option<Universe>) {
+        self.0[i] = val.map_or(Self::UNASSIGNED, |val| val as u8);
+    }
+}
+
+/// Order backtracking tries the values of each variable in. It is kept apart
+/// from the domains, which stay sorted so values can be found by binary search
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ValueOrder {
+    /// Indexed by variable, `None` (or missing) tries the values ascending
+    orders: Vec<Option<Permutation>>,
+}
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Permutation {
+    /// Indices into the sorted domain, in the order they are tried
+    order: Vec<usize>,
+    /// Inverse of `order`: when each index of the domain is tried
+    rank: Vec<usize>,
+}
+impl Permutation {
+    fn new(order: Vec<usize>) -> Self {
+        let mut rank = vec![usize::MAX; order.len()];
+        for (i, &index) in order.iter().enumerate() {
+            rank[index] = i;
+        }
+        assert!(rank.iter().all(|&i| i != usize::MAX), "not a permutation");
+        Self { order, rank }
+    }
+}
+impl ValueOrder {
+    /// Every variable's values ascending, the way the domains are stored
+    pub fn ascending() -> Self {
+        Self::default()
+    }
+    /// Tries the values of every variable by increasing `key`, ties ascending
+    pub fn by_key<K: Ord>(
+        problem: &PropagatedProblem,
+        mut key: impl FnMut(Variable, Universe) -> K,
+    ) -> Self {
+        let orders = (0..problem.variables.len())
+            .map(|i| {
+                let var = problem.variables[i];
+                let values = problem.values(i);
+                let mut order: Vec<usize> = (0..values.len()).collect();
+                order.sort_by_cached_key(|&index| key(var, values[index]));
+                Some(Permutation::new(order))
+            })
+            .collect();
+        Self { orders }
+    }
+    /// Values closest to the middle of each domain first, ties ascending,
+    /// like for placing queens where the center columns attack the most
+    pub fn middle_first(problem: &PropagatedProblem) -> Self {
+        Self::by_key(problem, |var, value| {
+            let values = problem.values(var.id);
+            let (lo, hi) = (values[0] as i64, values[values.len() - 1] as i64);
+            (2 * value as i64 - lo - hi).abs()
+        })
+    }
+    /// Least constraining value first: the values with the most supports in
+    /// the domains of the variables they share a binary constraint with
+    pub fn least_constraining(problem: &PropagatedProblem) -> Self {
+        let mut supports: Vec<Vec<usize>> = (0..problem.variables.len())
+            .map(|i| vec![0; problem.values(i).len()])
+            .collect();
+        for (scope, constraints) in &problem.constraints {
+            let &[a, b] = scope.as_slice() else {
+                continue;
+            };
+            for (i, &va) in problem.values(a.id).iter().enumerate() {
+                for (j, &vb) in problem.values(b.id).iter().enumerate() {
+                    if Constraint::all_satisfied(constraints, &[va, vb]) {
+                        supports[a.id][i] += 1;
+                        supports[b.id][j] += 1;
+                    }
+                }
+            }
+        }
+
+        Self::by_key(problem, |var, value| {
+            let index = problem.values(var.id).binary_search(&value).unwrap();
+            Reverse(supports[var.id][index])
+        })
+    }
+    /// Tries the values of `var` in the order of `values`, which must have
+    /// every value of its domain once
+    pub fn set(&mut self, problem: &PropagatedProblem, var: Variable, values: &[Universe]) {
+        let domain = problem.values(var.id);
+        assert_eq!(values.len(), domain.len());
+        let order = values
+            .iter()
+            .map(|value| {
+                domain
+                    .binary_search(value)
+                    .expect("value not in the domain")
+            })
+            .collect();
+
+        if self.orders.len() <= var.id {
+            self.orders.resize(var.id + 1, None);
+        }
+        self.orders[var.id] = Some(Permutation::new(order));
+    }
+
+    /// Index of the first value of the `var`th variable to try
+    fn first(&self, var: usize) -> usize {
+        match self.orders.get(var) {
+            Some(Some(permutation)) => permutation.order[0],
+            _ => 0,
+        }
+    }
+    /// How many values of the `var`th variable are tried before the one at
+    /// `index`
+    fn rank(&self, var: usize, index: usize) -> usize {
+        match self.orders.get(var) {
+            Some(Some(permutation)) => permutation.rank[index],
+            _ => index,
+        }
+    }
+    /// Index of the value tried after the one at `index`, out of `len`
+    fn after(&self, var: usize, index: usize, len: usize) -> Option<usize> {
+        match self.orders.get(var) {
+            Some(Some(permutation)) => permutation.order.get(permutation.rank[index] + 1).copied(),
+            _ => (index + 1 < len).then_some(index + 1),
+        }
+    }
+}
+
+// Based on https://en.wikipedia.org/wiki/Backtracking and https://www.geeksforgeeks.org/sudoku-backtracking-7/
+impl PropagatedProblem {
+    pub fn solve_backtracking(&self) -> Option<Vec<Universe>> {
+        self.solve_backtracking_stats().0
+    }
+    /// Same as `solve_backtracking`, also returning the scope of every
+    /// constraint checked against the solution, for an independent check
+    /// with [`Self::verify_certificate`]
+    pub fn solve_with_certificate(&self) -> Option<(Vec<Universe>, Vec<Vec<Variable>>)> {
+        let solution = self.solve_backtracking()?;
+        let mut certificate = Vec::new();
+        for (scope, constraints) in &self.constraints {
+            let vals: Vec<Universe> = scope.iter().map(|var| solution[var.id]).collect();
+            for constraint in constraints {
+                if !Constraint::all_satisfied(core::slice::from_ref(constraint), &vals) {
+                    return None;
+                }
+                certificate.push(scope.clone());
+            }
+        }
+        Some((solution, certificate))
+    }
+    /// Same as `solve_backtracking`, with the solution keyed by variable
+    /// instead of indexed by id
+    pub fn solve_map(&self) -> Option<HashMap<Variable, Universe>> {
+        self.solve_backtracking()
+            .map(|solution| Solution(solution).as_map())
+    }
+    /// Same as `solve_backtracking`, also returning how much searching it took
+    pub fn solve_backtracking_stats(&self) -> (Option<Vec<Universe>>, SearchStats) {
+        // Lazy domains aren't generated yet, so they can't be checked
+        let packable = self.lazy_domains.is_empty()
+            && self
+                .domains
+                .iter()
+                .all(|dom| dom.values.iter().all(|&val| PackedCandidate::fits(val)));
+        if packable {
+            self.solve_backtracking_with::<PackedCandidate>()
+        } else {
+            self.solve_backtracking_with::<Candidate>()
+        }
+    }
+    /// Same as `solve_backtracking`, trying the values in the given order
+    pub fn solve_backtracking_ordered(&self, order: &ValueOrder) -> Option<Vec<Universe>> {
+        self.solve_backtracking_guarded::<Candidate>(order, &|_| true, Observers::default())
+            .0
+    }
+    pub(crate) fn solve_backtracking_with<C: Assignment>(
+        &self,
+    ) -> (Option<Vec<Universe>>, SearchStats) {
+        self.solve_backtracking_guarded::<C>(
+            &ValueOrder::ascending(),
+            &|_| true,
+            Observers::default(),
+        )
+    }
+    /// Backtracking search that also prunes every partial assignment for
+    /// which `guard` returns false, after the constraints have accepted it.
+    /// Unassigned variables are `None` in the slice passed to the guard
+    pub fn solve_backtracking_with_guard(
+        &self,
+        guard: impl Fn(&[Option<Universe>]) -> bool,
+    ) -> Option<Vec<Universe>> {
+        self.solve_backtracking_guarded::<Candidate>(
+            &ValueOrder::ascending(),
+            &|candidate| guard(candidate),
+            Observers::default(),
+        )
+        .0
+    }
+    /// Same as `solve_backtracking`, calling `on_progress` with an estimate
+    /// of the fraction of the search space explored so far at every node.
+    /// The estimate never decreases and is 1 once the search is exhausted.
+    ///
+    /// Each variable on the current path has consumed a fraction of its
+    /// values, and that counts in proportion to the share of the search
+    /// space under the values above it, assuming the tree is uniform
+    pub fn solve_backtracking_with_progress(
+        &self,
+        mut on_progress: impl FnMut(f64),
+    ) -> Option<Vec<Universe>> {
+        let solution = self
+            .solve_backtracking_guarded::<Candidate>(
+                &ValueOrder::ascending(),
+                &|_| true,
+                Observers {
+                    progress: Some(&mut on_progress),
+                    ..Default::default()
+                },
+            )
+            .0;
+        if solution.is_none() {
+            on_progress(1.0);
+        }
+        solution
+    }
+    /// Same as [`Self::solve_backtracking_ordered`], calling `on_event` with
+    /// every value tried and every dead end, in the order they happen
+    pub fn solve_backtracking_traced(
+        &self,
+        order: &ValueOrder,
+        mut on_event: impl FnMut(SearchEvent),
+    ) -> Option<Vec<Universe>> {
+        self.solve_backtracking_guarded::<Candidate>(
+            order,
+            &|_| true,
+            Observers {
+                trace: Some(&mut on_event),
+                ..Default::default()
+            },
+        )
+        .0
+    }
+    /// The tree [`Self::solve_backtracking`] explores as a Graphviz DOT
+    /// graph. Nodes are partial assignments, below the root with nothing
+    /// assigned, and each edge is labeled with the value it assigns. Leaves
+    /// are marked as a failure or as the solution found. The search is cut
+    /// short after [`DOT_NODE_LIMIT`] nodes, adding a `...` node
+    pub fn solve_to_dot(&self) -> String {
+        let mut dot = "digraph search {\n    n0 [label=\"\"];\n".to_owned();
+        // Nodes from the root to the one being explored, with whether they
+        // have children
+        let mut path = vec![(0, false)];
+        let mut nodes = 1;
+        let truncated = Cell::new(false);
+        let mut on_event = |event| {
+            if truncated.get() {
+                return;
+            }
+            match event {
+                SearchEvent::Assign { var, value } if nodes == DOT_NODE_LIMIT => {
+                    truncated.set(true);
+                    let parent = path.last().unwrap().0;
+                    dot += "    truncated [label=\"...\", shape=none];\n";
+                    dot += &format!(
+                        "    n{parent} -> truncated [label=\"x{} = {value}\"];\n",
+                        var.id
+                    );
+                }
+                SearchEvent::Assign { var, value } => {
+                    let parent = path.last_mut().unwrap();
+                    parent.1 = true;
+                    dot += &format!("    n{nodes} [label=\"\"];\n");
+                    dot += &format!(
+                        "    n{} -> n{nodes} [label=\"x{} = {value}\"];\n",
+                        parent.0, var.id
+                    );
+                    path.push((nodes, false));
+                    nodes += 1;
+                }
+                SearchEvent::DeadEnd { .. } => {
+                    let (node, has_children) = path.pop().unwrap();
+                    if !has_children {
+                        dot += &format!("    n{node} [label=\"fail\", color=red];\n");
+                    }
+                }
+            }
+        };
+
+        let solution = self
+            .solve_backtracking_guarded::<Candidate>(
+                &ValueOrder::default(),
+                &|_| !truncated.get(),
+                Observers {
+                    trace: Some(&mut on_event),
+                    ..Default::default()
+                },
+            )
+            .0;
+        if solution.is_some() {
+            let node = path.last().unwrap().0;
+            dot += &format!("    n{node} [label=\"solution\", color=green];\n");
+        }
+        dot += "}\n";
+        dot
+    }
+    fn solve_backtracking_guarded<C: Assignment>(
+        &self,
+        order: &ValueOrder,
+        guard: &dyn Fn(&C) -> bool,
+        mut observers: Observers,
+    ) -> (Option<Vec<Universe>>, SearchStats) {
+        let mut candidate = C::unassigned(self.variables.len());
+        let mut stats = SearchStats::default();
+        if self.backtrack(&mut candidate, 0, order, guard, &mut stats, &mut observers) {
+            let solution = (0..candidate.len()).map(|i| candidate.get(i)).collect();
+            (solution, stats)
+        } else {
+            (None, stats)
+        }
+    }
+    fn backtrack<C: Assignment>(
+        &self,
+        candidate: &mut C,
+        k: usize,
+        order: &ValueOrder,
+        guard: &dyn Fn(&C) -> bool,
+        stats: &mut SearchStats,
+        observers: &mut Observers,
+    ) -> bool {
+        // for _ in 0..k {
+        //     print!("-");
+        // }
+        // println!();
+        stats.nodes += 1;
+        if let Some(on_progress) = &mut observers.progress {
+            on_progress(self.explored_fraction(candidate, k, order));
+        }
+        // The variable assigned last, whose value this node checks
+        let assigned = k.checked_sub(1).map(|i| self.variables[i]);
+        let dead_end = |observers: &mut Observers| {
+            if let (Some(var), Some(trace)) = (assigned, &mut observers.trace) {
+                trace(SearchEvent::DeadEnd { var });
+            }
+        };
+        if let (Some(var), Some(trace)) = (assigned, &mut observers.trace) {
+            let value = candidate.get(var.id).unwrap();
+            trace(SearchEvent::Assign { var, value });
+        }
+
+        if self.reject(candidate, k) || !guard(candidate) {
+            stats.backtracks += 1;
+            dead_end(observers);
+            return false;
+        }
+        if self.accept(candidate) {
+            return true;
+        }
+
+        if let Some(value) = self.determined_value(candidate, k) {
+            if self.values(k).binary_search(&value).is_ok() {
+                candidate.set(k, Some(value));
+                if self.backtrack(candidate, k + 1, order, guard, stats, observers) {
+                    return true;
+                }
+            }
+            candidate.set(k, None);
+            stats.backtracks += 1;
+            dead_end(observers);
+            return false;
+        }
+
+        let mut s = self.first(candidate, k, order);
+        while s {
+            let res = self.backtrack(candidate, k + 1, order, guard, stats, observers);
+            if res {
+                return true;
+            }
+
+            s = self.next(candidate, k + 1, order);
+        }
+
+        candidate.set(k, None);
+        stats.backtracks += 1;
+        dead_end(observers);
+        false
+    }
+    /// Estimate of how much of the search tree comes before the path to the
+    /// first `k` variables, see [`Self::solve_backtracking_with_progress`]
+    fn explored_fraction<C: Assignment>(&self, candidate: &C, k: usize, order: &ValueOrder) -> f64 {
+        let mut explored = 0.0;
+        let mut share = 1.0;
+        for var in 0..k {
+            let values = self.values(var);
+            // Values found by a functional constraint may not be in the domain
+            let Ok(index) = values.binary_search(&candidate.get(var).unwrap()) else {
+                continue;
+            };
+            share /= values.len() as f64;
+            explored += order.rank(var, index) as f64 * share;
+        }
+        explored
+    }
+    /// Value of variable `k` if some functional constraint already determines
+    /// it from the assigned variables
+    fn determined_value<C: Assignment>(&self, candidate: &C, k: usize) -> Option<Universe> {
+        let var = self.variables[k];
+        let (scope, function) = self
+            .constraints
+            .iter()
+            .filter(|(scope, _)| scope.len() > 1 && scope.last() == Some(&var))
+            .find_map(|(scope, constraints)| {
+                let function = constraints.iter().find_map(|c| c.function.as_ref())?;
+                Some((scope, function))
+            })?;
+
+        let mut args = scope[..scope.len() - 1]
+            .iter()
+            .map(|var| candidate.get(var.id).unwrap());
+        Some(function(&mut args))
+    }
+    /// Returns true if candidate values are inconsistent with constraints
+    fn reject<C: Assignment>(&self, candidate: &C, k: usize) -> bool {
+        // let k = candidate.len();
+        if k == 0 {
+            return false;
+        }
+
+        let curr_var = self.variables[k - 1];
+
+        let to_check = self
+            .constraints
+            .iter()
+            .filter(|constraint| constraint.0.last() == Some(&curr_var));
+
+        for (scope, constraints) in to_check {
+            let vals_needed: Vec<Universe> = scope
+                .iter()
+                .map(|var| candidate.get(var.id).unwrap())
+                .collect();
+            if !Constraint::all_satisfied(constraints, &vals_needed) {
+                return true;
+            }
+        }
+
+        // Constraints with some variables still unassigned
+        let to_check_partially = self.constraints.iter().filter(|(scope, constraints)| {
+            scope.last().unwrap().id > curr_var.id
+                && scope.contains(&curr_var)
+                && constraints.iter().any(|c| c.watches(curr_var))
+        });
+
+        for (scope, constraints) in to_check_partially {
+            let vals_needed: Candidate = scope
+                .iter()
+                .map(|var| {
+                    if var.id < k {
+                        candidate.get(var.id)
+                    } else {
+                        None
+                    }
+                })
+                .collect();
+            let mut watching = constraints.iter().filter(|c| c.watches(curr_var));
+            if !watching.all(|c| c.partially_satisfied(&vals_needed)) {
+                return true;
+            }
+        }
+
+        false
+    }
+    /// Returns true if candidate values are consistent and complete with constraints
+    fn accept<C: Assignment>(&self, candidate: &C) -> bool {
+        // With no variables the empty assignment is already complete
+        candidate
+            .len()
+            .checked_sub(1)
+            .is_none_or(|last| candidate.get(last).is_some())
+    }
+    fn first<C: Assignment>(&self, candidate: &mut C, k: usize, order: &ValueOrder) -> bool {
+        // let k = candidate.len();
+        if candidate.len() > 0 && candidate.get(candidate.len() - 1).is_some() {
+            false
+        } else if self.values(k).is_empty() {
+            // A lazy domain propagation didn't generate can turn out empty
+            false
+        } else {
+            let first_val_next_var = self.values(k)[order.first(k)];
+            // let mut next_cand = candidate.clone();
+            // next_cand.push(first_val_next_var);
+            // Some(next_cand)
+            candidate.set(k, Some(first_val_next_var));
+
+            true
+        }
+    }
+    fn next<C: Assignment>(&self, candidate: &mut C, k: usize, order: &ValueOrder) -> bool {
+        // let k = candidate.len();
+        let values = self.values(k - 1);
+        let curr_val = candidate.get(k - 1).unwrap();
+        let i = values.binary_search(&curr_val).unwrap();
+        match order.after(k - 1, i, values.len()) {
+            Some(j) => {
+                candidate.set(k - 1, Some(values[j]));
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+// CBJ based on https://cse.unl.edu/~choueiry/Documents/Hybrid-Prosser.pdf
+// (HYBRID ALGORITHMS FOR THE CONSTRAINT SATISFACTION PROBLEM PATRICK PROSS)
+// impl PropagatedProblem {
+//     pub fn solve_cbj(&mut self) -> Option<Vec<Universe>> {
+//         let mut vals = vec![0; self.variables.len()];
+//         let mut current_domain = self.domains.iter().map(|dom| dom.values.clone()).collect();
+//         let mut conf_set: Vec<HashSet<usize>> = vec![HashSet::new(); self.variables.len()];
+//         let mut status = Status::Unknown;
+
+//         self.cbj_bcssp(&mut vals, &mut current_domain, &mut conf_set, &mut status);
+
+//         if status == Status::Solution {
+//             Some(vals)
+//         } else {
+//             None
+//         }
+//     }
+
+//     fn cbj_bcssp(
+//         &mut self,
+//         vals: &mut Vec<Universe>,
+//         current_domain: &mut Vec<Vec<Universe>>,
+//         conf_set: &mut Vec<HashSet<usize>>,
+//         status: &mut Status,
+//     ) {
+//         let mut consistent = true;
+//         *status = Status::Unknown;
+//         let mut i = 0;
+//         let n = self.variables.len();
+
+//         while *status == Status::Unknown {
+//             if consistent {
+//                 i = self.cbj_label(i, vals, current_domain, conf_set, &mut consistent);
+//             } else {
+//                 i = self.cbj_unlabel(i, &mut consistent);
+//             }
+//             if i >= n {
+//                 *status = Status::Solution;
+//             } else if i == 0 {
+//                 *status = Status::Impossible;
+//             }
+//         }
+//     }
+//     fn cbj_label(
+//         &self,
+//         i: usize,
+//         vals: &mut Vec<Universe>,
+//         current_domain: &mut Vec<Vec<Universe>>,
+//         conf_set: &mut Vec<HashSet<usize>>,
+//         consistent: &mut bool,
+//     ) -> usize {
+//         *consistent = false;
+//         for &val in &current_domain[i] {
+//             vals[i] = val;
+//         }
+
+//         todo!()
+//     }
+//     fn cbj_unlabel(&self, i: usize, consistent: &mut bool) -> usize {}
+// }
+
+// #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+// enum Status {
+//     Unknown,
+//     Solution,
+//     Impossible,
+// }
+
+/// How often each constraint failed during conflict-directed backjumping,
+/// keyed by scope, for wdeg-style variable ordering: the variables in the
+/// heaviest constraints get assigned first. Weights can be set up front or
+/// learned from earlier runs, see [`PropagatedProblem::solve_cbj_weighted`]
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ConstraintWeights {
+    weights: HashMap<Vec<Variable>, u64>,
+}
+impl ConstraintWeights {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Weight of the constraints over `scope`, 0 until set or bumped
+    pub fn weight(&self, scope: &[Variable]) -> u64 {
+        self.weights.get(scope).copied().unwrap_or(0)
+    }
+    pub fn set(&mut self, scope: Vec<Variable>, weight: u64) {
+        self.weights.insert(scope, weight);
+    }
+    fn bump(&mut self, scope: &[Variable]) {
+        *self.weights.entry(scope.to_vec()).or_default() += 1;
+    }
+    /// Ids of the variables by decreasing total weight of their constraints,
+    /// ties by lowest id, so without weights it is the order of the ids
+    fn order(&self, problem: &PropagatedProblem) -> Vec<usize> {
+        let mut degrees = vec![0; problem.variables.len()];
+        for (scope, _) in &problem.constraints {
+            for var in scope {
+                degrees[var.id] += self.weight(scope);
+            }
+        }
+        let mut order: Vec<usize> = (0..degrees.len()).collect();
+        order.sort_by_key(|&id| Reverse(degrees[id]));
+        order
+    }
+}
+
+/// A check [`PropagatedProblem::solve_cbj`] runs when assigning the variable
+/// at some position of the order
+struct CbjCheck<'a> {
+    scope: &'a [Variable],
+    constraints: &'a [Constraint],
+    /// Positions of the variables of the scope assigned before, which are to
+    /// blame if it fails
+    earlier: Vec<usize>,
+    /// Some variables of the scope come later, so only the partial
+    /// evaluations can run
+    partial: bool,
+    /// Whether each variable of the scope is assigned by then
+    assigned: Vec<bool>,
+}
+
+/// What a conflict-directed backjumping search keeps track of besides the
+/// assignment
+#[derive(Default)]
+struct CbjHooks<'a> {
+    activity: Option<&'a mut Activity>,
+    weights: Option<&'a mut ConstraintWeights>,
+    stats: SearchStats,
+}
+
+// Based on https://ics.uci.edu/~dechter/books/chapter06.pdf figure 6.7
+impl PropagatedProblem {
+    pub fn solve_cbj(&self) -> Option<Vec<Universe>> {
+        self.solve_cbj_stats().0
+    }
+    /// Same as `solve_cbj`, also returning how much searching it took
+    pub fn solve_cbj_stats(&self) -> (Option<Vec<Universe>>, SearchStats) {
+        self.solve_cbj_hooked(CbjHooks::default())
+    }
+    /// Same as `solve_cbj`, bumping the activity of the variables in every
+    /// conflict it runs into
+    pub fn solve_cbj_with_activity(&self, activity: &mut Activity) -> Option<Vec<Universe>> {
+        self.solve_cbj_hooked(CbjHooks {
+            activity: Some(activity),
+            ..Default::default()
+        })
+        .0
+    }
+    /// Same as `solve_cbj_stats`, but assigning the variables in the heaviest
+    /// constraints of `weights` first, and bumping the weight of every
+    /// constraint that fails. Running it again with the same weights starts
+    /// with the variables that caused trouble before. Backjumps still go to
+    /// the latest culprit, so no solution is skipped
+    pub fn solve_cbj_weighted(
+        &self,
+        weights: &mut ConstraintWeights,
+    ) -> (Option<Vec<Universe>>, SearchStats) {
+        self.solve_cbj_hooked(CbjHooks {
+            weights: Some(weights),
+            ..Default::default()
+        })
+    }
+    fn solve_cbj_hooked(&self, mut hooks: CbjHooks) -> (Option<Vec<Universe>>, SearchStats) {
+        let mut first = None;
+        self.cbj(&mut hooks, |solution| {
+            first = Some(solution);
+            false
+        });
+        (first, hooks.stats)
+    }
+    /// Every solution, found with conflict-directed backjumping. Each
+    /// variable tries its largest values first, so the solutions come in
+    /// decreasing lexicographic order
+    pub fn solve_cbj_all(&self) -> Vec<Vec<Universe>> {
+        let mut all = Vec::new();
+        self.cbj(&mut CbjHooks::default(), |solution| {
+            all.push(solution);
+            true
+        });
+        all
+    }
+    /// Calls `on_solution` with every solution until it returns false
+    fn cbj(&self, hooks: &mut CbjHooks, mut on_solution: impl FnMut(Vec<Universe>) -> bool) {
+        let mut i: usize = 0;
+        let n = self.variables.len();
+        if n == 0 {
+            on_solution(Vec::new());
+            return;
+        }
+        // Variable assigned at each position, and the other way around
+        let order = match &hooks.weights {
+            Some(weights) => weights.order(self),
+            None => (0..n).collect(),
+        };
+        let mut position = vec![0; n];
+        for (i, &var) in order.iter().enumerate() {
+            position[var] = i;
+        }
+        let checks = self.cbj_checks(&position);
+
+        let mut curr_domain: Vec<Vec<Universe>> = vec![Vec::new(); n];
+        self.values(order[0]).clone_into(&mut curr_domain[0]);
+        // Positions of the culprits. Only ever searched or maxed, so the
+        // set's order doesn't matter
+        let mut conf_set: Vec<HashSet<usize>> = vec![HashSet::new(); n];
+        let mut vals: Candidate = vec![None; n];
+
+        loop {
+            vals[order[i]] = Self::select_val_cbj(
+                order[i],
+                &checks[i],
+                &mut curr_domain[i],
+                &mut conf_set[i],
+                &mut vals,
+                hooks,
+            );
+
+            if vals[order[i]].is_none() {
+                let i_prev = i;
+                // Must jump to the most recently assigned culprit, the one at
+                // the latest position. Jumping further back (e.g. to the
+                // culprit that conflicts most often) would skip the untried
+                // values of the variables in between, and with them possibly
+                // every solution
+                let max = conf_set[i].iter().max();
+                if let Some(&max) = max {
+                    hooks.stats.backtracks += 1;
+                    i = max;
+                    let b = conf_set[i_prev].clone();
+                    conf_set[i].extend(&b);
+                    conf_set[i].remove(&i);
+                } else {
+                    return;
+                }
+            } else if i + 1 == n {
+                let solution = vals.iter().map(|val| val.unwrap()).collect();
+                if !on_solution(solution) {
+                    return;
+                }
+                // Every variable now has a solution below its current value,
+                // so none of them can be jumped over anymore: blaming all the
+                // earlier variables makes each jump a chronological step back.
+                // The last variable keeps trying its remaining values
+                for (j, set) in conf_set.iter_mut().enumerate() {
+                    set.extend(0..j);
+                }
+            } else {
+                i += 1;
+                self.values(order[i]).clone_into(&mut curr_domain[i]);
+                conf_set[i].clear();
+            }
+        }
+    }
+    /// The checks to run when assigning the variable at each position, from
+    /// the ones whose latest culprit is earliest, so the backjumps go as far
+    /// as they can. Constraints missing some values are checked with their
+    /// partial evaluations
+    fn cbj_checks(&self, position: &[usize]) -> Vec<Vec<CbjCheck<'_>>> {
+        let mut checks: Vec<Vec<CbjCheck>> = (0..position.len()).map(|_| Vec::new()).collect();
+        for (scope, constraints) in &self.constraints {
+            if scope.len() < 2 {
+                continue;
+            }
+            let mut positions: Vec<usize> = scope.iter().map(|var| position[var.id]).collect();
+            positions.sort_unstable();
+            let has_partial = constraints.iter().any(|c| c.partial.is_some());
+            for j in 1..positions.len() {
+                let partial = j + 1 < positions.len();
+                if partial && !has_partial {
+                    continue;
+                }
+                checks[positions[j]].push(CbjCheck {
+                    scope,
+                    constraints,
+                    earlier: positions[..j].to_vec(),
+                    partial,
+                    assigned: scope
+                        .iter()
+                        .map(|var| position[var.id] <= positions[j])
+                        .collect(),
+                });
+            }
+        }
+        for checks in &mut checks {
+            // Stable, so same culprits keep the order of the constraints
+            checks.sort_by_key(|check| (check.earlier.last().copied(), check.partial));
+        }
+        checks
+    }
+
+    /// Tries the values left in `domain` for `var` until one passes
+    /// `checks`, adding the culprits of the ones that don't to `conflicts`
+    fn select_val_cbj(
+        var: usize,
+        checks: &[CbjCheck],
+        domain: &mut Vec<Universe>,
+        conflicts: &mut HashSet<usize>,
+        vals: &mut Candidate,
+        hooks: &mut CbjHooks,
+    ) -> Option<Universe> {
+        while let Some(a) = domain.pop() {
+            hooks.stats.nodes += 1;
+            vals[var] = Some(a);
+            let broken = checks.iter().find(|check| {
+                if check.partial {
+                    let vals_needed: Candidate = check
+                        .scope
+                        .iter()
+                        .zip(&check.assigned)
+                        .map(|(v, &assigned)| if assigned { vals[v.id] } else { None })
+                        .collect();
+                    !Constraint::all_partially_satisfied(check.constraints, &vals_needed)
+                } else {
+                    let vals_needed: Vec<Universe> =
+                        check.scope.iter().map(|v| vals[v.id].unwrap()).collect();
+                    !Constraint::all_satisfied(check.constraints, &vals_needed)
+                }
+            });
+
+            let Some(broken) = broken else {
+                return Some(a);
+            };
+            hooks.stats.backtracks += 1;
+            if let Some(activity) = hooks.activity.as_deref_mut() {
+                activity.bump(broken.scope);
+            }
+            if let Some(weights) = hooks.weights.as_deref_mut() {
+                weights.bump(broken.scope);
+            }
+            // Only the variables assigned before can be to blame
+            conflicts.extend(&broken.earlier);
+        }
+
+        None
+    }
+}
+
+/// How often each variable was in a conflict, with recent conflicts counting
+/// more, for VSIDS-style variable ordering like in SAT solvers
+#[derive(Debug, Clone, PartialEq)]
+pub struct Activity {
+    scores: Vec<f64>,
+    increment: f64,
+    decay: f64,
+}
+impl Activity {
+    /// Every score starts at 0. After each conflict the older scores are
+    /// worth `decay` (in `(0, 1]`) times as much, so 1 never forgets
+    pub fn new(num_vars: usize, decay: f64) -> Self {
+        assert!(decay > 0.0 && decay <= 1.0);
+        Self {
+            scores: vec![0.0; num_vars],
+            increment: 1.0,
+            decay,
+        }
+    }
+    pub fn score(&self, var: Variable) -> f64 {
+        self.scores[var.id]
+    }
+    /// Variables from most to least active, ties by lowest id. This is the
+    /// order the search would branch on them in
+    pub fn order(&self) -> Vec<Variable> {
+        let mut order: Vec<Variable> = (0..self.scores.len()).map(|id| Variable { id }).collect();
+        order.sort_by(|a, b| self.scores[b.id].total_cmp(&self.scores[a.id]));
+        order
+    }
+    fn bump(&mut self, vars: &[Variable]) {
+        for var in vars {
+            self.scores[var.id] += self.increment;
+        }
+        // Growing the later bumps is the same as decaying every score
+        self.increment /= self.decay;
+        if self.increment > 1e100 {
+            for score in &mut self.scores {
+                *score *= 1e-100;
+            }
+            self.increment *= 1e-100;
+        }
+    }
+    /// Most active unassigned variable, the lowest id on ties
+    fn pick(&self, candidate: &Candidate) -> Option<usize> {
+        (0..candidate.len())
+            .filter(|&i| candidate[i].is_none())
+            .fold(None, |best, i| match best {
+                Some(best) if self.scores[best] >= self.scores[i] => Some(best),
+                _ => Some(i),
+            })
+    }
+}
+
+impl PropagatedProblem {
+    /// Backtracking that always branches on the most active unassigned
+    /// variable, bumping the variables of every constraint that fails.
+    /// `activity` can come from an earlier search, like
+    /// [`PropagatedProblem::solve_cbj_with_activity`], and keeps the scores
+    /// after this one
+    pub fn solve_vsids(&self, activity: &mut Activity) -> Option<Vec<Universe>> {
+        let mut candidate = vec![None; self.variables.len()];
+        if self.vsids(&mut candidate, activity) {
+            candidate.into_iter().collect()
+        } else {
+            None
+        }
+    }
+    fn vsids(&self, candidate: &mut Candidate, activity: &mut Activity) -> bool {
+        let Some(var) = activity.pick(candidate) else {
+            return true;
+        };
+
+        for &value in self.values(var) {
+            candidate[var] = Some(value);
+            match self.broken_constraint_on(candidate, var) {
+                Some(scope) => activity.bump(scope),
+                None => {
+                    if self.vsids(candidate, activity) {
+                        return true;
+                    }
+                }
+            }
+        }
+        candidate[var] = None;
+        false
+    }
+    /// Scope of a constraint on the `var`th variable that the candidate
+    /// already breaks, checking partially assigned ones with their partial
+    /// evaluation
+    fn broken_constraint_on(&self, candidate: &Candidate, var: usize) -> Option<&[Variable]> {
+        self.constraints.iter().find_map(|(scope, constraints)| {
+            if !scope.iter().any(|v| v.id == var) {
+                return None;
+            }
+            let vals: Candidate = scope.iter().map(|v| candidate[v.id]).collect();
+            let satisfied = match vals.iter().copied().collect::<Option<Vec<_>>>() {
+                Some(vals) => Constraint::all_satisfied(constraints, &vals),
+                None => Constraint::all_partially_satisfied(constraints, &vals),
+            };
+            (!satisfied).then_some(scope.as_slice())
+        })
+    }
+}
+
+/// How the search picks the next variable to branch on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum VariableOrder {
+    /// By id, like [`PropagatedProblem::solve_backtracking`]
+    #[default]
+    Static,
+    /// The variable with the fewest values left (minimum remaining values)
+    Mrv,
+    /// A random variable, picked with the given seed with probability
+    /// inversely proportional to how many values it has left. Like `Mrv` but
+    /// with some randomness, so solvers with different seeds search
+    /// differently
+    WeightedRandom(u64),
+}
+
+/// One of `vars`, with probability inversely proportional to its domain size
+pub(crate) fn pick_weighted(
+    rng: &mut random::Rng,
+    vars: &[Variable],
+    domains: &[Domain],
+) -> Variable {
+    let weight = |var: &Variable| 1.0 / domains[var.id].values.len().max(1) as f64;
+    let mut target = rng.next_f64() * vars.iter().map(weight).sum::<f64>();
+    for var in vars {
+        target -= weight(var);
+        if target < 0.0 {
+            return *var;
+        }
+    }
+    // Rounding left a bit of weight over
+    *vars.last().unwrap()
+}
+
+/// Which variable is picked when several tie under the [`VariableOrder`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TieBreak {
+    #[default]
+    LowestId,
+    /// The one in the most constraints, then the lowest id
+    HighestDegree,
+    /// A random one, picked with the given seed
+    Random(u64),
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SolverOptions {
+    pub variable_order: VariableOrder,
+    pub tie_break: TieBreak,
+}
+
+/// What a solve is expected to end in, to pick the techniques for it in
+/// [`NormalizedProblem::solve_with`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SolveIntent {
+    /// A solution is expected: arc consistency, then MRV search keeping the
+    /// domains consistent, breaking ties randomly and restarting with a new
+    /// seed whenever a run tries too many values, see
+    /// [`PropagatedProblem::solve_with_restarts`]
+    FindSolution,
+    /// No solution is expected: singleton arc consistency, then conflict
+    /// directed backjumping, which learns which variables caused each dead
+    /// end
+    ProveInfeasible,
+    /// Arc consistency, then plain backtracking
+    #[default]
+    Balanced,
+}
+
+impl NormalizedProblem {
+    /// Propagates and solves the problem with the techniques suited to
+    /// `intent`. Every intent finds a solution if there is one, only how fast
+    /// changes
+    pub fn solve_with(self, intent: SolveIntent) -> Option<Vec<Universe>> {
+        let problem = self.constraint_propagation()?;
+        match intent {
+            SolveIntent::FindSolution => problem.solve_with_restarts(0),
+            SolveIntent::ProveInfeasible => problem.make_singleton_arc_consistency()?.solve_cbj(),
+            SolveIntent::Balanced => problem.solve_backtracking(),
+        }
+    }
+}
+
+impl PropagatedProblem {
+    /// Searches assigning variables in the order given by `options`, keeping
+    /// the domains arc consistent after every assignment
+    pub fn solve_with_options(&self, options: &SolverOptions) -> Option<Vec<Universe>> {
+        self.solve_with_budget(options, None)
+            .expect("an unlimited search always finishes")
+    }
+
+    /// MRV search with random tie breaks, the `i`th run seeded with
+    /// `seed + i` and allowed to try [`RESTART_VALUES`] values, doubled on
+    /// every restart. A run lost after bad early choices is cut short
+    /// instead of exploring everything below them. The limit eventually
+    /// covers the whole search tree, so this finds a solution if there is one
+    pub fn solve_with_restarts(&self, seed: u64) -> Option<Vec<Universe>> {
+        let mut limit = RESTART_VALUES;
+        for i in 0.. {
+            let options = SolverOptions {
+                variable_order: VariableOrder::Mrv,
+                tie_break: TieBreak::Random(seed.wrapping_add(i)),
+            };
+            if let Some(solution) = self.solve_with_budget(&options, Some(limit)) {
+                return solution;
+            }
+            limit = limit.saturating_mul(2);
+        }
+        unreachable!()
+    }
+
+    /// [`PropagatedProblem::solve_with_options`] trying at most `budget`
+    /// values, `None` if it ran out before finishing
+    fn solve_with_budget(
+        &self,
+        options: &SolverOptions,
+        mut budget: Option<usize>,
+    ) -> Option<Option<Vec<Universe>>> {
+        let mut rng = match (options.variable_order, options.tie_break) {
+            (VariableOrder::WeightedRandom(seed), _) | (_, TieBreak::Random(seed)) => {
+                Some(random::Rng::new(seed))
+            }
+            _ => None,
+        };
+        let mut degrees = vec![0; self.variables.len()];
+        for (scope, constraints) in &self.constraints {
+            for var in scope {
+                degrees[var.id] += constraints.len();
+            }
+        }
+
+        let mut candidate: Candidate = vec![None; self.variables.len()];
+        if self.search_with_options(
+            self.realized_domains(),
+            &mut candidate,
+            options,
+            &degrees,
+            &mut rng,
+            &mut budget,
+        ) {
+            Some(candidate.into_iter().collect())
+        } else if budget == Some(0) {
+            None
+        } else {
+            Some(None)
+        }
+    }
+
+    fn search_with_options(
+        &self,
+        domains: Vec<Domain>,
+        candidate: &mut Candidate,
+        options: &SolverOptions,
+        degrees: &[usize],
+        rng: &mut Option<random::Rng>,
+        budget: &mut Option<usize>,
+    ) -> bool {
+        let Some(var) = self.pick_variable(&domains, candidate, options, degrees, rng) else {
+            return true;
+        };
+
+        for &value in &domains[var.id].values {
+            if let Some(left) = budget {
+                if *left == 0 {
+                    break;
+                }
+                *left -= 1;
+            }
+            candidate[var.id] = Some(value);
+            if !self.consistent_with(candidate, var) {
+                continue;
+            }
+
+            let mut next_domains = domains.clone();
+            next_domains[var.id].values = vec![value];
+            if self.propagate_domains(&mut next_domains, &[var])
+                && self.search_with_options(next_domains, candidate, options, degrees, rng, budget)
+            {
+                return true;
+            }
+        }
+
+        candidate[var.id] = None;
+        false
+    }
+
+    fn pick_variable(
+        &self,
+        domains: &[Domain],
+        candidate: &Candidate,
+        options: &SolverOptions,
+        degrees: &[usize],
+        rng: &mut Option<random::Rng>,
+    ) -> Option<Variable> {
+        let unassigned = self
+            .variables
+            .iter()
+            .copied()
+            .filter(|var| candidate[var.id].is_none());
+
+        let mut tied: Vec<Variable> = match options.variable_order {
+            VariableOrder::Static => return unassigned.min_by_key(|var| var.id),
+            VariableOrder::WeightedRandom(_) => {
+                let unassigned: Vec<Variable> = unassigned.collect();
+                if unassigned.is_empty() {
+                    return None;
+                }
+                return Some(pick_weighted(rng.as_mut().unwrap(), &unassigned, domains));
+            }
+            VariableOrder::Mrv => {
+                let remaining = |var: &Variable| domains[var.id].values.len();
+                let fewest = unassigned.clone().map(|var| remaining(&var)).min()?;
+                unassigned.filter(|var| remaining(var) == fewest).collect()
+            }
+        };
+
+        match options.tie_break {
+            TieBreak::LowestId => tied.first().copied(),
+            TieBreak::HighestDegree => tied
+                .iter()
+                .copied()
+                .max_by_key(|var| (degrees[var.id], Reverse(var.id))),
+            TieBreak::Random(_) => {
+                let rng = rng.as_mut().unwrap();
+                Some(tied.swap_remove(rng.below(tied.len())))
+            }
+        }
+    }
+
+    /// Checks the constraints on `var` against the assigned variables, fully
+    /// if all their variables are assigned and partially otherwise
+    fn consistent_with(&self, candidate: &Candidate, var: Variable) -> bool {
+        self.constraints
+            .iter()
+            .filter(|(scope, _)| scope.contains(&var))
+            .all(|(scope, constraints)| {
+                let vals: Candidate = scope.iter().map(|var| candidate[var.id]).collect();
+                if vals.iter().all(Option::is_some) {
+                    let vals: Vec<Universe> = vals.into_iter().flatten().collect();
+                    Constraint::all_satisfied(constraints, &vals)
+                } else {
+                    Constraint::all_partially_satisfied(constraints, &vals)
+                }
+            })
+    }
+}
+
+/// Iterator over every solution of a problem, found by backtracking with an
+/// explicit stack so the search can stop and resume between solutions
+pub struct Solutions<'a> {
+    problem: &'a PropagatedProblem,
+    /// Order in which the values of each variable are tried
+    value_orders: Vec<Vec<Universe>>,
+    /// Index in `value_orders` of the next value to try for each variable
+    positions: Vec<usize>,
+    candidate: Candidate,
+    /// Variable currently being assigned
+    k: usize,
+    done: bool,
+}
+impl Solutions<'_> {
+    /// Same as [`Iterator::next`], but writes the solution into `buf` instead
+    /// of allocating a new vector for it, so going through many solutions
+    /// reuses the same memory. Returns false, leaving `buf` as it was, once
+    /// there are no solutions left
+    pub fn next_solution_into(&mut self, buf: &mut Vec<Universe>) -> bool {
+        if !self.advance() {
+            return false;
+        }
+        buf.clear();
+        buf.extend(self.candidate.iter().map(|value| value.unwrap()));
+        true
+    }
+
+    /// Moves on to the next solution, which is left in `candidate`. Returns
+    /// false once there are none left
+    fn advance(&mut self) -> bool {
+        let n = self.value_orders.len();
+        if self.done {
+            return false;
+        }
+        if n == 0 {
+            self.done = true;
+            return true;
+        }
+
+        loop {
+            let k = self.k;
+            if self.positions[k] < self.value_orders[k].len() {
+                self.candidate[k] = Some(self.value_orders[k][self.positions[k]]);
+                self.positions[k] += 1;
+
+                if self.problem.reject(&self.candidate, k + 1) {
+                    continue;
+                }
+                if k + 1 == n {
+                    return true;
+                }
+                self.k += 1;
+                self.positions[self.k] = 0;
+            } else {
+                self.candidate[k] = None;
+                if k == 0 {
+                    self.done = true;
+                    return false;
+                }
+                self.k -= 1;
+            }
+        }
+    }
+}
+impl Iterator for Solutions<'_> {
+    type Item = Vec<Universe>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.advance() {
+            self.candidate.iter().copied().collect()
+        } else {
+            None
+        }
+    }
+}
+
+impl PropagatedProblem {
+    /// Same solution as [`Self::solve_backtracking`], but searching with an
+    /// explicit stack instead of recursion, so problems with many thousands
+    /// of variables can't overflow the call stack
+    pub fn solve_backtracking_iterative(&self) -> Option<Vec<Universe>> {
+        self.solutions().next()
+    }
+    /// Iterates over every solution, in lexicographic order
+    pub fn solutions(&self) -> Solutions<'_> {
+        let value_orders = (0..self.domains.len())
+            .map(|i| self.values(i).to_vec())
+            .collect();
+        self.solutions_with_orders(value_orders)
+    }
+    /// Sends every solution, in the order of [`Self::solutions`], down `tx`
+    /// as soon as it's found. Meant to run on its own thread: it stops early
+    /// once the receiver is dropped
+    #[cfg(feature = "std")]
+    pub fn solve_streaming(&self, tx: std::sync::mpsc::Sender<Vec<Universe>>) {
+        for solution in self.solutions() {
+            if tx.send(solution).is_err() {
+                return;
+            }
+        }
+    }
+    /// Iterates over every solution, trying the values of each variable in an
+    /// order shuffled by `seed`, so the first solutions are a random-ish sample
+    pub fn solutions_shuffled(&self, seed: u64) -> Solutions<'_> {
+        let mut rng = random::Rng::new(seed);
+        let value_orders = (0..self.domains.len())
+            .map(|i| {
+                let mut values = self.values(i).to_vec();
+                // Fisher-Yates
+                for i in (1..values.len()).rev() {
+                    values.swap(i, rng.below(i + 1));
+                }
+                values
+            })
+            .collect();
+        self.solutions_with_orders(value_orders)
+    }
+    /// Iterates over one solution of each symmetry class, where `symmetry`
+    /// gives the other solutions symmetric to a solution, like its
+    /// reflections. Each class is represented by its lexicographically
+    /// smallest member, yielded the first time the search reaches the class.
+    /// Remembers every class found, so it needs memory for all of them
+    pub fn solutions_canonical<'a>(
+        &'a self,
+        symmetry: impl Fn(&[Universe]) -> Vec<Vec<Universe>> + 'a,
+    ) -> impl Iterator<Item = Vec<Universe>> + 'a {
+        let mut seen = HashSet::new();
+        self.solutions().filter_map(move |solution| {
+            let canonical = symmetry(&solution)
+                .into_iter()
+                .fold(solution, |smallest, variant| smallest.min(variant));
+            seen.insert(canonical.clone()).then_some(canonical)
+        })
+    }
+    /// Solves with temporary restrictions, leaving the problem as it is.
+    /// Each assumption `(var, value, equal)` means `var == value` if `equal`
+    /// and `var != value` otherwise. The restricted domains are propagated
+    /// before searching
+    pub fn solve_under_assumptions(
+        &self,
+        assumptions: &[(Variable, Universe, bool)],
+    ) -> Option<Vec<Universe>> {
+        let mut domains = self.realized_domains();
+        let mut changed = Vec::new();
+        for &(var, value, equal) in assumptions {
+            domains[var.id].values.retain(|&v| (v == value) == equal);
+            if domains[var.id].values.is_empty() {
+                return None;
+            }
+            changed.push(var);
+        }
+        if !self.propagate_domains(&mut domains, &changed) {
+            return None;
+        }
+
+        let value_orders = domains.into_iter().map(|dom| dom.values).collect();
+        self.solutions_with_orders(value_orders).next()
+    }
+    fn solutions_with_orders(&self, value_orders: Vec<Vec<Universe>>) -> Solutions<'_> {
+        let n = self.variables.len();
+        Solutions {
+            problem: self,
+            value_orders,
+            positions: vec![0; n],
+            candidate: vec![None; n],
+            k: 0,
+            done: false,
+        }
+    }
+}
+
+/// Whether the objective of an optimization should be as small or as large as
+/// possible
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Sense {
+    Minimize,
+    Maximize,
+}
+
+/// Which solution [`PropagatedProblem::solve_optimize_tied`] returns when
+/// several have the best objective value
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum OptimumTieBreak {
+    /// The lexicographically smallest
+    #[default]
+    Lexicographic,
+    /// The first one found trying the values in this order
+    FirstFound(ValueOrder),
+    /// The one with the best value of a second objective, lexicographically
+    /// smallest among ties of both
+    Secondary { objective: Variable, sense: Sense },
+}
+
+impl PropagatedProblem {
+    /// Finds a solution with the best value for the `objective` variable.
+    /// Returns that value and the solution, the lexicographically smallest
+    /// among ties, or `None` if there is no solution
+    pub fn solve_optimize(
+        &self,
+        objective: Variable,
+        sense: Sense,
+    ) -> Option<(Universe, Vec<Universe>)> {
+        self.solve_optimize_tied(objective, sense, &OptimumTieBreak::Lexicographic)
+    }
+    /// Like [`Self::solve_optimize`], picking among the solutions with the
+    /// best objective value by `tie_break`
+    pub fn solve_optimize_tied(
+        &self,
+        objective: Variable,
+        sense: Sense,
+        tie_break: &OptimumTieBreak,
+    ) -> Option<(Universe, Vec<Universe>)> {
+        let ascending = ValueOrder::ascending();
+        self.objective_values(objective, sense)
+            .into_iter()
+            .find_map(|value| {
+                let fixed = [(objective, value)];
+                let solution = match tie_break {
+                    OptimumTieBreak::Lexicographic => {
+                        self.solutions_fixing(&fixed, &ascending)?.next()
+                    }
+                    OptimumTieBreak::FirstFound(order) => {
+                        self.solutions_fixing(&fixed, order)?.next()
+                    }
+                    OptimumTieBreak::Secondary {
+                        objective: second,
+                        sense,
+                    } => self.objective_values(*second, *sense).into_iter().find_map(
+                        |second_value| {
+                            let fixed = [(objective, value), (*second, second_value)];
+                            self.solutions_fixing(&fixed, &ascending)?.next()
+                        },
+                    ),
+                };
+                Some((value, solution?))
+            })
+    }
+    /// Like [`Self::solve_optimize`] but returns every solution with the best
+    /// objective value, in lexicographic order
+    pub fn solve_all_optimal(
+        &self,
+        objective: Variable,
+        sense: Sense,
+    ) -> Option<(Universe, Vec<Vec<Universe>>)> {
+        self.objective_values(objective, sense)
+            .into_iter()
+            .find_map(|value| {
+                let solutions: Vec<Vec<Universe>> = self
+                    .solutions_fixing(&[(objective, value)], &ValueOrder::ascending())?
+                    .collect();
+                (!solutions.is_empty()).then_some((value, solutions))
+            })
+    }
+    /// The `k` solutions with the highest `score`, best first, with ties
+    /// kept in lexicographic order. Goes through every solution, but only
+    /// keeps the best `k` found so far in a heap
+    pub fn solve_top_k(
+        &self,
+        k: usize,
+        score: impl Fn(&[Universe]) -> i64,
+    ) -> Vec<(Vec<Universe>, i64)> {
+        if k == 0 {
+            return Vec::new();
+        }
+        // The top of the heap is the worst one kept: the lowest score, and
+        // the last found among those
+        let mut best: BinaryHeap<(Reverse<i64>, usize, Vec<Universe>)> = BinaryHeap::new();
+        let mut solutions = self.solutions();
+        let mut solution = Vec::new();
+        let mut found = 0;
+        while solutions.next_solution_into(&mut solution) {
+            let score = score(&solution);
+            if best.len() < k {
+                best.push((Reverse(score), found, solution.clone()));
+            } else if let Some(mut worst) = best.peek_mut() {
+                // Found after the ones kept, so it has to score higher to
+                // replace one, reusing its memory
+                if score > worst.0 .0 {
+                    worst.0 = Reverse(score);
+                    worst.1 = found;
+                    worst.2.clone_from(&solution);
+                }
+            }
+            found += 1;
+        }
+        best.into_sorted_vec()
+            .into_iter()
+            .map(|(Reverse(score), _, solution)| (solution, score))
+            .collect()
+    }
+
+    /// Values of `objective` from best to worst
+    fn objective_values(&self, objective: Variable, sense: Sense) -> Vec<Universe> {
+        let mut values = self.values(objective.id).to_vec();
+        if sense == Sense::Maximize {
+            values.reverse();
+        }
+        values
+    }
+    /// Solutions with each variable of `fixed` set to its value, trying the
+    /// values left in `order`, or `None` if propagating them already fails
+    fn solutions_fixing(
+        &self,
+        fixed: &[(Variable, Universe)],
+        order: &ValueOrder,
+    ) -> Option<Solutions<'_>> {
+        let mut domains = self.realized_domains();
+        for &(var, value) in fixed {
+            domains[var.id].values.retain(|&v| v == value);
+            if domains[var.id].values.is_empty() {
+                return None;
+            }
+        }
+        let changed: Vec<Variable> = fixed.iter().map(|&(var, _)| var).collect();
+        if !self.propagate_domains(&mut domains, &changed) {
+            return None;
+        }
+
+        let value_orders = domains
+            .into_iter()
+            .enumerate()
+            .map(|(i, dom)| {
+                let mut values = dom.values;
+                let all = self.values(i);
+                values.sort_by_cached_key(|v| order.rank(i, all.binary_search(v).unwrap()));
+                values
+            })
+            .collect();
+        Some(self.solutions_with_orders(value_orders))
+    }
+}
+
+/// Problem with a stack of upper bounds on objective variables, for
+/// optimization loops outside the crate that tighten a bound, solve, and
+/// relax it again without rebuilding the problem
+pub struct BoundedProblem {
+    problem: PropagatedProblem,
+    /// Domains before each bound, and whether the problem was still feasible
+    saved: Vec<(DomainSnapshot, bool)>,
+    feasible: bool,
+}
+impl BoundedProblem {
+    pub fn new(problem: PropagatedProblem) -> Self {
+        BoundedProblem {
+            problem,
+            saved: Vec::new(),
+            feasible: true,
+        }
+    }
+    /// Adds `objective <= bound` and propagates it. Returns false if that
+    /// makes the problem infeasible
+    pub fn tighten(&mut self, objective: Variable, bound: Universe) -> bool {
+        self.saved.push((self.problem.snapshot(), self.feasible));
+        if self.feasible {
+            self.problem.realize_all();
+            self.problem.domains[objective.id]
+                .values
+                .retain(|&value| value <= bound);
+            self.feasible = !self.problem.domains[objective.id].values.is_empty()
+                && self.problem.propagate_changes(&[objective]);
+        }
+        self.feasible
+    }
+    /// Removes the most recent bound. Does nothing if there are none
+    pub fn relax(&mut self) {
+        if let Some((snapshot, feasible)) = self.saved.pop() {
+            self.problem.restore(snapshot);
+            self.feasible = feasible;
+        }
+    }
+    /// Solves with the current bounds
+    pub fn solve(&self) -> Option<Vec<Universe>> {
+        if self.feasible {
+            self.problem.solve_backtracking()
+        } else {
+            None
+        }
+    }
+    pub fn problem(&self) -> &PropagatedProblem {
+        &self.problem
+    }
+}
+
+/// Time spent in each step of [`RawProblem::solve_profiled`]
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PhaseTimings {
+    pub normalization: Duration,
+    /// Node consistency and the bounds of orderings like `x < y`
+    pub node_consistency: Duration,
+    /// Arc consistency together with the custom propagators, run to their
+    /// shared fixpoint
+    pub arc_consistency: Duration,
+    /// Zero if propagation already found the problem infeasible
+    pub search: Duration,
+}
+#[cfg(feature = "std")]
+impl PhaseTimings {
+    pub fn total(&self) -> Duration {
+        self.normalization + self.node_consistency + self.arc_consistency + self.search
+    }
+}
+
+#[cfg(feature = "std")]
+impl RawProblem {
+    /// Normalizes, propagates and solves the problem with backtracking like
+    /// usual, timing every step
+    pub fn solve_profiled(self) -> (Option<Vec<Universe>>, PhaseTimings) {
+        let mut timings = PhaseTimings::default();
+        let mut start = Instant::now();
+        let mut lap = || {
+            let elapsed = start.elapsed();
+            start = Instant::now();
+            elapsed
+        };
+
+        let problem = self.normalize_problem();
+        timings.normalization = lap();
+        let problem = problem.make_node_consistency().make_bounds_consistency();
+        timings.node_consistency = lap();
+        let problem = problem.make_propagators_fixpoint();
+        timings.arc_consistency = lap();
+        let Some(problem) = problem.map(NormalizedProblem::into_propagated) else {
+            return (None, timings);
+        };
+        let solution = problem.solve_backtracking();
+        timings.search = lap();
+
+        (solution, timings)
+    }
+}
+
+/// Most nodes of the search tree [`PropagatedProblem::solve_to_dot`] writes
+pub const DOT_NODE_LIMIT: usize = 1000;
+
+/// Largest search space [`PropagatedProblem::solve_brute_force`] will go through
+pub const BRUTE_FORCE_LIMIT: u128 = 10_000_000;
+
+/// Values the first run of [`PropagatedProblem::solve_with_restarts`] may try
+pub const RESTART_VALUES: usize = 100;
+
+/// The problem has more complete assignments than [`BRUTE_FORCE_LIMIT`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SearchSpaceTooLarge {
+    pub size: u128,
+}
+
+impl PropagatedProblem {
+    /// Tries every assignment of the cartesian product of the domains, in
+    /// lexicographic order, and returns the first one passing `verify`.
+    /// Exponential, only meant to check other solvers on tiny problems
+    pub fn solve_brute_force(&self) -> Result<Option<Vec<Universe>>, SearchSpaceTooLarge> {
+        let mut first = None;
+        self.brute_force(|solution| {
+            first = Some(solution.to_vec());
+            false
+        })?;
+        Ok(first)
+    }
+    /// Every solution, in lexicographic order, found by going through the
+    /// whole cartesian product of the domains
+    pub fn solve_brute_force_all(&self) -> Result<Vec<Vec<Universe>>, SearchSpaceTooLarge> {
+        let mut all = Vec::new();
+        self.brute_force(|solution| {
+            all.push(solution.to_vec());
+            true
+        })?;
+        Ok(all)
+    }
+    /// Calls `on_solution` with every assignment passing `verify` until it
+    /// returns false
+    fn brute_force(
+        &self,
+        mut on_solution: impl FnMut(&[Universe]) -> bool,
+    ) -> Result<(), SearchSpaceTooLarge> {
+        let size = self.solution_upper_bound();
+        if size > BRUTE_FORCE_LIMIT {
+            return Err(SearchSpaceTooLarge { size });
+        }
+        if size == 0 {
+            return Ok(());
+        }
+
+        let n = self.variables.len();
+        let mut indices = vec![0; n];
+        let mut assignment: Vec<Universe> = (0..n).map(|i| self.values(i)[0]).collect();
+
+        loop {
+            if self.verify(&assignment) && !on_solution(&assignment) {
+                return Ok(());
+            }
+
+            // Advance like an odometer, last variable changing fastest
+            let mut i = n;
+            loop {
+                if i == 0 {
+                    return Ok(());
+                }
+                i -= 1;
+
+                indices[i] += 1;
+                if indices[i] < self.values(i).len() {
+                    assignment[i] = self.values(i)[indices[i]];
+                    break;
+                }
+                indices[i] = 0;
+                assignment[i] = self.values(i)[0];
+            }
+        }
+    }
+}
@@ -1,6 +1,33 @@
-use std::fmt::Display;
+use std::{fmt::Display, str::FromStr};
 
-use crate::{RawProblem, Variable};
+use crate::{RawProblem, VarArray, VarGrid};
+
+/// How hard a puzzle is, judged by what it takes to solve it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Difficulty {
+    /// Solved by filling cells that have a single candidate left
+    Easy,
+    /// Also needs digits that fit in a single cell of a row, column or box
+    Medium,
+    /// Singles get stuck, needs searching
+    Hard,
+    /// Needs more than [`EXPERT_BACKTRACKS`] backtracks of searching
+    Expert,
+}
+pub const EXPERT_BACKTRACKS: u64 = 1000;
+
+/// One deduction a person could make while solving, see
+/// [`Sudoku::solve_with_steps`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeductionStep {
+    /// The cell at `(x, y)` has `value` as its only candidate
+    NakedSingle { x: usize, y: usize, value: u8 },
+    /// The cell at `(x, y)` is the only place left for `value` in one of its
+    /// row, column or square
+    HiddenSingle { x: usize, y: usize, value: u8 },
+    /// Singles got stuck, the rest needs guessing
+    RequiresGuessing,
+}
 
 pub struct Sudoku {
     board: [u8; 81],
@@ -19,78 +46,234 @@ impl Sudoku {
     }
     pub fn to_constraint_problem(&self) -> RawProblem {
         let mut problem = RawProblem::new();
+        let grid = problem.add_var_grid(9, 9, 1, 9);
 
-        for _ in 0..81 {
-            problem.add_var(vec![1, 2, 3, 4, 5, 6, 7, 8, 9]);
-        }
-
-        let check_nine_distinct = |vals: &mut dyn Iterator<Item = i32>| {
-            let mut bit_arr: u16 = 0;
-            for val in vals {
-                bit_arr |= 1 << val;
-            }
-            bit_arr == 0b1111111110
-        };
-
-        // No repeating in rows
-        for y in 0..9 {
-            let row = (0..9).map(|x| Variable { id: 9 * y + x }).collect();
-            problem.add_constraint(row, Box::new(check_nine_distinct));
-        }
-        // No repeating in columns
-        for x in 0..9 {
-            let column = (0..9).map(|y| Variable { id: 9 * y + x }).collect();
-            problem.add_constraint(column, Box::new(check_nine_distinct));
-        }
-
-        // No repeating in 3x3 squares
-        for sy in 0..3 {
-            for sx in 0..3 {
-                let top_left = 9 * 3 * sy + 3 * sx;
-                let square = [
-                    top_left,
-                    top_left + 1,
-                    top_left + 2,
-                    top_left + 9,
-                    top_left + 9 + 1,
-                    top_left + 9 + 2,
-                    top_left + 18,
-                    top_left + 18 + 1,
-                    top_left + 18 + 2,
-                ]
-                .into_iter()
-                .map(|i| Variable { id: i })
-                .collect();
-
-                problem.add_constraint(square, Box::new(check_nine_distinct));
-            }
-        }
+        // No repeating in rows, columns and 3x3 squares. Tagged as
+        // all-different, so propagation handles them without spelling out
+        // every pair
+        problem.add_constraint_pattern(&grid, sudoku_units, |unit| unit.all_different());
 
         // Tiles that are set must use those values
         for (i, &num) in self.board.iter().enumerate() {
             if num != 0 {
-                problem.add_constraint(
-                    vec![Variable { id: i }],
-                    Box::new(move |vals| vals.next().unwrap() == num.into()),
-                );
+                problem.fix_var(grid[(i % 9, i / 9)], num.into());
             }
         }
 
         problem
     }
+
+    /// Rates the puzzle by first filling in naked and hidden singles, and if
+    /// that isn't enough, by the backtracks the solver needs for the rest.
+    /// Returns `None` if the puzzle has no solution
+    pub fn rate_difficulty(&self) -> Option<Difficulty> {
+        if !units()
+            .iter()
+            .all(|unit| distinct_digits(&self.board, unit))
+        {
+            return None;
+        }
+
+        let mut board = self.board;
+        let mut steps = Vec::new();
+        fill_singles(&mut board, &mut steps)?;
+        if board.iter().all(|&num| num != 0) {
+            let needed_hidden = steps
+                .iter()
+                .any(|step| matches!(step, DeductionStep::HiddenSingle { .. }));
+            return Some(if needed_hidden {
+                Difficulty::Medium
+            } else {
+                Difficulty::Easy
+            });
+        }
+
+        let mut problem = Sudoku { board }.to_constraint_problem().normalize_problem();
+        // The backtracks are counted after plain arc consistency, the
+        // all-different propagators would leave too few to tell puzzles apart
+        problem.propagators.clear();
+        let (solution, stats) = problem.constraint_propagation()?.solve_backtracking_stats();
+        solution?;
+
+        Some(if stats.backtracks > EXPERT_BACKTRACKS {
+            Difficulty::Expert
+        } else {
+            Difficulty::Hard
+        })
+    }
+
+    /// The naked and hidden singles that fill the puzzle, in the order they
+    /// are found, for walking someone through it. Ends in
+    /// [`DeductionStep::RequiresGuessing`] if singles leave cells empty, and
+    /// stops early if the clues contradict each other
+    pub fn solve_with_steps(&self) -> Vec<DeductionStep> {
+        let mut board = self.board;
+        let mut steps = Vec::new();
+        if fill_singles(&mut board, &mut steps).is_some() && board.contains(&0) {
+            steps.push(DeductionStep::RequiresGuessing);
+        }
+        steps
+    }
+}
+
+/// Every row, column and 3x3 square of a Sudoku grid
+fn sudoku_units(grid: &VarGrid) -> Vec<VarArray> {
+    grid.rows()
+        .chain(grid.columns())
+        .chain(grid.blocks(3, 3))
+        .collect()
+}
+
+/// Indices of the cells of every row, column and 3x3 square
+fn units() -> Vec<[usize; 9]> {
+    let mut units = Vec::with_capacity(27);
+    for y in 0..9 {
+        units.push(core::array::from_fn(|x| 9 * y + x));
+    }
+    for x in 0..9 {
+        units.push(core::array::from_fn(|y| 9 * y + x));
+    }
+    for sy in 0..3 {
+        for sx in 0..3 {
+            let top_left = 9 * 3 * sy + 3 * sx;
+            units.push(core::array::from_fn(|i| top_left + 9 * (i / 3) + i % 3));
+        }
+    }
+    units
 }
 
-fn check_distinct(array: &[i32]) -> bool {
-    for i in 0..array.len() {
-        for j in 0..i {
-            if array[i] == array[j] {
+fn distinct_digits(board: &[u8; 81], unit: &[usize; 9]) -> bool {
+    let mut seen: u16 = 0;
+    for &i in unit {
+        if board[i] != 0 {
+            if seen & (1 << board[i]) != 0 {
                 return false;
             }
+            seen |= 1 << board[i];
         }
     }
     true
 }
 
+/// Bit set of the digits cell `i` can still take
+fn candidates(board: &[u8; 81], units: &[[usize; 9]], i: usize) -> u16 {
+    let mut used: u16 = 0;
+    for unit in units.iter().filter(|unit| unit.contains(&i)) {
+        for &j in unit {
+            used |= 1 << board[j];
+        }
+    }
+    0b1111111110 & !used
+}
+
+/// Fills in naked singles (cells with one candidate) and hidden singles
+/// (digits with one possible cell in a unit) until neither applies, pushing
+/// each to `steps`. Returns `None` on a contradiction
+fn fill_singles(board: &mut [u8; 81], steps: &mut Vec<DeductionStep>) -> Option<()> {
+    let units = units();
+
+    loop {
+        let mut progress = false;
+        for i in 0..81 {
+            if board[i] != 0 {
+                continue;
+            }
+            let cands = candidates(board, &units, i);
+            if cands == 0 {
+                return None;
+            }
+            if cands.is_power_of_two() {
+                board[i] = cands.trailing_zeros() as u8;
+                steps.push(DeductionStep::NakedSingle {
+                    x: i % 9,
+                    y: i / 9,
+                    value: board[i],
+                });
+                progress = true;
+            }
+        }
+        if progress {
+            continue;
+        }
+
+        // Only look for hidden singles once naked singles run out
+        for unit in &units {
+            for digit in 1..=9 {
+                if unit.iter().any(|&i| board[i] == digit) {
+                    continue;
+                }
+                let mut places = unit.iter().filter(|&&i| {
+                    board[i] == 0 && candidates(board, &units, i) & (1 << digit) != 0
+                });
+                match (places.next(), places.next()) {
+                    (None, _) => return None,
+                    (Some(&i), None) => {
+                        board[i] = digit;
+                        steps.push(DeductionStep::HiddenSingle {
+                            x: i % 9,
+                            y: i / 9,
+                            value: digit,
+                        });
+                        progress = true;
+                    }
+                    _ => {}
+                }
+            }
+        }
+        if !progress {
+            return Some(());
+        }
+    }
+}
+
+impl Default for Sudoku {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Why a puzzle couldn't be parsed, see [`Sudoku::from_str`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseSudokuError {
+    /// A character that is neither a digit, a `.` nor whitespace
+    InvalidChar(char),
+    /// The number of cells given, when it isn't 81
+    WrongLength(usize),
+}
+impl Display for ParseSudokuError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParseSudokuError::InvalidChar(c) => write!(f, "invalid character {c:?}"),
+            ParseSudokuError::WrongLength(len) => write!(f, "expected 81 cells, got {len}"),
+        }
+    }
+}
+
+/// Reads the 81 cells row by row, with `0` or `.` for empty ones. Whitespace
+/// is skipped, so the grid can be on one line or spread over nine like
+/// [`Sudoku`]'s `Display` writes it
+impl FromStr for Sudoku {
+    type Err = ParseSudokuError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let cells = s
+            .chars()
+            .filter(|c| !c.is_whitespace())
+            .map(|c| match c {
+                '.' => Ok(0),
+                _ => c
+                    .to_digit(10)
+                    .map(|digit| digit as u8)
+                    .ok_or(ParseSudokuError::InvalidChar(c)),
+            })
+            .collect::<Result<Vec<u8>, _>>()?;
+        if cells.len() != 81 {
+            return Err(ParseSudokuError::WrongLength(cells.len()));
+        }
+        Ok(Sudoku::from_slice(&cells))
+    }
+}
+
 impl Display for Sudoku {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         for i in 0..81 {
@@ -106,6 +289,7 @@ impl Display for Sudoku {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::{diff_solutions, Universe, Variable};
 
     #[test]
     fn test_empty() {
@@ -125,4 +309,342 @@ mod tests {
             Sudoku::from_slice(&solution.iter().map(|&x| x as u8).collect::<Vec<u8>>());
         println!("{}", solution_board);
     }
+
+    #[test]
+    fn test_solve_streaming() {
+        use std::{sync::mpsc, thread};
+
+        let (tx, rx) = mpsc::channel();
+        // Constraints aren't `Send`, so the problem is built on the solver's
+        // thread. An empty board has more solutions than anyone could wait for
+        let solver = thread::spawn(move || {
+            Sudoku::new()
+                .to_constraint_problem()
+                .normalize_problem()
+                .constraint_propagation()
+                .unwrap()
+                .solve_streaming(tx)
+        });
+
+        let first = rx.recv().unwrap();
+        let second = rx.recv().unwrap();
+        assert_ne!(first, second);
+        drop(rx);
+        solver.join().unwrap();
+    }
+
+    fn parse(puzzle: &str) -> Sudoku {
+        let digits: Vec<u8> = puzzle.bytes().map(|c| c - b'0').collect();
+        Sudoku::from_slice(&digits)
+    }
+
+    #[test]
+    fn test_rate_difficulty() {
+        let easy = parse(
+            "000260701680070090190004500820100040004602900050003028009300074040050036703018000",
+        );
+        assert_eq!(easy.rate_difficulty(), Some(Difficulty::Easy));
+
+        let medium = parse(
+            "306508400520000000087000031003010080900863005050090600130000250000000074005206300",
+        );
+        assert_eq!(medium.rate_difficulty(), Some(Difficulty::Medium));
+
+        let expert = parse(
+            "162007090030020008009600500005300900010080002600004000300000010040000007007000300",
+        );
+        assert_eq!(expert.rate_difficulty(), Some(Difficulty::Expert));
+        assert_eq!(expert.rate_difficulty(), expert.rate_difficulty());
+
+        let mut invalid = Sudoku::new();
+        invalid.add_num(1, 0, 0);
+        invalid.add_num(1, 5, 0);
+        assert_eq!(invalid.rate_difficulty(), None);
+    }
+
+    /// The usual model, plus every unit spelled out as pairs of different
+    /// cells, the way arc consistency sees them
+    fn pairwise_problem(sudoku: &Sudoku) -> RawProblem {
+        let mut problem = sudoku.to_constraint_problem();
+        for unit in units() {
+            for (i, &a) in unit.iter().enumerate() {
+                for &b in &unit[i + 1..] {
+                    problem.add_constraint(
+                        vec![Variable { id: a }, Variable { id: b }],
+                        Box::new(|vals| vals.next() != vals.next()),
+                    );
+                }
+            }
+        }
+        problem
+    }
+
+    #[test]
+    fn test_try_solve_by_propagation() {
+        let propagate = |sudoku: &Sudoku| {
+            pairwise_problem(sudoku)
+                .normalize_problem()
+                .constraint_propagation()
+                .unwrap()
+        };
+
+        // Naked singles are enough for the easy one
+        let easy = propagate(&parse(
+            "000260701680070090190004500820100040004602900050003028009300074040050036703018000",
+        ));
+        assert!(easy.try_solve_by_propagation().is_some());
+
+        let hard = propagate(&parse(
+            "162007090030020008009600500005300900010080002600004000300000010040000007007000300",
+        ));
+        assert_eq!(hard.try_solve_by_propagation(), None);
+    }
+
+    #[test]
+    fn test_propagate_domains() {
+        let sudoku = parse(
+            "000260701680070090190004500820100040004602900050003028009300074040050036703018000",
+        );
+        let domains = pairwise_problem(&sudoku)
+            .normalize_problem()
+            .propagate_domains()
+            .unwrap();
+        assert!(domains.iter().all(|domain| domain.values.len() == 1));
+        let solution: String = domains
+            .iter()
+            .map(|domain| char::from_digit(domain.values[0] as u32, 10).unwrap())
+            .collect();
+        assert_eq!(
+            solution,
+            "435269781682571493197834562826195347374682915951743628519326874248957136763418259"
+        );
+    }
+
+    #[test]
+    fn test_all_different_without_pairs() {
+        let easy = parse(
+            "000260701680070090190004500820100040004602900050003028009300074040050036703018000",
+        );
+        let lazy = easy.to_constraint_problem().normalize_problem();
+        let materialized = pairwise_problem(&easy).normalize_problem();
+        let units = lazy.constraints().count();
+        assert_eq!(units, 27);
+        assert_eq!(materialized.constraints().count(), units + 27 * 36);
+        let domains = lazy.propagate_domains().unwrap();
+        assert!(domains.iter().all(|domain| domain.values.len() == 1));
+        assert_eq!(Some(domains), materialized.propagate_domains());
+
+        let hard = parse(
+            "162007090030020008009600500005300900010080002600004000300000010040000007007000300",
+        );
+        let solve = |problem: RawProblem| {
+            problem
+                .normalize_problem()
+                .constraint_propagation()
+                .unwrap()
+                .solve_backtracking()
+        };
+        let solution = solve(hard.to_constraint_problem());
+        assert!(solution.is_some());
+        assert_eq!(solution, solve(pairwise_problem(&hard)));
+    }
+
+    #[test]
+    fn test_solve_profiled() {
+        let sudoku = parse(
+            "000260701680070090190004500820100040004602900050003028009300074040050036703018000",
+        );
+        let (solution, timings) = sudoku.to_constraint_problem().solve_profiled();
+        let expected = sudoku
+            .to_constraint_problem()
+            .normalize_problem()
+            .constraint_propagation()
+            .unwrap()
+            .solve_backtracking();
+        assert!(solution.is_some());
+        assert_eq!(solution, expected);
+
+        let phases = [
+            timings.normalization,
+            timings.node_consistency,
+            timings.arc_consistency,
+            timings.search,
+        ];
+        assert_eq!(phases.iter().sum::<std::time::Duration>(), timings.total());
+        // Searching a whole Sudoku takes measurable time
+        assert!(timings.search > std::time::Duration::ZERO);
+    }
+
+    #[test]
+    fn test_packed_candidate_same_solution() {
+        use crate::{solve::PackedCandidate, Candidate};
+
+        let problem = parse(
+            "000260701680070090190004500820100040004602900050003028009300074040050036703018000",
+        )
+        .to_constraint_problem()
+        .normalize_problem()
+        .constraint_propagation()
+        .unwrap();
+
+        let (packed, packed_stats) = problem.solve_backtracking_with::<PackedCandidate>();
+        let (boxed, boxed_stats) = problem.solve_backtracking_with::<Candidate>();
+        assert!(packed.is_some());
+        assert_eq!(packed, boxed);
+        assert_eq!(packed_stats, boxed_stats);
+    }
+
+    #[test]
+    fn test_constraint_scopes() {
+        let mut sudoku = Sudoku::new();
+        sudoku.add_num(5, 0, 0);
+        sudoku.add_num(3, 4, 2);
+
+        let problem = sudoku.to_constraint_problem().normalize_problem();
+        // 9 rows, 9 columns and 9 squares, clues only fix domains
+        assert_eq!(problem.constraints().count(), 27);
+        assert_eq!(problem.domains[2 * 9 + 4].values, vec![3]);
+
+        let problem = problem.constraint_propagation().unwrap();
+        assert_eq!(problem.constraints().count(), 27);
+        assert!(problem.constraints().all(|scope| scope.len() == 9));
+    }
+
+    #[test]
+    fn test_diff_ambiguous_puzzle() {
+        let solved =
+            "435269781682571493197834562826195347374682915951743628519326874248957136763418259";
+        // Blanking a rectangle of 5s and 2s over two boxes lets them swap
+        let mut puzzle: Vec<u8> = solved.bytes().map(|c| c - b'0').collect();
+        for cell in [2, 3, 11, 12] {
+            puzzle[cell] = 0;
+        }
+        let problem = Sudoku::from_slice(&puzzle)
+            .to_constraint_problem()
+            .normalize_problem()
+            .constraint_propagation()
+            .unwrap();
+
+        let solutions: Vec<Vec<Universe>> = problem.solutions().take(3).collect();
+        assert_eq!(solutions.len(), 2);
+        let cell = |id| Variable { id };
+        assert_eq!(
+            diff_solutions(&solutions[0], &solutions[1]),
+            vec![
+                (cell(2), 2, 5),
+                (cell(3), 5, 2),
+                (cell(11), 5, 2),
+                (cell(12), 2, 5)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_solve_with_steps() {
+        let puzzle =
+            "000260701680070090190004500820100040004602900050003028009300074040050036703018000";
+        let solved =
+            "435269781682571493197834562826195347374682915951743628519326874248957136763418259";
+        let mut board: Vec<u8> = puzzle.bytes().map(|c| c - b'0').collect();
+
+        let steps = parse(puzzle).solve_with_steps();
+        assert_eq!(steps.len(), board.iter().filter(|&&num| num == 0).count());
+        for step in steps {
+            let DeductionStep::NakedSingle { x, y, value } = step else {
+                panic!("easy puzzle needed {step:?}");
+            };
+            assert_eq!(board[9 * y + x], 0);
+            board[9 * y + x] = value;
+        }
+        let expected: Vec<u8> = solved.bytes().map(|c| c - b'0').collect();
+        assert_eq!(board, expected);
+
+        let hard = parse(
+            "162007090030020008009600500005300900010080002600004000300000010040000007007000300",
+        );
+        assert_eq!(
+            hard.solve_with_steps().last(),
+            Some(&DeductionStep::RequiresGuessing)
+        );
+    }
+
+    #[test]
+    fn test_from_str() {
+        let puzzle =
+            "000260701680070090190004500820100040004602900050003028009300074040050036703018000";
+        let sudoku: Sudoku = puzzle.parse().unwrap();
+        assert_eq!(sudoku.board, parse(puzzle).board);
+
+        // Reads back what Display writes, with dots for blanks too
+        let dotted = puzzle.replace('0', ".");
+        assert_eq!(dotted.parse::<Sudoku>().unwrap().board, sudoku.board);
+        assert_eq!(
+            sudoku.to_string().parse::<Sudoku>().unwrap().board,
+            sudoku.board
+        );
+
+        assert_eq!(
+            "12x".parse::<Sudoku>().err(),
+            Some(ParseSudokuError::InvalidChar('x'))
+        );
+        assert_eq!(
+            puzzle[1..].parse::<Sudoku>().err(),
+            Some(ParseSudokuError::WrongLength(80))
+        );
+    }
+
+    #[test]
+    fn test_var_grid_model() {
+        let mut problem = RawProblem::new();
+        let grid = problem.add_var_grid(9, 9, 1, 9);
+        for y in 0..3 {
+            for x in 0..3 {
+                problem.push_constraint(grid.block(3 * x, 3 * y, 3, 3).all_different());
+            }
+        }
+        for unit in grid.rows().chain(grid.columns()) {
+            problem.push_constraint(unit.all_different());
+        }
+        let built = problem.normalize_problem();
+        assert!(built
+            .domains
+            .iter()
+            .all(|dom| dom.values == [1, 2, 3, 4, 5, 6, 7, 8, 9]));
+
+        // The same units spelled out cell by cell, rather than through the
+        // grid or Sudoku::to_constraint_problem which uses it
+        let cell = |x: usize, y: usize| Variable { id: 9 * y + x };
+        let mut expected: Vec<Vec<Variable>> = Vec::new();
+        for i in 0..9 {
+            expected.push((0..9).map(|x| cell(x, i)).collect());
+            expected.push((0..9).map(|y| cell(i, y)).collect());
+            let (left, top) = (3 * (i % 3), 3 * (i / 3));
+            let mut block: Vec<Variable> =
+                (0..9).map(|j| cell(left + j % 3, top + j / 3)).collect();
+            block.sort_unstable();
+            expected.push(block);
+        }
+        expected.sort_unstable();
+        let mut scopes: Vec<Vec<Variable>> = built.constraints().map(<[_]>::to_vec).collect();
+        scopes.sort_unstable();
+        assert_eq!(scopes, expected);
+    }
+
+    #[test]
+    fn test_sudoku_units_pattern() {
+        let mut problem = RawProblem::new();
+        let grid = problem.add_var_grid(9, 9, 1, 9);
+        let groups: Vec<Vec<usize>> = sudoku_units(&grid)
+            .iter()
+            .map(|unit| unit.vars.iter().map(|var| var.id).collect())
+            .collect();
+        let expected: Vec<Vec<usize>> = units().iter().map(|unit| unit.to_vec()).collect();
+        assert_eq!(groups.len(), 27);
+        assert_eq!(groups, expected);
+
+        // Each cell is in one row, one column and one square
+        for cell in 0..81 {
+            assert_eq!(groups.iter().filter(|g| g.contains(&cell)).count(), 3);
+        }
+    }
 }
@@ -0,0 +1,203 @@
+//! Variables grouped into arrays and grids, with the constraints usually put
+//! on them, so models like the Sudoku don't need index arithmetic
+
+use alloc::{boxed::Box, vec::Vec};
+use core::ops::Index;
+
+use crate::{Constraint, RawProblem, Universe, Variable};
+
+/// A sequence of distinct variables, like a row of a grid
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VarArray {
+    pub vars: Vec<Variable>,
+}
+impl VarArray {
+    pub fn new(vars: Vec<Variable>) -> Self {
+        let array = VarArray { vars };
+        assert!(array.sorted().windows(2).all(|pair| pair[0] != pair[1]));
+        array
+    }
+    pub fn len(&self) -> usize {
+        self.vars.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.vars.is_empty()
+    }
+
+    /// The variables all have different values
+    pub fn all_different(&self) -> Constraint {
+        Constraint::all_different(self.sorted())
+    }
+    /// Each variable is smaller than the next one in the array
+    pub fn increasing(&self) -> Constraint {
+        self.in_order(|vals| vals.windows(2).all(|pair| pair[0] < pair[1]))
+    }
+    /// The variables add up to `total`
+    pub fn sum_eq(&self, total: Universe) -> Constraint {
+        Constraint::new(
+            self.sorted(),
+            Box::new(move |vals| vals.sum::<Universe>() == total),
+        )
+    }
+
+    fn sorted(&self) -> Vec<Variable> {
+        let mut scope = self.vars.clone();
+        scope.sort_unstable();
+        scope
+    }
+    /// Constraint over the sorted scope where `evaluate` gets the values in
+    /// the order of the array instead
+    fn in_order(&self, evaluate: impl Fn(&[Universe]) -> bool + 'static) -> Constraint {
+        let scope = self.sorted();
+        // Where each variable of the array is in the scope
+        let positions: Vec<usize> = self
+            .vars
+            .iter()
+            .map(|var| scope.binary_search(var).unwrap())
+            .collect();
+        Constraint::new_slice(scope, move |vals| {
+            let ordered: Vec<Universe> = positions.iter().map(|&i| vals[i]).collect();
+            evaluate(&ordered)
+        })
+    }
+}
+impl Index<usize> for VarArray {
+    type Output = Variable;
+
+    fn index(&self, i: usize) -> &Variable {
+        &self.vars[i]
+    }
+}
+
+/// Variables laid out in rows of `width`, indexed by `(x, y)` with `x` the
+/// column
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VarGrid {
+    vars: Vec<Variable>,
+    width: usize,
+}
+impl VarGrid {
+    /// `vars` row by row
+    pub fn new(vars: Vec<Variable>, width: usize) -> Self {
+        assert!(width > 0 && vars.len().is_multiple_of(width));
+        VarGrid { vars, width }
+    }
+    pub fn width(&self) -> usize {
+        self.width
+    }
+    pub fn height(&self) -> usize {
+        self.vars.len() / self.width
+    }
+
+    pub fn row(&self, y: usize) -> VarArray {
+        VarArray::new(self.vars[y * self.width..(y + 1) * self.width].to_vec())
+    }
+    pub fn column(&self, x: usize) -> VarArray {
+        VarArray::new((0..self.height()).map(|y| self[(x, y)]).collect())
+    }
+    /// The `width` by `height` rectangle with its top left corner at `(x, y)`,
+    /// row by row
+    pub fn block(&self, x: usize, y: usize, width: usize, height: usize) -> VarArray {
+        VarArray::new(
+            (y..y + height)
+                .flat_map(|y| (x..x + width).map(move |x| (x, y)))
+                .map(|at| self[at])
+                .collect(),
+        )
+    }
+    pub fn rows(&self) -> impl Iterator<Item = VarArray> + '_ {
+        (0..self.height()).map(|y| self.row(y))
+    }
+    pub fn columns(&self) -> impl Iterator<Item = VarArray> + '_ {
+        (0..self.width).map(|x| self.column(x))
+    }
+    /// The `width` by `height` blocks tiling the grid, like the 3x3 squares
+    /// of a Sudoku, row by row
+    pub fn blocks(&self, width: usize, height: usize) -> impl Iterator<Item = VarArray> + '_ {
+        assert!(self.width.is_multiple_of(width) && self.height().is_multiple_of(height));
+        let across = self.width / width;
+        (0..across * (self.height() / height))
+            .map(move |i| self.block(width * (i % across), height * (i / across), width, height))
+    }
+}
+impl Index<(usize, usize)> for VarGrid {
+    type Output = Variable;
+
+    fn index(&self, (x, y): (usize, usize)) -> &Variable {
+        assert!(x < self.width);
+        &self.vars[y * self.width + x]
+    }
+}
+
+impl RawProblem {
+    /// Adds `len` variables with the values from `lo` to `hi` inclusive
+    pub fn add_var_array(&mut self, len: usize, lo: Universe, hi: Universe) -> VarArray {
+        VarArray::new((0..len).map(|_| self.add_var_range(lo, hi)).collect())
+    }
+    /// Adds a `width` by `height` grid of variables with the values from `lo`
+    /// to `hi` inclusive, row by row
+    pub fn add_var_grid(
+        &mut self,
+        width: usize,
+        height: usize,
+        lo: Universe,
+        hi: Universe,
+    ) -> VarGrid {
+        VarGrid::new(self.add_var_array(width * height, lo, hi).vars, width)
+    }
+    /// Adds the constraint made by `constraint` for each group of variables of
+    /// `grid` that `pattern` picks, like all of its rows
+    pub fn add_constraint_pattern(
+        &mut self,
+        grid: &VarGrid,
+        pattern: impl Fn(&VarGrid) -> Vec<VarArray>,
+        constraint: impl Fn(VarArray) -> Constraint,
+    ) {
+        for group in pattern(grid) {
+            self.push_constraint(constraint(group));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+
+    use super::*;
+
+    #[test]
+    fn test_grid_indexing() {
+        let mut problem = RawProblem::new();
+        let grid = problem.add_var_grid(3, 2, 0, 1);
+        let cell = |id| Variable { id };
+        assert_eq!(grid.height(), 2);
+        assert_eq!(grid[(2, 1)], cell(5));
+        assert_eq!(grid.row(1).vars, [cell(3), cell(4), cell(5)]);
+        assert_eq!(grid.column(1).vars, [cell(1), cell(4)]);
+        assert_eq!(
+            grid.block(1, 0, 2, 2).vars,
+            [cell(1), cell(2), cell(4), cell(5)]
+        );
+        assert_eq!(grid.columns().count(), 3);
+        let blocks: Vec<VarArray> = grid.blocks(1, 2).collect();
+        assert_eq!(blocks.len(), 3);
+        assert_eq!(blocks[2].vars, [cell(2), cell(5)]);
+    }
+
+    #[test]
+    fn test_increasing_follows_array_order() {
+        let mut problem = RawProblem::new();
+        let vars = problem.add_var_array(3, 1, 3);
+        // Reversed, so the ids decrease along the array
+        let reversed = VarArray::new(vars.vars.iter().rev().copied().collect());
+        problem.push_constraint(reversed.increasing());
+        problem.push_constraint(vars.sum_eq(6));
+
+        let problem = problem
+            .normalize_problem()
+            .constraint_propagation()
+            .unwrap();
+        assert_eq!(problem.solve_backtracking(), Some(vec![3, 2, 1]));
+        assert_eq!(problem.solutions().count(), 1);
+    }
+}
@@ -0,0 +1,461 @@
+//! Entry points for calling the solver across a string-only boundary, like
+//! from JavaScript through WebAssembly. [`solve`] takes a JSON problem and
+//! [`solve_dsl`] takes [`crate::dsl`] text, and both return the solution as
+//! JSON. This crate doesn't depend on wasm-bindgen, so the crate built for
+//! the browser exports them itself:
+//!
+//! ```text
+//! #[wasm_bindgen]
+//! pub fn solve(problem_json: &str) -> String {
+//!     constraint::wasm::solve(problem_json)
+//! }
+//! ```
+//!
+//! A JSON problem lists its variables in order, each with a `min` and `max`
+//! or its `values`, and then the constraints of the text format:
+//!
+//! ```json
+//! {
+//!   "variables": [
+//!     {"name": "x", "min": 1, "max": 9},
+//!     {"name": "y", "values": [2, 4, 8]}
+//!   ],
+//!   "constraints": [
+//!     {"alldiff": ["x", "y"]},
+//!     {"left": "x", "op": "<", "right": "y"},
+//!     {"left": "x", "op": ">=", "right": 5}
+//!   ]
+//! }
+//! ```
+
+use alloc::{
+    format,
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
+use core::fmt::Write;
+use hashbrown::HashMap;
+
+use crate::{
+    dsl::{parse_dsl, parse_statements},
+    RawProblem, Variable,
+};
+
+/// Solves the JSON `problem`. Returns `{"solution":{"x":1,...}}` with the
+/// variables in declaration order, `{"solution":null}` if it has no
+/// solution, or `{"error":"..."}` if the problem isn't valid
+pub fn solve(problem: &str) -> String {
+    let parsed = Json::parse(problem).and_then(|json| {
+        let (statements, num_vars) = statements(&json)?;
+        parse_statements(
+            statements
+                .iter()
+                .map(|words| words.iter().map(String::as_str).collect()),
+        )
+        .map_err(|error| {
+            let (list, i) = if error.line <= num_vars {
+                ("variables", error.line - 1)
+            } else {
+                ("constraints", error.line - 1 - num_vars)
+            };
+            format!("{list}[{i}]: {}", error.message)
+        })
+    });
+    solve_parsed(parsed)
+}
+
+/// Like [`solve`] for a problem in the [`crate::dsl`] text format
+pub fn solve_dsl(problem: &str) -> String {
+    solve_parsed(parse_dsl(problem).map_err(|error| error.to_string()))
+}
+
+fn solve_parsed(parsed: Result<(RawProblem, HashMap<String, Variable>), String>) -> String {
+    let (problem, names) = match parsed {
+        Ok(parsed) => parsed,
+        Err(error) => {
+            let mut json = String::from("{\"error\":");
+            push_json_string(&mut json, &error);
+            json.push('}');
+            return json;
+        }
+    };
+
+    let solution = problem
+        .normalize_problem()
+        .constraint_propagation()
+        .and_then(|problem| problem.solve_backtracking());
+    let Some(solution) = solution else {
+        return String::from("{\"solution\":null}");
+    };
+
+    let mut names: Vec<(&String, &Variable)> = names.iter().collect();
+    names.sort_by_key(|(_, var)| var.id);
+
+    let mut json = String::from("{\"solution\":{");
+    for (i, (name, var)) in names.into_iter().enumerate() {
+        if i > 0 {
+            json.push(',');
+        }
+        push_json_string(&mut json, name);
+        write!(json, ":{}", solution[var.id]).unwrap();
+    }
+    json.push_str("}}");
+    json
+}
+
+/// The problem as statements of the text format split into words, along
+/// with how many of them declare variables
+fn statements(problem: &Json) -> Result<(Vec<Vec<String>>, usize), String> {
+    let variables = problem.field("variables")?.array("variables")?;
+    let constraints = match problem.optional("constraints")? {
+        Some(constraints) => constraints.array("constraints")?,
+        None => &[],
+    };
+
+    let mut statements = Vec::new();
+    for (i, variable) in variables.iter().enumerate() {
+        let at = |error: String| format!("variables[{i}]: {error}");
+        let name = variable
+            .field("name")
+            .and_then(|name| name.word())
+            .map_err(at)?;
+        let mut words = vec!["var".to_string(), name];
+        match variable.optional("values").map_err(at)? {
+            Some(values) => {
+                for value in values.array("values").map_err(at)? {
+                    words.push(value.number().map_err(at)?);
+                }
+            }
+            None => {
+                let min = variable.field("min").and_then(Json::number).map_err(at)?;
+                let max = variable.field("max").and_then(Json::number).map_err(at)?;
+                words.push(format!("{min}..{max}"));
+            }
+        }
+        statements.push(words);
+    }
+    for (i, constraint) in constraints.iter().enumerate() {
+        let at = |error: String| format!("constraints[{i}]: {error}");
+        let words = match constraint.optional("alldiff").map_err(at)? {
+            Some(names) => {
+                let mut words = vec!["alldiff".to_string()];
+                for name in names.array("alldiff").map_err(at)? {
+                    words.push(name.word().map_err(at)?);
+                }
+                words
+            }
+            None => ["left", "op", "right"]
+                .into_iter()
+                .map(|key| constraint.field(key).and_then(Json::word))
+                .collect::<Result<_, _>>()
+                .map_err(at)?,
+        };
+        statements.push(words);
+    }
+    Ok((statements, variables.len()))
+}
+
+/// Just enough of JSON for problems, with integers as the only numbers
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Json {
+    Null,
+    Bool(bool),
+    Number(i64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+impl Json {
+    fn parse(text: &str) -> Result<Json, String> {
+        let mut parser = JsonParser { text, pos: 0 };
+        let value = parser.value()?;
+        parser.skip_whitespace();
+        if parser.pos < text.len() {
+            return Err(parser.error("the end of the input"));
+        }
+        Ok(value)
+    }
+
+    fn array(&self, what: &str) -> Result<&[Json], String> {
+        match self {
+            Json::Array(values) => Ok(values),
+            _ => Err([what, " must be an array"].concat()),
+        }
+    }
+    /// The value of `key` in the object, if it has one
+    fn optional(&self, key: &str) -> Result<Option<&Json>, String> {
+        match self {
+            Json::Object(fields) => Ok(fields.iter().find(|(k, _)| k == key).map(|(_, v)| v)),
+            _ => Err("expected an object".to_string()),
+        }
+    }
+    /// The value of `key` in the object, which must have one
+    fn field(&self, key: &str) -> Result<&Json, String> {
+        self.optional(key)?
+            .ok_or_else(|| ["missing ", key].concat())
+    }
+    fn number(&self) -> Result<String, String> {
+        match self {
+            Json::Number(n) => Ok(n.to_string()),
+            _ => Err("expected a number".to_string()),
+        }
+    }
+    /// A name or a number, as a word of the text format
+    fn word(&self) -> Result<String, String> {
+        match self {
+            Json::String(s) => Ok(s.clone()),
+            other => other
+                .number()
+                .map_err(|_| "expected a string or a number".to_string()),
+        }
+    }
+}
+
+struct JsonParser<'a> {
+    text: &'a str,
+    pos: usize,
+}
+impl JsonParser<'_> {
+    fn error(&self, expected: &str) -> String {
+        format!("invalid JSON at byte {}: expected {expected}", self.pos)
+    }
+    fn rest(&self) -> &str {
+        &self.text[self.pos..]
+    }
+    fn skip_whitespace(&mut self) {
+        let rest = self.rest();
+        self.pos += rest.len() - rest.trim_start_matches([' ', '\t', '\n', '\r']).len();
+    }
+    /// Skips `token` if it comes next
+    fn eat(&mut self, token: &str) -> bool {
+        self.skip_whitespace();
+        let found = self.rest().starts_with(token);
+        if found {
+            self.pos += token.len();
+        }
+        found
+    }
+    fn expect(&mut self, token: &str) -> Result<(), String> {
+        if self.eat(token) {
+            Ok(())
+        } else {
+            Err(self.error(&format!("\"{token}\"")))
+        }
+    }
+
+    fn value(&mut self) -> Result<Json, String> {
+        self.skip_whitespace();
+        match self.rest().chars().next() {
+            Some('{') => self.object(),
+            Some('[') => self.array(),
+            Some('"') => self.string().map(Json::String),
+            Some('-' | '0'..='9') => self.number(),
+            _ if self.eat("null") => Ok(Json::Null),
+            _ if self.eat("true") => Ok(Json::Bool(true)),
+            _ if self.eat("false") => Ok(Json::Bool(false)),
+            _ => Err(self.error("a value")),
+        }
+    }
+    fn object(&mut self) -> Result<Json, String> {
+        self.expect("{")?;
+        let mut fields = Vec::new();
+        if self.eat("}") {
+            return Ok(Json::Object(fields));
+        }
+        loop {
+            self.skip_whitespace();
+            let key = self.string()?;
+            self.expect(":")?;
+            fields.push((key, self.value()?));
+            if self.eat("}") {
+                return Ok(Json::Object(fields));
+            }
+            self.expect(",")?;
+        }
+    }
+    fn array(&mut self) -> Result<Json, String> {
+        self.expect("[")?;
+        let mut values = Vec::new();
+        if self.eat("]") {
+            return Ok(Json::Array(values));
+        }
+        loop {
+            values.push(self.value()?);
+            if self.eat("]") {
+                return Ok(Json::Array(values));
+            }
+            self.expect(",")?;
+        }
+    }
+    fn number(&mut self) -> Result<Json, String> {
+        let rest = self.rest();
+        let sign = usize::from(rest.starts_with('-'));
+        let len = rest[sign..]
+            .find(|c: char| !c.is_ascii_digit())
+            .map_or(rest.len(), |digits| sign + digits);
+        let number = rest[..len].parse().map_err(|_| self.error("an integer"))?;
+        self.pos += len;
+        if self.rest().starts_with(['.', 'e', 'E']) {
+            return Err(self.error("an integer"));
+        }
+        Ok(Json::Number(number))
+    }
+    fn string(&mut self) -> Result<String, String> {
+        if !self.rest().starts_with('"') {
+            return Err(self.error("a string"));
+        }
+        self.pos += 1;
+        let mut s = String::new();
+        let mut chars = self.rest().char_indices();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '"' => {
+                    self.pos += i + 1;
+                    return Ok(s);
+                }
+                '\\' => {
+                    let escaped = match chars.next().map(|(_, c)| c) {
+                        Some('u') => {
+                            let hex: String = chars.by_ref().take(4).map(|(_, c)| c).collect();
+                            u32::from_str_radix(&hex, 16).ok().and_then(char::from_u32)
+                        }
+                        Some('"') => Some('"'),
+                        Some('\\') => Some('\\'),
+                        Some('/') => Some('/'),
+                        Some('b') => Some('\u{8}'),
+                        Some('f') => Some('\u{c}'),
+                        Some('n') => Some('\n'),
+                        Some('r') => Some('\r'),
+                        Some('t') => Some('\t'),
+                        _ => None,
+                    };
+                    match escaped {
+                        Some(c) => s.push(c),
+                        None => {
+                            self.pos += i;
+                            return Err(self.error("a valid escape"));
+                        }
+                    }
+                }
+                c => s.push(c),
+            }
+        }
+        self.pos = self.text.len();
+        Err(self.error("the end of the string"))
+    }
+}
+
+fn push_json_string(json: &mut String, s: &str) {
+    json.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            c if (c as u32) < 0x20 => write!(json, "\\u{:04x}", c as u32).unwrap(),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::{format, vec::Vec};
+
+    use super::*;
+
+    /// A Sudoku as a JSON problem, with cells named `r{row}c{column}`
+    fn serialize_sudoku(puzzle: &str) -> String {
+        let cell = |i: usize| format!("\"r{}c{}\"", i / 9, i % 9);
+        let variables: Vec<String> = (0..81)
+            .map(|i| format!("{{\"name\":{},\"min\":1,\"max\":9}}", cell(i)))
+            .collect();
+        let units = (0..9).flat_map(|n| {
+            let row: Vec<usize> = (0..9).map(|x| 9 * n + x).collect();
+            let column: Vec<usize> = (0..9).map(|y| 9 * y + n).collect();
+            let top_left = 27 * (n / 3) + 3 * (n % 3);
+            let square: Vec<usize> = (0..9).map(|i| top_left + 9 * (i / 3) + i % 3).collect();
+            [row, column, square]
+        });
+        let mut constraints: Vec<String> = units
+            .map(|unit| {
+                let names: Vec<String> = unit.into_iter().map(cell).collect();
+                format!("{{\"alldiff\":[{}]}}", names.join(","))
+            })
+            .collect();
+        for (i, digit) in puzzle.chars().enumerate() {
+            if digit != '0' {
+                constraints.push(format!(
+                    "{{\"left\":{},\"op\":\"==\",\"right\":{digit}}}",
+                    cell(i)
+                ));
+            }
+        }
+        format!(
+            "{{\"variables\":[{}],\n\"constraints\":[{}]}}",
+            variables.join(","),
+            constraints.join(",\n")
+        )
+    }
+
+    #[test]
+    fn test_solve_serialized_sudoku() {
+        let puzzle =
+            "000260701680070090190004500820100040004602900050003028009300074040050036703018000";
+        let solved =
+            "435269781682571493197834562826195347374682915951743628519326874248957136763418259";
+
+        let json = solve(&serialize_sudoku(puzzle));
+        let expected: Vec<String> = solved
+            .chars()
+            .enumerate()
+            .map(|(i, digit)| format!("\"r{}c{}\":{}", i / 9, i % 9, digit))
+            .collect();
+        assert_eq!(json, format!("{{\"solution\":{{{}}}}}", expected.join(",")));
+    }
+
+    #[test]
+    fn test_json_problems() {
+        let problem = r#"{
+            "variables": [
+                {"name": "x", "min": 1, "max": 9},
+                {"name": "y\"", "values": [2, 4, 8]}
+            ],
+            "constraints": [
+                {"left": "x", "op": ">=", "right": 5},
+                {"left": "y\u0022", "op": ">", "right": "x"}
+            ]
+        }"#;
+        assert_eq!(solve(problem), r#"{"solution":{"x":5,"y\"":8}}"#);
+        assert_eq!(
+            solve(r#"{"variables": [{"name": "x", "values": []}]}"#),
+            r#"{"error":"variables[0]: variable x has no values"}"#
+        );
+        assert_eq!(
+            solve(r#"{"variables": [], "constraints": [{"alldiff": ["x"]}]}"#),
+            r#"{"error":"constraints[0]: unknown variable x"}"#
+        );
+        assert_eq!(
+            solve(r#"{"variables": [{"name": "x", "min": 1}]}"#),
+            r#"{"error":"variables[0]: missing max"}"#
+        );
+        assert_eq!(
+            solve(r#"{"variables": [{"name": "x", "min": 1.5}]}"#),
+            r#"{"error":"invalid JSON at byte 37: expected an integer"}"#
+        );
+        assert_eq!(solve("[]"), r#"{"error":"expected an object"}"#);
+    }
+
+    #[test]
+    fn test_no_solution_and_errors() {
+        assert_eq!(
+            solve_dsl("var x 1..2\nvar y 1..2\nx < y\ny < x"),
+            "{\"solution\":null}"
+        );
+        assert_eq!(
+            solve_dsl("var x 1..2\nx \"< 3"),
+            "{\"error\":\"line 2: unknown comparison \\\"<\"}"
+        );
+    }
+}
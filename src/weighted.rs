@@ -0,0 +1,336 @@
+//! Weighted constraints, which give every combination of values a cost
+//! instead of allowing or forbidding it, and the search for the cheapest
+//! solution
+
+use alloc::{boxed::Box, vec, vec::Vec};
+
+use crate::{Constraint, Domain, PropagatedProblem, Universe, Variable};
+
+/// Cost of the values of a scope, ordered like the scope
+pub type CostFunction = Box<dyn Fn(&mut dyn Iterator<Item = Universe>) -> u64>;
+
+pub struct CostConstraint {
+    pub scope: Vec<Variable>,
+    pub cost: CostFunction,
+}
+impl CostConstraint {
+    pub fn new(scope: Vec<Variable>, cost: CostFunction) -> Self {
+        assert!(!scope.is_empty() && scope.is_sorted_by_key(|v| v.id));
+        CostConstraint { scope, cost }
+    }
+}
+
+/// The unary and binary cost constraints as tables over the values of the
+/// domains, indexed like them. Soft arc consistency (AC*) moves costs from
+/// the binary tables onto the values and from the values onto `lower`
+/// without changing the total cost of any assignment of the values still
+/// alive, so `lower` is paid by all of them
+#[derive(Clone)]
+struct SoftArcs {
+    lower: u64,
+    unary: Vec<Vec<u64>>,
+    binary: Vec<(usize, usize, Vec<Vec<u64>>)>,
+    alive: Vec<Vec<bool>>,
+}
+impl SoftArcs {
+    /// Tables of the cost constraints with at most two variables, the others
+    /// are left out
+    fn new(problem: &PropagatedProblem, costs: &[CostConstraint]) -> Self {
+        let n = problem.variables.len();
+        let mut unary: Vec<Vec<u64>> = (0..n).map(|i| vec![0; problem.values(i).len()]).collect();
+        let mut binary = Vec::new();
+        for constraint in costs {
+            match *constraint.scope.as_slice() {
+                [x] => {
+                    for (i, &vx) in problem.values(x.id).iter().enumerate() {
+                        unary[x.id][i] += (constraint.cost)(&mut [vx].into_iter());
+                    }
+                }
+                [x, y] => {
+                    let table = problem
+                        .values(x.id)
+                        .iter()
+                        .map(|&vx| {
+                            problem
+                                .values(y.id)
+                                .iter()
+                                .map(|&vy| (constraint.cost)(&mut [vx, vy].into_iter()))
+                                .collect()
+                        })
+                        .collect();
+                    binary.push((x.id, y.id, table));
+                }
+                _ => {}
+            }
+        }
+        let alive = unary.iter().map(|costs| vec![true; costs.len()]).collect();
+        SoftArcs {
+            lower: 0,
+            unary,
+            binary,
+            alive,
+        }
+    }
+
+    /// Kills every value of `var` but the one at `index`
+    fn assign(&mut self, var: usize, index: usize) {
+        for (i, alive) in self.alive[var].iter_mut().enumerate() {
+            *alive = i == index;
+        }
+    }
+
+    /// Projects costs until nothing moves, removing the values that would
+    /// take the cost to `upper` or more. Returns false if the lower bound
+    /// gets there or some variable has no values left
+    fn propagate(&mut self, upper: u64) -> bool {
+        let SoftArcs {
+            lower,
+            unary,
+            binary,
+            alive,
+        } = self;
+        loop {
+            for (x, y, table) in binary.iter_mut() {
+                let (x, y) = (*x, *y);
+                for i in (0..table.len()).filter(|&i| alive[x][i]) {
+                    let row = &mut table[i];
+                    let cheapest = (0..row.len())
+                        .filter(|&j| alive[y][j])
+                        .map(|j| row[j])
+                        .min();
+                    if let Some(cheapest) = cheapest.filter(|&c| c > 0) {
+                        for j in (0..row.len()).filter(|&j| alive[y][j]) {
+                            row[j] -= cheapest;
+                        }
+                        unary[x][i] += cheapest;
+                    }
+                }
+                for j in (0..alive[y].len()).filter(|&j| alive[y][j]) {
+                    let column = (0..table.len()).filter(|&i| alive[x][i]);
+                    let cheapest = column.clone().map(|i| table[i][j]).min();
+                    if let Some(cheapest) = cheapest.filter(|&c| c > 0) {
+                        for i in column {
+                            table[i][j] -= cheapest;
+                        }
+                        unary[y][j] += cheapest;
+                    }
+                }
+            }
+
+            let mut pruned = false;
+            for (costs, alive) in unary.iter_mut().zip(alive.iter()) {
+                let cheapest = (0..costs.len())
+                    .filter(|&i| alive[i])
+                    .map(|i| costs[i])
+                    .min();
+                let Some(cheapest) = cheapest else {
+                    return false;
+                };
+                for i in (0..costs.len()).filter(|&i| alive[i]) {
+                    costs[i] -= cheapest;
+                }
+                *lower += cheapest;
+                if *lower >= upper {
+                    return false;
+                }
+            }
+            for (costs, alive) in unary.iter().zip(alive.iter_mut()) {
+                for (i, alive) in alive.iter_mut().enumerate() {
+                    if *alive && *lower + costs[i] >= upper {
+                        *alive = false;
+                        pruned = true;
+                    }
+                }
+            }
+            if !pruned {
+                return true;
+            }
+        }
+    }
+}
+
+/// What [`PropagatedProblem::solve_weighted`] checks once each variable is
+/// assigned, indexed by it as the last variable of the scopes
+struct WeightedChecks<'a> {
+    hard: Vec<Vec<(&'a [Variable], &'a [Constraint])>>,
+    /// Cost constraints over more than two variables, which soft arc
+    /// consistency doesn't see
+    costs: Vec<Vec<&'a CostConstraint>>,
+}
+
+impl PropagatedProblem {
+    /// Soft arc consistency on the cost constraints of at most two variables,
+    /// removing the values that can't lead to a total cost below `upper`.
+    /// Returns a lower bound on the cost of any solution and the domains
+    /// left, or `None` if no solution can cost less than `upper`
+    pub fn soft_arc_consistency(
+        &self,
+        costs: &[CostConstraint],
+        upper: u64,
+    ) -> Option<(u64, Vec<Domain>)> {
+        let mut arcs = SoftArcs::new(self, costs);
+        if !arcs.propagate(upper) {
+            return None;
+        }
+        let domains = self
+            .variables
+            .iter()
+            .map(|&var| Domain {
+                of: var,
+                values: self
+                    .values(var.id)
+                    .iter()
+                    .zip(&arcs.alive[var.id])
+                    .filter_map(|(&value, &alive)| alive.then_some(value))
+                    .collect(),
+            })
+            .collect();
+        Some((arcs.lower, domains))
+    }
+
+    /// Finds the solution with the lowest total cost of `costs`, returning
+    /// that cost and the solution, the first one in lexicographic order among
+    /// ties, or `None` if there is no solution.
+    ///
+    /// Branch and bound that keeps the costs soft arc consistent after every
+    /// assignment, like [`Self::soft_arc_consistency`], so values whose
+    /// lower bound reaches the best cost so far are never tried
+    pub fn solve_weighted(&self, costs: &[CostConstraint]) -> Option<(u64, Vec<Universe>)> {
+        let n = self.variables.len();
+        let mut checks = WeightedChecks {
+            hard: vec![Vec::new(); n],
+            costs: vec![Vec::new(); n],
+        };
+        for (scope, constraints) in &self.constraints {
+            checks.hard[scope[scope.len() - 1].id].push((scope, constraints));
+        }
+        for constraint in costs.iter().filter(|c| c.scope.len() > 2) {
+            checks.costs[constraint.scope[constraint.scope.len() - 1].id].push(constraint);
+        }
+
+        let mut arcs = SoftArcs::new(self, costs);
+        if !arcs.propagate(u64::MAX) {
+            return None;
+        }
+        let mut best = None;
+        self.weighted(&checks, arcs, &mut vec![0; n], 0, 0, &mut best);
+        best
+    }
+    fn weighted(
+        &self,
+        checks: &WeightedChecks,
+        arcs: SoftArcs,
+        candidate: &mut Vec<Universe>,
+        k: usize,
+        spent: u64,
+        best: &mut Option<(u64, Vec<Universe>)>,
+    ) {
+        if k == candidate.len() {
+            *best = Some((arcs.lower + spent, candidate.clone()));
+            return;
+        }
+
+        for (i, &value) in self.values(k).iter().enumerate() {
+            if !arcs.alive[k][i] {
+                continue;
+            }
+            candidate[k] = value;
+            let consistent = checks.hard[k].iter().all(|(scope, constraints)| {
+                let vals: Vec<Universe> = scope.iter().map(|var| candidate[var.id]).collect();
+                Constraint::all_satisfied(constraints, &vals)
+            });
+            if !consistent {
+                continue;
+            }
+            let spent = spent
+                + checks.costs[k]
+                    .iter()
+                    .map(|c| (c.cost)(&mut c.scope.iter().map(|var| candidate[var.id])))
+                    .sum::<u64>();
+
+            let upper = best.as_ref().map_or(u64::MAX, |(cost, _)| *cost);
+            let mut next = arcs.clone();
+            next.assign(k, i);
+            if spent < upper && next.propagate(upper - spent) {
+                self.weighted(checks, next, candidate, k + 1, spent, best);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+
+    use super::*;
+    use crate::RawProblem;
+
+    #[test]
+    fn test_soft_arc_consistency_prunes() {
+        let mut problem = RawProblem::new();
+        let x = problem.add_var_range(0, 1);
+        let y = problem.add_var_range(0, 1);
+        let problem = problem
+            .normalize_problem()
+            .constraint_propagation()
+            .unwrap();
+        // x = 0 costs 2 whatever y is, x = 1 costs 1
+        let costs = [CostConstraint::new(
+            vec![x, y],
+            Box::new(|vals| if vals.next() == Some(0) { 2 } else { 1 }),
+        )];
+
+        // Every cost is on a pair, yet every solution costs at least 1
+        let (lower, domains) = problem.soft_arc_consistency(&costs, u64::MAX).unwrap();
+        assert_eq!(lower, 1);
+        assert_eq!(domains[x.id].values, [0, 1]);
+
+        // Knowing a solution of cost 2 rules out x = 0 before trying it
+        let (_, domains) = problem.soft_arc_consistency(&costs, 2).unwrap();
+        assert_eq!(domains[x.id].values, [1]);
+        assert_eq!(domains[y.id].values, [0, 1]);
+        assert!(problem.soft_arc_consistency(&costs, 1).is_none());
+
+        assert_eq!(problem.solve_weighted(&costs), Some((1, vec![1, 0])));
+    }
+
+    #[test]
+    fn test_solve_weighted() {
+        let mut problem = RawProblem::new();
+        let x = problem.add_var_range(1, 3);
+        let y = problem.add_var_range(1, 3);
+        let z = problem.add_var_range(1, 3);
+        problem.push_constraint(Constraint::all_different(vec![x, y, z]));
+        let problem = problem
+            .normalize_problem()
+            .constraint_propagation()
+            .unwrap();
+
+        // Same preferences as for solve_preferred, x weighs more
+        let x_cost = CostConstraint::new(
+            vec![x],
+            Box::new(|vals| (vals.next() != Some(2)) as u64 * 5),
+        );
+        let z_cost = CostConstraint::new(
+            vec![z],
+            Box::new(|vals| (vals.next() != Some(2)) as u64 * 2),
+        );
+        assert_eq!(
+            problem.solve_weighted(&[x_cost, z_cost]),
+            Some((2, vec![2, 1, 3]))
+        );
+
+        // Costs on the whole scope are added once it is assigned
+        let descending = CostConstraint::new(
+            vec![x, y, z],
+            Box::new(|vals| {
+                let vals: Vec<Universe> = vals.collect();
+                (vals[0] < vals[1]) as u64 + (vals[1] < vals[2]) as u64
+            }),
+        );
+        assert_eq!(
+            problem.solve_weighted(&[descending]),
+            Some((0, vec![3, 2, 1]))
+        );
+    }
+}
@@ -0,0 +1,70 @@
+//! Runs the binary the way a user would
+#![cfg(feature = "std")]
+
+use std::{
+    io::Write,
+    process::{Command, Output, Stdio},
+};
+
+const PUZZLE: &str =
+    "000260701680070090190004500820100040004602900050003028009300074040050036703018000";
+const SOLUTION: &str = "\
+435269781
+682571493
+197834562
+826195347
+374682915
+951743628
+519326874
+248957136
+763418259
+";
+
+fn run(args: &[&str], stdin: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_constraint"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    // The binary may exit on bad arguments before reading, closing the pipe
+    let _ = child.stdin.take().unwrap().write_all(stdin.as_bytes());
+    child.wait_with_output().unwrap()
+}
+
+#[test]
+fn test_solves_from_stdin_with_every_solver() {
+    for solver in ["cbj", "backtracking", "mac"] {
+        let output = run(&[&format!("--solver={solver}")], PUZZLE);
+        assert!(output.status.success(), "{solver}");
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        assert!(stdout.starts_with(SOLUTION), "{solver}: {stdout}");
+    }
+}
+
+#[test]
+fn test_solves_from_file() {
+    let path = std::env::temp_dir().join(format!("constraint-cli-{}.txt", std::process::id()));
+    std::fs::write(&path, PUZZLE).unwrap();
+    let output = run(&[path.to_str().unwrap()], "");
+    std::fs::remove_file(&path).unwrap();
+
+    assert!(output.status.success());
+    assert!(String::from_utf8(output.stdout)
+        .unwrap()
+        .starts_with(SOLUTION));
+}
+
+#[test]
+fn test_unsolvable_fails() {
+    // Two 6s in the first row
+    let puzzle = format!("6{}", &PUZZLE[1..]);
+    let output = run(&[], &puzzle);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty());
+    assert!(!output.stderr.is_empty());
+
+    let output = run(&["--solver=guess"], PUZZLE);
+    assert_eq!(output.status.code(), Some(2));
+}